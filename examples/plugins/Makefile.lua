@@ -5,3 +5,7 @@ end
 function clean_code(code)
     return code
 end
+
+function render_section_docs(module_path, header, docs, code)
+    return docs .. "\n```make\n" .. code .. "\n```"
+end
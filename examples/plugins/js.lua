@@ -1,4 +1,11 @@
-function get_import_code(current_path, referenced_path_str, code_block)
+lp_manifest = {
+    name = "javascript",
+    version = "0.1.0",
+    extensions = { "mjs", "cjs" },
+    api_version = 1,
+}
+
+function get_exports(module_path, code_block)
     local definitions = {}
     for line in code_block:gmatch("[^\r\n]+") do
         local export_function_name = line:match("^export%s+function%s+([%w_]+)%(")
@@ -11,6 +18,11 @@ function get_import_code(current_path, referenced_path_str, code_block)
             table.insert(definitions, export_class_name)
         end
     end
+    return definitions
+end
+
+function get_import_code(current_path, referenced_path_str, code_block)
+    local definitions = get_exports(referenced_path_str, code_block)
 
     if #definitions == 0 then
         return ""
@@ -1,15 +1,23 @@
-function get_import_code(current_path, referenced_path_str, code_block)
-    local definitions = {}
-    for line in code_block:gmatch("[^\r\n]+") do
+lp_manifest = {
+    name = "python",
+    version = "0.1.0",
+    extensions = { "pyw" },
+    api_version = 1,
+}
 
-        local class_name = line:match("^class%s+([%w_]+)")
-        local function_name = line:match("^def%s+([%w_]+)")
-        if class_name then
-            table.insert(definitions, class_name)
-        elseif function_name then
-            table.insert(definitions, function_name)
-        end
+-- a shebang, an encoding declaration and `from __future__` imports must stay first
+header_pattern = "^(#!|#.*coding[:=]|from __future__ import)"
+
+function get_exports(module_path, code_block)
+    local definitions = {}
+    for _, captures in ipairs(lp.captures_all("(?m)^(?:class|def)\\s+(\\w+)", code_block)) do
+        table.insert(definitions, captures[1])
     end
+    return definitions
+end
+
+function get_import_code(current_path, referenced_path_str, code_block)
+    local definitions = get_exports(referenced_path_str, code_block)
 
     local referenced_module = referenced_path_str
         :gsub("%.py$", "")
@@ -18,7 +26,10 @@ function get_import_code(current_path, referenced_path_str, code_block)
     if #definitions == 0 then
         return ""
     end
-    return "from " .. referenced_module .. " import " .. table.concat(definitions, ", ")
+    return {
+        imports = { "from " .. referenced_module .. " import " .. table.concat(definitions, ", ") },
+        placement = "after_header",
+    }
 end
 
 function clean_code(code)
@@ -74,3 +85,26 @@ function clean_code(code)
     
     return table.concat(clean_lines, "\n")
 end
+
+-- makes every directory with python modules a package
+function after_build(modules)
+    local outputs = {}
+    for _, module in ipairs(modules) do
+        outputs[module.output] = true
+    end
+
+    local files = {}
+    for _, module in ipairs(modules) do
+        if module.extension == "py" then
+            local dir = module.output:match("^(.*)[/\\][^/\\]*$")
+            while dir do
+                local init = dir .. "/__init__.py"
+                if not outputs[init] then
+                    files[init] = ""
+                end
+                dir = dir:match("^(.*)[/\\][^/\\]*$")
+            end
+        end
+    end
+    return files
+end
@@ -2,11 +2,10 @@
 
 use std::rc::Rc;
 
-use mlua::Lua;
-
 use crate::{config::config::Config, error::LPError};
 
 use super::{
+    cache::BuildCache,
     code::{self, code_builder::CodeBuilder},
     docs::{self, docs_builder::DocsBuilder},
     index::ProjectIndex,
@@ -24,26 +23,31 @@ impl Builder {
     /// Creates a new Builder instance.
     /// # Arguments
     /// * `config` - a Config instance that contains the configuration for the builder.
-    /// * `lua` - an Rc<Lua> instance that is used for running Lua plugins.
     /// # Returns
     /// Returns either a Builder instance or an LPError.
-    pub fn new(config: Config, lua: Rc<Lua>) -> Result<Self, LPError> {
-        let project = Project::new(&config.source_dir)?;
+    pub fn new(config: Config) -> Result<Self, LPError> {
+        let project = Project::new(&config.source_dir, &config.ignore)?;
         let shared_project = Rc::new(project);
-        let index = ProjectIndex::new(shared_project.clone());
+        let index = Rc::new(ProjectIndex::new(shared_project.clone()));
         let code_builder = CodeBuilder::new(
             code::config::Config::new(
                 config.code_dir.clone(),
                 config.source_dir.clone(),
                 config.code_plugins_dir.clone(),
+                config.plugins.clone(),
             ),
             Rc::clone(&shared_project),
-            Rc::new(index),
-            lua,
+            Rc::clone(&index),
         )?;
         let docs_builder = DocsBuilder::new(
-            docs::config::Config::new(config.docs_dir.clone(), config.source_dir.clone()),
+            docs::config::Config::new(
+                config.docs_dir.clone(),
+                config.source_dir.clone(),
+                config.docs.format,
+            ),
             Rc::clone(&shared_project),
+            index,
+            code_builder.plugins_caller(),
         );
 
         Ok(Builder {
@@ -61,25 +65,41 @@ impl Builder {
             if std::fs::metadata(&self.config.code_dir).is_ok() {
                 std::fs::remove_dir_all(&self.config.code_dir)?;
             }
+            if std::fs::metadata(&self.config.cache_file).is_ok() {
+                std::fs::remove_file(&self.config.cache_file)?;
+            }
         }
 
-        std::fs::create_dir_all(&self.config.docs_dir)?;
+        if self.config.docs.enabled {
+            std::fs::create_dir_all(&self.config.docs_dir)?;
+        }
         std::fs::create_dir_all(&self.config.code_dir)?;
 
         Ok(())
     }
 
     /// The main method that builds the code and documentation.
-    pub fn build(&self) -> Result<(), LPError> {
+    /// The exports of the sections are collected first, so the documentation can list them.
+    /// Only the outputs whose inputs changed since the previous build are rebuilt.
+    /// Returns the number of the outputs which were (re)written.
+    pub fn build(&self) -> Result<usize, LPError> {
         self.init()?;
+        let mut cache = BuildCache::load(&self.config.cache_file);
+
+        let result = self
+            .code_builder
+            .collect_exports()
+            .and_then(|_| self.code_builder.build(&mut cache));
+        for message in self.code_builder.take_plugin_messages() {
+            eprintln!("{}", message);
+        }
+        result?;
+        if self.config.docs.enabled {
+            self.docs_builder.build(&mut cache)?;
+        }
 
-        println!("Bulding code to: {:?}", self.config.code_dir);
-        self.code_builder.build()?;
-
-        println!("Bulding documentation to: {:?}", self.config.docs_dir);
-        self.docs_builder.build()?;
-
-        Ok(())
+        cache.save(&self.config.cache_file)?;
+        Ok(cache.rewritten())
     }
 }
 
@@ -97,8 +117,7 @@ mod tests {
             &temp_dir.path().join("plugins"),
             false,
         );
-        let lua = Rc::new(Lua::new());
-        let builder = Builder::new(config, lua);
+        let builder = Builder::new(config);
         assert!(builder.is_ok());
 
         let builder = builder.unwrap();
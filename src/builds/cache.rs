@@ -0,0 +1,403 @@
+#![forbid(unsafe_code)]
+
+use std::{
+    collections::{HashMap, HashSet},
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    builds::{
+        index::{IndexRead, ProjectIndex},
+        spec::structs::Module,
+    },
+    error::LPError,
+};
+
+/// Incremental hash of the contents a build output depends on.
+///
+/// FNV-1a is used, so the hashes are stable between runs and versions of the compiler.
+pub struct ContentHasher(u64);
+
+impl Default for ContentHasher {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ContentHasher {
+    pub fn new() -> Self {
+        let mut hasher = ContentHasher(0xcbf29ce484222325);
+        // outputs of another version of the system may differ
+        hasher.update(env!("CARGO_PKG_VERSION").as_bytes());
+        hasher
+    }
+
+    /// Adds a part to the hash. The length is hashed as well, so the parts can't be confused
+    pub fn update(&mut self, part: &[u8]) -> &mut Self {
+        for byte in (part.len() as u64).to_le_bytes().iter().chain(part) {
+            self.0 ^= *byte as u64;
+            self.0 = self.0.wrapping_mul(0x100000001b3);
+        }
+        self
+    }
+
+    pub fn finish(&self) -> String {
+        format!("{:016x}", self.0)
+    }
+}
+
+/// Returns the hash of `content`
+pub fn hash_content(content: &[u8]) -> String {
+    ContentHasher::new().update(content).finish()
+}
+
+/// Returns a hasher of the literate module's inputs: its source and every section it references.
+///
+/// `source` is the content of the module's file.
+pub fn hash_module_inputs(module: &Module, index: &ProjectIndex, source: &[u8]) -> ContentHasher {
+    let mut hasher = ContentHasher::new();
+    hasher.update(source);
+
+    for section in module.sections.as_deref().unwrap_or_default() {
+        for reference in &section.references {
+            let referenced_module_path = module.resolve_relative_module_path(&reference.path);
+            hasher
+                .update(referenced_module_path.to_string_lossy().as_bytes())
+                .update(reference.header.as_bytes());
+
+            if let (Some(referenced_module), Some(referenced_section)) = (
+                index.get_module(&referenced_module_path),
+                index.get_section(&referenced_module_path, &reference.header),
+            ) {
+                hasher
+                    .update(referenced_module.path.to_string_lossy().as_bytes())
+                    .update(referenced_section.code.as_bytes());
+            }
+        }
+    }
+    hasher
+}
+
+/// Returns the inputs of an output extended with the current state of the parts of the index
+/// the plugins read while building it, the inputs are returned as is if they read nothing.
+pub fn hash_index_reads(inputs: &str, reads: &[IndexRead], index: &ProjectIndex) -> String {
+    if reads.is_empty() {
+        return inputs.to_string();
+    }
+    let mut hasher = ContentHasher::new();
+    hasher.update(inputs.as_bytes());
+    for read in reads {
+        match read {
+            IndexRead::Modules => {
+                hasher.update(b"modules");
+                for module in index.get_modules() {
+                    hasher.update(module.path.to_string_lossy().as_bytes());
+                }
+            }
+            IndexRead::Section { module, header } => {
+                hasher
+                    .update(module.to_string_lossy().as_bytes())
+                    .update(header.as_bytes());
+                if let Some(section) = index.get_section(module, header) {
+                    hasher
+                        .update(section.header.as_deref().unwrap_or_default().as_bytes())
+                        .update(section.code.as_bytes())
+                        .update(section.docs.as_bytes());
+                }
+            }
+        }
+    }
+    hasher.finish()
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct CacheEntry {
+    /// hash of everything the output was built from
+    inputs: String,
+    /// hash of the output's content
+    output: String,
+    /// the parts of the index the plugins read while building the output, see `hash_index_reads`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    reads: Vec<IndexRead>,
+}
+
+/// Cache of the build outputs, stored in the target directory between builds.
+///
+/// Each output file is recorded with the hash of its inputs and the hash of its content.
+/// The output is up to date if both hashes still match.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct BuildCache {
+    entries: HashMap<PathBuf, CacheEntry>,
+    /// names exported by the sections, by the module path without any extension and the section's header.
+    /// They are reported by the plugins only when the referencing modules are built, so they are kept between builds.
+    #[serde(default)]
+    exports: HashMap<PathBuf, HashMap<String, Vec<String>>>,
+    /// outputs checked or written during the current build, others are dropped on saving
+    #[serde(skip)]
+    used: HashSet<PathBuf>,
+    /// number of the outputs (re)written during the current build
+    #[serde(skip)]
+    rewritten: usize,
+}
+
+impl BuildCache {
+    /// Reads the cache, an empty cache is returned if the file is missing or can't be parsed
+    pub fn load(path: &Path) -> Self {
+        std::fs::read_to_string(path)
+            .ok()
+            .and_then(|content| serde_json::from_str(&content).ok())
+            .unwrap_or_default()
+    }
+
+    /// Returns the number of the outputs (re)written during the current build
+    pub fn rewritten(&self) -> usize {
+        self.rewritten
+    }
+
+    /// Writes the cache, keeping only the outputs of the current build
+    pub fn save(&mut self, path: &Path) -> Result<(), LPError> {
+        let used = std::mem::take(&mut self.used);
+        self.entries.retain(|output, _| used.contains(output));
+        let content =
+            serde_json::to_string(self).map_err(|e| LPError::Io(std::io::Error::other(e)))?;
+        std::fs::write(path, content)?;
+        Ok(())
+    }
+
+    /// Checks whether the output was built from the same inputs and hasn't been changed since
+    pub fn is_fresh(&mut self, output: &Path, inputs: &str) -> bool {
+        self.used.insert(output.to_path_buf());
+        let Some(entry) = self.entries.get(output) else {
+            return false;
+        };
+        entry.inputs == inputs
+            && std::fs::read(output).is_ok_and(|content| hash_content(&content) == entry.output)
+    }
+
+    /// Writes the output built from `inputs`.
+    /// The file is not rewritten if its content is the same, so its modification time is kept.
+    pub fn write(&mut self, output: &Path, inputs: &str, content: &[u8]) -> Result<(), LPError> {
+        if std::fs::read(output).map_or(true, |existing| existing != content) {
+            std::fs::write(output, content)?;
+            self.rewritten += 1;
+        }
+        self.record(output, inputs, content);
+        Ok(())
+    }
+
+    /// Copies the `source` file to the output, unless the output already has the same content.
+    pub fn copy(&mut self, source: &Path, output: &Path) -> Result<(), LPError> {
+        let content = std::fs::read(source)?;
+        if std::fs::read(output).map_or(true, |existing| existing != content) {
+            std::fs::copy(source, output)?;
+            self.rewritten += 1;
+        }
+        self.record(output, &hash_content(&content), &content);
+        Ok(())
+    }
+
+    /// Returns the parts of the index the plugins read while building the output last time
+    pub fn index_reads(&self, output: &Path) -> Vec<IndexRead> {
+        self.entries
+            .get(output)
+            .map(|entry| entry.reads.clone())
+            .unwrap_or_default()
+    }
+
+    /// Records the parts of the index the plugins read while building the output just written
+    pub fn record_index_reads(&mut self, output: &Path, reads: Vec<IndexRead>) {
+        if let Some(entry) = self.entries.get_mut(output) {
+            entry.reads = reads;
+        }
+    }
+
+    /// Records the names exported by the module's section
+    pub fn record_exports(&mut self, module: &Path, header: &str, exports: Vec<String>) {
+        self.exports
+            .entry(module.to_path_buf())
+            .or_default()
+            .insert(header.to_string(), exports);
+    }
+
+    /// Keeps only the exports of the sections for which `keep` returns true
+    pub fn retain_exports(&mut self, mut keep: impl FnMut(&PathBuf, &str) -> bool) {
+        for (module, sections) in self.exports.iter_mut() {
+            sections.retain(|header, _| keep(module, header));
+        }
+        self.exports.retain(|_, sections| !sections.is_empty());
+    }
+
+    /// Returns the names exported by the module's sections, by the sections' headers
+    pub fn module_exports(&self, module: &Path) -> HashMap<String, Vec<String>> {
+        self.exports.get(module).cloned().unwrap_or_default()
+    }
+
+    fn record(&mut self, output: &Path, inputs: &str, content: &[u8]) {
+        self.used.insert(output.to_path_buf());
+        self.entries.insert(
+            output.to_path_buf(),
+            CacheEntry {
+                inputs: inputs.to_string(),
+                output: hash_content(content),
+                reads: vec![],
+            },
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::rc::Rc;
+
+    use super::*;
+    use crate::builds::spec::structs::{Project, Reference, Section};
+    use tempfile::tempdir;
+
+    fn section(header: &str, code: &str, references: Vec<Reference>) -> Rc<Section> {
+        Rc::new(Section {
+            code: code.to_string(),
+            docs: format!("# {}", header),
+            header: Some(header.to_string()),
+            references,
+        })
+    }
+
+    fn project(lib_code: &str) -> Rc<Project> {
+        let reference = Reference {
+            path: PathBuf::from("lib"),
+            header: "Lib".to_string(),
+            text: "Lib".to_string(),
+        };
+        Rc::new(Project {
+            modules: vec![
+                Rc::new(Module {
+                    path: PathBuf::from("main.py.lpnb"),
+                    sections: Some(vec![section("Main", "main()", vec![reference])]),
+                }),
+                Rc::new(Module {
+                    path: PathBuf::from("lib.py.lpnb"),
+                    sections: Some(vec![section("Lib", lib_code, vec![])]),
+                }),
+            ],
+        })
+    }
+
+    fn main_inputs(project: Rc<Project>) -> String {
+        let index = ProjectIndex::new(Rc::clone(&project));
+        hash_module_inputs(&project.modules[0], &index, b"source").finish()
+    }
+
+    #[test]
+    fn test_content_hasher() {
+        assert_eq!(hash_content(b"abc"), hash_content(b"abc"));
+        assert_ne!(hash_content(b"abc"), hash_content(b"abd"));
+        assert_ne!(
+            ContentHasher::new().update(b"ab").update(b"c").finish(),
+            ContentHasher::new().update(b"a").update(b"bc").finish()
+        );
+    }
+
+    #[test]
+    fn test_hash_module_inputs() {
+        assert_eq!(main_inputs(project("x = 1")), main_inputs(project("x = 1")));
+        assert_ne!(main_inputs(project("x = 1")), main_inputs(project("x = 2")));
+    }
+
+    #[test]
+    fn test_hash_index_reads() {
+        let reads = [IndexRead::Section {
+            module: PathBuf::from("lib"),
+            header: "Lib".to_string(),
+        }];
+        let inputs = |lib_code: &str| {
+            let index = ProjectIndex::new(project(lib_code));
+            hash_index_reads("inputs", &reads, &index)
+        };
+
+        let index = ProjectIndex::new(project("x = 1"));
+        assert_eq!(hash_index_reads("inputs", &[], &index), "inputs");
+        assert_eq!(inputs("x = 1"), inputs("x = 1"));
+        assert_ne!(inputs("x = 1"), inputs("x = 2"));
+        assert_ne!(
+            hash_index_reads("inputs", &[IndexRead::Modules], &index),
+            "inputs"
+        );
+    }
+
+    #[test]
+    fn test_build_cache_index_reads() {
+        let dir = tempdir().unwrap();
+        let output = dir.path().join("out.py");
+        let cache_path = dir.path().join("cache");
+        let reads = vec![IndexRead::Modules];
+
+        let mut cache = BuildCache::load(&cache_path);
+        cache.write(&output, "inputs", b"code").unwrap();
+        cache.record_index_reads(&output, reads.clone());
+        cache.save(&cache_path).unwrap();
+
+        let mut cache = BuildCache::load(&cache_path);
+        assert_eq!(cache.index_reads(&output), reads);
+        assert!(cache.is_fresh(&output, "inputs"));
+        assert!(cache.index_reads(&dir.path().join("other.py")).is_empty());
+    }
+
+    #[test]
+    fn test_build_cache() {
+        let dir = tempdir().unwrap();
+        let output = dir.path().join("out.py");
+        let cache_path = dir.path().join("cache");
+
+        let mut cache = BuildCache::load(&cache_path);
+        assert!(!cache.is_fresh(&output, "inputs"));
+        cache.write(&output, "inputs", b"code").unwrap();
+        cache.save(&cache_path).unwrap();
+
+        let mut cache = BuildCache::load(&cache_path);
+        assert!(cache.is_fresh(&output, "inputs"));
+        assert!(!cache.is_fresh(&output, "other inputs"));
+        cache.write(&output, "other inputs", b"code").unwrap();
+        assert_eq!(cache.rewritten(), 0);
+
+        std::fs::write(&output, "edited").unwrap();
+        assert!(!cache.is_fresh(&output, "inputs"));
+    }
+
+    #[test]
+    fn test_build_cache_save_drops_unused() {
+        let dir = tempdir().unwrap();
+        let cache_path = dir.path().join("cache");
+
+        let mut cache = BuildCache::default();
+        cache.write(&dir.path().join("a"), "a", b"a").unwrap();
+        cache.write(&dir.path().join("b"), "b", b"b").unwrap();
+        cache.save(&cache_path).unwrap();
+
+        let mut cache = BuildCache::load(&cache_path);
+        assert!(cache.is_fresh(&dir.path().join("a"), "a"));
+        cache.save(&cache_path).unwrap();
+
+        let mut cache = BuildCache::load(&cache_path);
+        assert!(!cache.is_fresh(&dir.path().join("b"), "b"));
+    }
+
+    #[test]
+    fn test_build_cache_exports() {
+        let dir = tempdir().unwrap();
+        let cache_path = dir.path().join("cache");
+
+        let mut cache = BuildCache::default();
+        cache.record_exports(Path::new("lib"), "Lib", vec!["f".to_string()]);
+        cache.record_exports(Path::new("lib"), "Removed", vec!["g".to_string()]);
+        cache.save(&cache_path).unwrap();
+
+        let mut cache = BuildCache::load(&cache_path);
+        cache.retain_exports(|_, header| header != "Removed");
+        let exports = cache.module_exports(Path::new("lib"));
+        assert_eq!(exports.len(), 1);
+        assert_eq!(exports["Lib"], vec!["f"]);
+        assert!(cache.module_exports(Path::new("main")).is_empty());
+    }
+}
@@ -0,0 +1,210 @@
+#![forbid(unsafe_code)]
+
+use std::rc::Rc;
+
+use crate::{config::config::Config, error::LPError};
+
+use super::{
+    code::{self, code_builder::CodeBuilder, plugins::library::MessageLevel},
+    index::ProjectIndex,
+    spec::structs::Project,
+    validation,
+};
+
+/// Checker validates the source project without writing anything to the target directory.
+pub struct Checker {
+    config: Config,
+    run_plugins: bool,
+}
+
+impl Checker {
+    /// Creates a new Checker instance.
+    /// # Arguments
+    /// * `config` - a Config instance that contains the configuration of the project.
+    /// * `run_plugins` - whether the plugins should also be run in memory to prepare the code.
+    pub fn new(config: Config, run_plugins: bool) -> Self {
+        Checker {
+            config,
+            run_plugins,
+        }
+    }
+
+    /// Parses every module, builds the index, validates the references and loads the plugins.
+    ///
+    /// The broken modules are reported and the rest of the project is still validated,
+    /// the plugins are only run when every module is valid.
+    ///
+    /// Returns all the problems found, an empty list means the project is fine.
+    pub fn check(&self) -> Vec<LPError> {
+        let (project, mut errors) = Project::parse(&self.config.source_dir, &self.config.ignore);
+        let modules_are_valid = errors.is_empty();
+        let project = Rc::new(project);
+        let index = Rc::new(ProjectIndex::new(Rc::clone(&project)));
+
+        let references = validation::validate_references(&project, &index);
+        let chunks = validation::validate_chunks(&project);
+        let code_is_valid = modules_are_valid && references.is_ok() && chunks.is_ok();
+        errors.extend(references.err());
+        errors.extend(chunks.err());
+
+        let code_builder = CodeBuilder::new(
+            code::config::Config::new(
+                self.config.code_dir.clone(),
+                self.config.source_dir.clone(),
+                self.config.code_plugins_dir.clone(),
+                self.config.plugins.clone(),
+            ),
+            project,
+            index,
+        );
+
+        match code_builder {
+            Ok(code_builder) => {
+                if self.run_plugins && code_is_valid {
+                    errors.extend(code_builder.collect_exports().err());
+                    if let Err(e) = code_builder.check() {
+                        errors.push(e);
+                    }
+                    errors.extend(
+                        code_builder
+                            .take_plugin_messages()
+                            .into_iter()
+                            .filter(|message| message.level == MessageLevel::Warning)
+                            .map(|message| LPError::PluginWarning {
+                                plugin: message.plugin,
+                                message: message.message,
+                            }),
+                    );
+                }
+            }
+            Err(e) => errors.push(e),
+        }
+
+        errors
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::constants::SYSTEM_FILES_EXTENSION;
+    use std::fs;
+    use tempfile::tempdir;
+
+    const MODULE: &str = r#"
+sections:
+  - code: |
+        x = 1
+    docs: |
+        # Main
+        See [missing](other#Nothing)
+"#;
+
+    #[test]
+    fn test_check_reports_problems_and_writes_nothing() {
+        let dir = tempdir().unwrap();
+        let src = dir.path().join("src");
+        fs::create_dir_all(&src).unwrap();
+        fs::write(
+            src.join(format!("main.py.{}", SYSTEM_FILES_EXTENSION)),
+            MODULE,
+        )
+        .unwrap();
+
+        let target = dir.path().join("target");
+        let config = Config::new(&target, &src, &dir.path().join("plugins"), false);
+        let checker = Checker::new(config, true);
+
+        let errors = checker.check();
+        assert_eq!(errors.len(), 1);
+        assert!(matches!(errors[0], LPError::IncorrectReference(_)));
+        assert!(!target.exists());
+    }
+
+    #[test]
+    fn test_check_runs_plugins() {
+        let dir = tempdir().unwrap();
+        let src = dir.path().join("src");
+        fs::create_dir_all(&src).unwrap();
+        fs::write(
+            src.join(format!("main.py.{}", SYSTEM_FILES_EXTENSION)),
+            "sections:\n  - code: x = 1\n    docs: '# Main'\n",
+        )
+        .unwrap();
+
+        let config = Config::new(
+            &dir.path().join("target"),
+            &src,
+            &dir.path().join("plugins"),
+            false,
+        );
+
+        assert!(Checker::new(config.clone(), false).check().is_empty());
+
+        let errors = Checker::new(config, true).check();
+        assert_eq!(errors.len(), 1);
+        assert!(matches!(errors[0], LPError::PluginNotFound(_)));
+    }
+
+    #[test]
+    fn test_check_reports_plugin_warnings() {
+        let dir = tempdir().unwrap();
+        let src = dir.path().join("src");
+        let plugins = dir.path().join("plugins");
+        fs::create_dir_all(&src).unwrap();
+        fs::create_dir_all(&plugins).unwrap();
+        fs::write(
+            src.join(format!("main.py.{}", SYSTEM_FILES_EXTENSION)),
+            "sections:\n  - code: x = 1\n    docs: '# Main'\n",
+        )
+        .unwrap();
+        fs::write(
+            plugins.join("py.lua"),
+            r#"
+function get_import_code(current_path, referenced_path, code_block)
+    return ""
+end
+
+function clean_code(code)
+    lp.log("cleaning")
+    lp.warn(#lp.modules() .. " module(s)")
+    return code
+end
+"#,
+        )
+        .unwrap();
+
+        let config = Config::new(&dir.path().join("target"), &src, &plugins, false);
+        let errors = Checker::new(config, true).check();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].to_string(), "Plugin py warning: 1 module(s)");
+    }
+
+    #[test]
+    fn test_check_validates_modules_next_to_broken_ones() {
+        let dir = tempdir().unwrap();
+        let src = dir.path().join("src");
+        fs::create_dir_all(&src).unwrap();
+        fs::write(
+            src.join(format!("main.py.{}", SYSTEM_FILES_EXTENSION)),
+            MODULE,
+        )
+        .unwrap();
+        fs::write(
+            src.join(format!("broken.py.{}", SYSTEM_FILES_EXTENSION)),
+            "sections: [\n",
+        )
+        .unwrap();
+
+        let config = Config::new(
+            &dir.path().join("target"),
+            &src,
+            &dir.path().join("plugins"),
+            false,
+        );
+        let errors = Checker::new(config, true).check();
+        assert_eq!(errors.len(), 2);
+        assert!(matches!(errors[0], LPError::MalformedModule { .. }));
+        assert!(matches!(errors[1], LPError::IncorrectReference(_)));
+    }
+}
@@ -1,21 +1,42 @@
 #![forbid(unsafe_code)]
 
-use std::{path::PathBuf, rc::Rc};
+use std::{
+    cell::RefCell,
+    collections::{HashMap, HashSet},
+    path::{Path, PathBuf},
+    rc::Rc,
+    sync::LazyLock,
+};
 
-use mlua::Lua;
+use regex::Regex;
 
 use crate::{
     builds::{
+        cache::{hash_content, hash_index_reads, hash_module_inputs, BuildCache},
         index::ProjectIndex,
         spec::{
+            sections::locate_code_lines,
             structs::{Module, Project},
             utils::{self, get_module_extension},
         },
+        validation,
     },
-    error::LPError,
+    error::{LPError, PluginFailure},
 };
 
-use super::{config::Config, plugins::caller::PluginsCaller};
+use super::{
+    config::Config,
+    plugins::{
+        caller::PluginsCaller,
+        import_code::{ImportCode, Placement},
+        library::PluginMessage,
+        lifecycle::{BuildHook, BuildOutput, GeneratedFile, AFTER_MODULE_HOOK_NAME},
+    },
+    source_map::{
+        insert_line_directives, join_traced, trace_cleaned_code, trace_text, LineOrigin, SourceMap,
+        TracedLine,
+    },
+};
 
 /// CodeBuilder is a struct that is responsible for building the code from the source project.
 pub struct CodeBuilder {
@@ -23,14 +44,67 @@ pub struct CodeBuilder {
     project: Rc<Project>,
     plugins_caller: Rc<PluginsCaller>,
     index: Rc<ProjectIndex>,
+    /// names exported by the referenced sections, as returned by the plugins during the current build,
+    /// by the referenced module path without any extension and the section's header
+    exports: RefCell<HashMap<PathBuf, HashMap<String, Vec<String>>>>,
+}
+
+/// the header of a file is only a shebang, unless the plugin declares its `header_pattern`
+static DEFAULT_HEADER_PATTERN: LazyLock<Regex> = LazyLock::new(|| Regex::new("^#!").unwrap());
+
+/// Imports of a module collected from its references, grouped by their placement
+#[derive(Default)]
+struct ModuleImports {
+    top: Vec<TracedLine>,
+    after_header: Vec<TracedLine>,
+    /// every import is added only once
+    seen: HashSet<String>,
+}
+
+impl ModuleImports {
+    fn add(&mut self, import: ImportCode, origin: LineOrigin) {
+        for text in import.imports {
+            if !self.seen.insert(text.clone()) {
+                continue;
+            }
+            let lines = trace_text(&text, Some(origin));
+            match import.placement {
+                Placement::Top => self.top.extend(lines),
+                Placement::AfterHeader => self.after_header.extend(lines),
+            }
+        }
+    }
 }
 
-fn join_code_with_imports(code: String, imports: String) -> String {
-    if imports.is_empty() {
-        code
-    } else {
-        format!("{}\n{}", imports, code)
+/// Returns the number of the leading lines of the code which form its header:
+/// the lines matching the pattern, possibly separated by blank lines
+fn header_len(code: &[TracedLine], pattern: &Regex) -> usize {
+    let mut len = 0;
+    for (i, line) in code.iter().enumerate() {
+        if pattern.is_match(&line.text) {
+            len = i + 1;
+        } else if !line.text.trim().is_empty() {
+            break;
+        }
     }
+    len
+}
+
+fn join_code_with_imports(
+    code: Vec<TracedLine>,
+    imports: ModuleImports,
+    header_pattern: &Regex,
+) -> Vec<TracedLine> {
+    let header = header_len(&code, header_pattern);
+    let mut code = code;
+    let body = code.split_off(header);
+    imports
+        .top
+        .into_iter()
+        .chain(code)
+        .chain(imports.after_header)
+        .chain(body)
+        .collect()
 }
 
 impl CodeBuilder {
@@ -39,39 +113,33 @@ impl CodeBuilder {
     /// * `config` - a Config instance that contains the configuration for the builder.
     /// * `project` - an Rc<Project> instance that contains the source project.
     /// * `index` - an Rc<ProjectIndex> instance that contains the index of the project.
-    /// * `lua` - an Rc<Lua> instance that is used for running Lua plugins.
     pub fn new(
         config: Config,
         project: Rc<Project>,
         index: Rc<ProjectIndex>,
-        lua: Rc<Lua>,
     ) -> Result<Self, LPError> {
-        let plugins_caller = Rc::new(PluginsCaller::new(lua.clone(), &config.plugins_dir)?);
+        let plugins_caller = Rc::new(PluginsCaller::new(
+            &config.plugins_dir,
+            &config.plugins,
+            Rc::clone(&index),
+        )?);
         Ok(Self {
             config,
             project,
             plugins_caller,
             index,
+            exports: RefCell::default(),
         })
     }
 
-    fn validate_references(&self, module: Rc<Module>) -> Result<(), LPError> {
-        for section in module.sections.as_ref().unwrap() {
-            for reference in &section.references {
-                let referenced_module_path = module.resolve_relative_module_path(&reference.path);
-                if self
-                    .index
-                    .get_section(&referenced_module_path, &reference.header)
-                    .is_none()
-                {
-                    return Err(LPError::IncorrectReference(
-                        referenced_module_path,
-                        reference.header.clone(),
-                    ));
-                }
-            }
-        }
-        Ok(())
+    /// Returns the plugins the code is built with, so the docs can be rendered by the same plugins
+    pub fn plugins_caller(&self) -> Rc<PluginsCaller> {
+        Rc::clone(&self.plugins_caller)
+    }
+
+    /// Returns the messages logged by the plugins since the previous call
+    pub fn take_plugin_messages(&self) -> Vec<PluginMessage> {
+        self.plugins_caller.take_messages()
     }
 
     fn prepare_target_path(&self, path: &PathBuf) -> PathBuf {
@@ -86,27 +154,25 @@ impl CodeBuilder {
         result
     }
 
-    fn get_all_code(&self, module: Rc<Module>) -> String {
-        module
-            .sections
-            .as_ref()
-            .unwrap()
-            .iter()
-            .map(|s| s.as_ref().code.as_str())
-            .collect::<Vec<&str>>()
-            .join("\n")
+    /// Returns the lines of the module's code with all the chunks expanded
+    fn get_all_code(&self, module: Rc<Module>) -> Result<Vec<TracedLine>, LPError> {
+        Ok(module
+            .get_tangled_lines()?
+            .into_iter()
+            .map(TracedLine::from)
+            .collect())
     }
 
     /// module must have sections
     /// all the references should be valid
-    fn get_all_imports(&self, module: Rc<Module>) -> Result<String, LPError> {
-        let mut imports: Vec<String> = vec![];
+    fn get_all_imports(&self, module: Rc<Module>) -> Result<ModuleImports, LPError> {
+        let mut imports = ModuleImports::default();
         let current_path = utils::prepare_module_file_extension(&module.path);
         let current_extension = get_module_extension(&current_path);
-        for section in module.sections.as_ref().unwrap() {
+        for (section_index, section) in module.sections.as_ref().unwrap().iter().enumerate() {
             for reference in &section.references {
                 let reference_path = reference.path.clone();
-                if reference_path != current_path && reference_path != PathBuf::from("") {
+                if reference_path != current_path && reference_path.as_os_str() != "" {
                     let referenced_module_relative_path = reference.path.clone();
                     let mut referenced_module_path =
                         module.resolve_relative_module_path(&referenced_module_relative_path);
@@ -117,6 +183,7 @@ impl CodeBuilder {
                         .unwrap()
                         .code
                         .clone();
+                    let referenced_module_name = referenced_module_path.clone();
                     if current_path.extension().is_some() {
                         referenced_module_path.set_extension(current_path.extension().unwrap());
                     }
@@ -127,36 +194,205 @@ impl CodeBuilder {
                         &referenced_code,
                     );
                     match import {
-                        Ok(import) => imports.push(import),
-                        Err(e) => return Err(e),
+                        Ok(mut import) => {
+                            if !import.exports.is_empty() {
+                                self.exports
+                                    .borrow_mut()
+                                    .entry(referenced_module_name)
+                                    .or_default()
+                                    .insert(referenced_header, std::mem::take(&mut import.exports));
+                            }
+                            imports.add(
+                                import,
+                                LineOrigin {
+                                    section: section_index,
+                                    line: None,
+                                },
+                            )
+                        }
+                        Err(e) => {
+                            return Err(e
+                                .in_section(
+                                    &module.path,
+                                    Some(section_index),
+                                    section.get_header().as_deref(),
+                                )
+                                .with_reference(&referenced_module_name, &referenced_header))
+                        }
                     }
                 }
             }
         }
-        Ok(imports.join("\n"))
+        Ok(imports)
     }
 
-    fn prepare_final_code(&self, module: Rc<Module>) -> Result<String, LPError> {
-        let no_imports_code = self.get_all_code(module.clone());
+    /// Returns the lines of the final code together with their origins
+    fn prepare_final_code(&self, module: Rc<Module>) -> Result<Vec<TracedLine>, LPError> {
+        let no_imports_code = self.get_all_code(module.clone())?;
         let imports = self.get_all_imports(module.clone())?;
-        self.plugins_caller.call_plugin_cleaning_func(
-            get_module_extension(&module.path).as_str(),
-            &join_code_with_imports(no_imports_code, imports),
-        )
+        let extension = get_module_extension(&module.path);
+        let header_pattern = self
+            .plugins_caller
+            .get_header_pattern(&extension)
+            .unwrap_or(&DEFAULT_HEADER_PATTERN);
+        let code = join_code_with_imports(no_imports_code, imports, header_pattern);
+        let cleaned_code = self
+            .plugins_caller
+            .call_plugin_cleaning_func(extension.as_str(), &join_traced(&code))
+            .map_err(|e| e.in_section(&module.path, None, None))?;
+        Ok(trace_cleaned_code(&cleaned_code, &code))
     }
 
-    /// The main method of the CodeBuilder that builds the code.
-    /// It validates the references, prepares the final code and writes it to the target directory.
-    /// If the module has no sections, it just copies the source file to the target directory.
-    /// Returns an error if any of the operations failed.
-    pub fn build(&self) -> Result<(), LPError> {
+    /// Inserts the line directives declared by the module's plugin into the final code, if any.
+    /// No directives are inserted if the sections' code can't be located in the module.
+    fn add_line_directives(
+        &self,
+        module: &Module,
+        target_path: &Path,
+        code: Vec<TracedLine>,
+    ) -> Result<Vec<TracedLine>, LPError> {
+        let Some(template) = self
+            .plugins_caller
+            .get_line_directive(get_module_extension(&module.path).as_str())
+        else {
+            return Ok(code);
+        };
+
+        let source_path = self.get_module_source_path(&module.path);
+        let content = std::fs::read_to_string(&source_path)
+            .map_err(|_| LPError::CannotReadFile(source_path.display().to_string()))?;
+        let Some(code_lines) =
+            locate_code_lines(&content, module.sections.as_deref().unwrap_or_default())
+        else {
+            return Ok(code);
+        };
+        Ok(insert_line_directives(
+            code,
+            template,
+            &source_path,
+            target_path,
+            &code_lines,
+        ))
+    }
+
+    /// Calls the plugins' `get_exports` for every section with a header and records the results in the index.
+    /// The modules whose plugins don't declare the function are skipped.
+    /// Returns all the errors the plugins produced.
+    pub fn collect_exports(&self) -> Result<(), LPError> {
+        let mut errors = vec![];
         for module in &self.project.modules {
-            if module.sections.is_some() {
-                self.validate_references(Rc::clone(module))?
+            let module_path = utils::prepare_module_file_extension(&module.path);
+            let module_name = utils::module_name(&module.path);
+            let extension = get_module_extension(&module.path);
+            for (section_index, section) in module
+                .sections
+                .as_deref()
+                .unwrap_or_default()
+                .iter()
+                .enumerate()
+            {
+                let Some(header) = section.get_header() else {
+                    continue;
+                };
+                match self.plugins_caller.call_plugin_exports_func(
+                    &extension,
+                    &module_path,
+                    &section.code,
+                ) {
+                    Ok(Some(exports)) => self.index.set_exports(&module_name, &header, exports),
+                    Ok(None) => {}
+                    Err(e) => {
+                        errors.push(e.in_section(&module.path, Some(section_index), Some(&header)))
+                    }
+                }
             }
         }
+        LPError::from_many(errors)
+    }
 
-        for module in &self.project.modules {
+    /// Prepares the final code of every literate module in memory, without writing anything.
+    /// All the references must be valid.
+    /// Returns all the errors the plugins produced.
+    pub fn check(&self) -> Result<(), LPError> {
+        let errors = self
+            .project
+            .modules
+            .iter()
+            .filter(|module| module.sections.is_some())
+            .filter_map(|module| self.prepare_final_code(module.clone()).err())
+            .collect();
+        LPError::from_many(errors)
+    }
+
+    /// Writes the files generated by the plugins' lifecycle `hook` into the target directory.
+    /// A generated file can't replace the output of a module.
+    fn write_generated_files(
+        &self,
+        cache: &mut BuildCache,
+        module_outputs: &HashSet<&Path>,
+        hook: &str,
+        files: Vec<(String, GeneratedFile)>,
+    ) -> Result<(), LPError> {
+        for (plugin, file) in files {
+            if module_outputs.contains(file.path.as_path()) {
+                return Err(LPError::PluginFailed(Box::new(PluginFailure {
+                    plugin,
+                    hook: hook.to_string(),
+                    message: format!(
+                        "the generated file {} would replace the output of a module",
+                        file.path.display()
+                    ),
+                    ..PluginFailure::default()
+                })));
+            }
+            let path = self.config.target_code_dir.join(&file.path);
+            if let Some(parent) = path.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+            let content = file.content.as_bytes();
+            cache.write(&path, &hash_content(content), content)?;
+        }
+        Ok(())
+    }
+
+    /// The main method of the CodeBuilder that builds the code.
+    /// It validates the references, prepares the final code and writes it to the target directory.
+    /// A source map is written next to each file built from a literate module.
+    /// If the module has no sections, it just copies the source file to the target directory.
+    /// A module is rebuilt only if it, a section it references or its plugin changed since the build recorded in `cache`.
+    /// The plugins' lifecycle hooks are called before and after the build and after each literate module,
+    /// including the up-to-date ones, and the files they return are written into the target directory.
+    /// The names the plugins report as exported by the referenced sections are recorded in the `cache`.
+    /// Returns an error if any of the operations failed.
+    pub fn build(&self, cache: &mut BuildCache) -> Result<(), LPError> {
+        LPError::from_many(
+            [
+                validation::validate_references(&self.project, &self.index),
+                validation::validate_chunks(&self.project),
+            ]
+            .into_iter()
+            .filter_map(Result::err)
+            .collect(),
+        )?;
+
+        let outputs: Vec<BuildOutput> = self
+            .project
+            .modules
+            .iter()
+            .map(|module| BuildOutput {
+                module: module.path.clone(),
+                output: utils::prepare_module_file_extension(&module.path),
+                extension: get_module_extension(&module.path),
+            })
+            .collect();
+        let module_outputs: HashSet<&Path> = outputs.iter().map(|o| o.output.as_path()).collect();
+
+        let files = self
+            .plugins_caller
+            .call_build_hook(BuildHook::BeforeBuild, &outputs)?;
+        self.write_generated_files(cache, &module_outputs, BuildHook::BeforeBuild.name(), files)?;
+
+        for (module, output) in self.project.modules.iter().zip(&outputs) {
             let source_path = self.get_module_source_path(&module.path);
             let target_path = self.prepare_target_path(&module.path);
 
@@ -165,10 +401,56 @@ impl CodeBuilder {
             }
 
             if module.sections.is_some() {
-                let final_code = self.prepare_final_code(module.clone())?;
-                std::fs::write(target_path, format!("{}\n", final_code))?;
+                let source = std::fs::read(&source_path)?;
+                let inputs = hash_module_inputs(module, &self.index, &source)
+                    .update(
+                        &self
+                            .plugins_caller
+                            .plugin_fingerprint(&get_module_extension(&module.path)),
+                    )
+                    .finish();
+                let source_map_path = SourceMap::path_for(&target_path);
+                let previous_inputs =
+                    hash_index_reads(&inputs, &cache.index_reads(&target_path), &self.index);
+                let code = if cache.is_fresh(&target_path, &previous_inputs)
+                    && cache.is_fresh(&source_map_path, &previous_inputs)
+                {
+                    std::fs::read_to_string(&target_path)?
+                } else {
+                    self.index.take_reads();
+                    let final_code = self.prepare_final_code(module.clone())?;
+                    let final_code = self.add_line_directives(module, &target_path, final_code)?;
+                    let reads = self.index.take_reads();
+                    let inputs = hash_index_reads(&inputs, &reads, &self.index);
+                    let code = format!("{}\n", join_traced(&final_code));
+                    cache.write(&target_path, &inputs, code.as_bytes())?;
+                    cache.record_index_reads(&target_path, reads.clone());
+                    let source_map =
+                        SourceMap::new(&self.config.source_dir, &target_path, module, &final_code);
+                    cache.write(&source_map_path, &inputs, source_map.to_json()?.as_bytes())?;
+                    cache.record_index_reads(&source_map_path, reads);
+                    code
+                };
+
+                let files = self
+                    .plugins_caller
+                    .call_after_module_hook(output, &code)
+                    .map_err(|e| e.in_section(&module.path, None, None))?;
+                self.write_generated_files(cache, &module_outputs, AFTER_MODULE_HOOK_NAME, files)?;
             } else {
-                std::fs::copy(source_path, target_path)?;
+                cache.copy(&source_path, &target_path)?;
+            }
+        }
+
+        let files = self
+            .plugins_caller
+            .call_build_hook(BuildHook::AfterBuild, &outputs)?;
+        self.write_generated_files(cache, &module_outputs, BuildHook::AfterBuild.name(), files)?;
+
+        cache.retain_exports(|module, header| self.index.get_section(module, header).is_some());
+        for (module, sections) in self.exports.take() {
+            for (header, exports) in sections {
+                cache.record_exports(&module, &header, exports);
             }
         }
         Ok(())
@@ -180,24 +462,78 @@ mod tests {
     use super::*;
     use crate::builds::spec::structs::Section;
     use crate::config::constants::SYSTEM_FILES_EXTENSION;
+    use crate::config::project_file::PluginsSettings;
+
+    fn imports(imports: Vec<(&str, Placement)>) -> ModuleImports {
+        let mut result = ModuleImports::default();
+        for (import, placement) in imports {
+            result.add(
+                ImportCode {
+                    imports: vec![import.to_string()],
+                    exports: vec![],
+                    placement,
+                },
+                LineOrigin {
+                    section: 0,
+                    line: None,
+                },
+            );
+        }
+        result
+    }
 
     #[test]
     fn test_join_code_with_imports() {
-        let code = "fn hello() {}".to_string();
-        let imports = "use std::io".to_string();
-
-        let result = join_code_with_imports(code, imports);
+        let pattern = &*DEFAULT_HEADER_PATTERN;
+        let code = trace_text("fn hello() {}", None);
+
+        let result = join_code_with_imports(
+            code,
+            imports(vec![
+                ("use std::io", Placement::Top),
+                ("use std::io", Placement::Top),
+            ]),
+            pattern,
+        );
         let expected = "use std::io\nfn hello() {}";
 
-        assert_eq!(result, expected);
+        assert_eq!(join_traced(&result), expected);
 
-        let code = "fn hello() {}".to_string();
-        let imports = "".to_string();
+        let code = trace_text("fn hello() {}", None);
 
-        let result = join_code_with_imports(code, imports);
+        let result = join_code_with_imports(code, ModuleImports::default(), pattern);
         let expected = "fn hello() {}";
 
-        assert_eq!(result, expected);
+        assert_eq!(join_traced(&result), expected);
+    }
+
+    #[test]
+    fn test_join_code_with_imports_after_header() {
+        let pattern = Regex::new("^(#!|//|package )").unwrap();
+        let code = trace_text("// License\n\npackage main\n\nfunc main() {}", None);
+
+        let result = join_code_with_imports(
+            code,
+            imports(vec![
+                ("import \"fmt\"", Placement::AfterHeader),
+                ("// generated", Placement::Top),
+            ]),
+            &pattern,
+        );
+        let expected = "// generated\n// License\n\npackage main\nimport \"fmt\"\n\nfunc main() {}";
+
+        assert_eq!(join_traced(&result), expected);
+
+        let code = trace_text("#!/usr/bin/env python3\nprint(1)", None);
+        let result = join_code_with_imports(
+            code,
+            imports(vec![("import sys", Placement::AfterHeader)]),
+            &DEFAULT_HEADER_PATTERN,
+        );
+        assert_eq!(
+            join_traced(&result),
+            "#!/usr/bin/env python3\nimport sys\nprint(1)"
+        );
     }
 
     #[test]
@@ -206,12 +542,12 @@ mod tests {
             PathBuf::from("/target"),
             PathBuf::from("/source"),
             PathBuf::from("/plugins"),
+            PluginsSettings::default(),
         );
         let project = Rc::new(Project { modules: vec![] });
         let index = Rc::new(ProjectIndex::new(Rc::clone(&project)));
-        let lua = Rc::new(Lua::new());
 
-        let builder = CodeBuilder::new(config, project, index, Rc::clone(&lua)).unwrap();
+        let builder = CodeBuilder::new(config, project, index).unwrap();
 
         let path = PathBuf::from(format!("module.rs.{}", SYSTEM_FILES_EXTENSION));
         let target_path = builder.prepare_target_path(&path);
@@ -225,12 +561,12 @@ mod tests {
             PathBuf::from("/target"),
             PathBuf::from("/source"),
             PathBuf::from("/plugins"),
+            PluginsSettings::default(),
         );
         let project = Rc::new(Project { modules: vec![] });
         let index = Rc::new(ProjectIndex::new(Rc::clone(&project)));
-        let lua = Rc::new(Lua::new());
 
-        let builder = CodeBuilder::new(config, project, index, Rc::clone(&lua)).unwrap();
+        let builder = CodeBuilder::new(config, project, index).unwrap();
 
         let module_path = PathBuf::from(format!("dir/module.rs.{}", SYSTEM_FILES_EXTENSION));
         let source_path = builder.get_module_source_path(&module_path);
@@ -247,6 +583,7 @@ mod tests {
             PathBuf::from("/target"),
             PathBuf::from("/source"),
             PathBuf::from("/plugins"),
+            PluginsSettings::default(),
         );
 
         let section1 = Rc::new(Section {
@@ -270,13 +607,19 @@ mod tests {
 
         let project = Rc::new(Project { modules: vec![] });
         let index = Rc::new(ProjectIndex::new(Rc::clone(&project)));
-        let lua = Rc::new(Lua::new());
 
-        let builder = CodeBuilder::new(config, project, index, Rc::clone(&lua)).unwrap();
+        let builder = CodeBuilder::new(config, project, index).unwrap();
 
-        let code = builder.get_all_code(module);
+        let code = builder.get_all_code(module).unwrap();
         let expected = "fn hello() {}\nfn world() {}";
 
-        assert_eq!(code, expected);
+        assert_eq!(join_traced(&code), expected);
+        assert_eq!(
+            code[1].origin,
+            Some(LineOrigin {
+                section: 1,
+                line: Some(1)
+            })
+        );
     }
 }
@@ -2,19 +2,28 @@
 
 use std::path::PathBuf;
 
+use crate::config::project_file::PluginsSettings;
+
 pub struct Config {
     pub target_code_dir: PathBuf,
     pub source_dir: PathBuf,
 
     pub plugins_dir: PathBuf,
+    pub plugins: PluginsSettings,
 }
 
 impl Config {
-    pub fn new(target_code_dir: PathBuf, source_dir: PathBuf, plugins_dir: PathBuf) -> Self {
+    pub fn new(
+        target_code_dir: PathBuf,
+        source_dir: PathBuf,
+        plugins_dir: PathBuf,
+        plugins: PluginsSettings,
+    ) -> Self {
         Config {
             target_code_dir,
             source_dir,
             plugins_dir,
+            plugins,
         }
     }
 }
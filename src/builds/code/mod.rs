@@ -3,3 +3,4 @@
 pub mod code_builder;
 pub mod config;
 pub mod plugins;
+pub mod source_map;
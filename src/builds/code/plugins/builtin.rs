@@ -0,0 +1,442 @@
+#![forbid(unsafe_code)]
+//! built-in plugins, they do the same as the plugins in `examples/plugins`
+
+use std::{
+    collections::{BTreeSet, HashSet},
+    path::{Path, PathBuf},
+};
+
+use regex::Regex;
+
+use crate::error::LPError;
+
+use super::{
+    import_code::{ImportCode, Placement},
+    language_plugin::LanguagePlugin,
+    lifecycle::{BuildHook, BuildOutput, GeneratedFile},
+    manifest::Manifest,
+};
+
+fn manifest(name: &str, extensions: &[&str], hooks: &[&str]) -> Manifest {
+    Manifest {
+        name: Some(name.to_string()),
+        version: Some(env!("CARGO_PKG_VERSION").to_string()),
+        extensions: extensions.iter().map(|e| e.to_string()).collect(),
+        hooks: hooks.iter().map(|h| h.to_string()).collect(),
+        ..Manifest::default()
+    }
+}
+
+/// Returns the non-empty lines of the code, as the example plugins split it
+fn non_empty_lines(code: &str) -> impl Iterator<Item = &str> {
+    code.split(['\r', '\n']).filter(|line| !line.is_empty())
+}
+
+/// Returns the built-in plugins by the names of the Lua plugins they replace
+pub fn builtin_plugins() -> Vec<(String, Box<dyn LanguagePlugin>)> {
+    vec![
+        ("py".to_string(), Box::new(PythonPlugin::new())),
+        ("js".to_string(), Box::new(JavaScriptPlugin::new())),
+        ("json".to_string(), Box::new(JsonPlugin::new())),
+    ]
+}
+
+/// Imports the classes and the functions with `from module import ...`
+pub struct PythonPlugin {
+    manifest: Manifest,
+    header_pattern: Regex,
+    definition: Regex,
+    import: Regex,
+    from_import: Regex,
+    imported_name: Regex,
+    path_separators: Regex,
+}
+
+impl PythonPlugin {
+    pub fn new() -> Self {
+        PythonPlugin {
+            manifest: manifest(
+                "python",
+                &["pyw"],
+                &[
+                    "get_import_code",
+                    "clean_code",
+                    "get_exports",
+                    "after_build",
+                ],
+            ),
+            header_pattern: Regex::new(r"^(#!|#.*coding[:=]|from __future__ import)").unwrap(),
+            definition: Regex::new(r"(?m)^(?:class|def)\s+(\w+)").unwrap(),
+            import: Regex::new(r"^import\s+([^\s,]+)").unwrap(),
+            from_import: Regex::new(r"^from\s+([\w.]+)\s+import\s+(.+)").unwrap(),
+            imported_name: Regex::new(r"^\s*(\w+)").unwrap(),
+            path_separators: Regex::new(r"[/\\]+").unwrap(),
+        }
+    }
+
+    fn definitions(&self, code_block: &str) -> Vec<String> {
+        self.definition
+            .captures_iter(code_block)
+            .map(|captures| captures[1].to_string())
+            .collect()
+    }
+}
+
+impl LanguagePlugin for PythonPlugin {
+    fn manifest(&self) -> &Manifest {
+        &self.manifest
+    }
+
+    /// The built-in plugins change only with the system itself
+    fn fingerprint(&self) -> &[u8] {
+        env!("CARGO_PKG_VERSION").as_bytes()
+    }
+
+    fn get_import_code(
+        &self,
+        _current_path: &Path,
+        referenced_path: &Path,
+        code_block: &str,
+    ) -> Result<ImportCode, LPError> {
+        let definitions = self.definitions(code_block);
+        if definitions.is_empty() {
+            return Ok(ImportCode::default());
+        }
+        let referenced_path = referenced_path.to_string_lossy();
+        let referenced_module = self
+            .path_separators
+            .replace_all(
+                referenced_path
+                    .strip_suffix(".py")
+                    .unwrap_or(&referenced_path),
+                ".",
+            )
+            .to_string();
+        Ok(ImportCode {
+            imports: vec![format!(
+                "from {} import {}",
+                referenced_module,
+                definitions.join(", ")
+            )],
+            exports: vec![],
+            placement: Placement::AfterHeader,
+        })
+    }
+
+    /// Removes the duplicate imports, the names imported from the same module several times are imported once
+    fn clean_code(&self, code: &str) -> Result<String, LPError> {
+        let mut seen_imports = HashSet::new();
+        let mut seen_from_imports: HashSet<(String, String)> = HashSet::new();
+        let mut clean_lines = vec![];
+
+        for line in non_empty_lines(code) {
+            let trimmed = line.trim();
+            if let Some(captures) = self.import.captures(trimmed) {
+                if seen_imports.insert(captures[1].to_string()) {
+                    clean_lines.push(line.to_string());
+                }
+            } else if let Some(captures) = self.from_import.captures(trimmed) {
+                let module = &captures[1];
+                let import_list: Vec<&str> = captures[2]
+                    .split(',')
+                    .filter(|item| {
+                        self.imported_name.captures(item).is_some_and(|name| {
+                            seen_from_imports.insert((module.to_string(), name[1].to_string()))
+                        })
+                    })
+                    .map(str::trim)
+                    .collect();
+                if !import_list.is_empty() {
+                    clean_lines.push(format!("from {} import {}", module, import_list.join(", ")));
+                }
+            } else {
+                clean_lines.push(line.to_string());
+            }
+        }
+        Ok(clean_lines.join("\n"))
+    }
+
+    fn get_exports(
+        &self,
+        _module_path: &Path,
+        code_block: &str,
+    ) -> Result<Option<Vec<String>>, LPError> {
+        Ok(Some(self.definitions(code_block)))
+    }
+
+    fn header_pattern(&self) -> Option<&Regex> {
+        Some(&self.header_pattern)
+    }
+
+    /// Makes every directory with python modules a package
+    fn call_build_hook(
+        &self,
+        hook: BuildHook,
+        modules: &[BuildOutput],
+    ) -> Result<Vec<GeneratedFile>, LPError> {
+        if hook != BuildHook::AfterBuild {
+            return Ok(vec![]);
+        }
+        let outputs: HashSet<&Path> = modules.iter().map(|m| m.output.as_path()).collect();
+        let packages: BTreeSet<PathBuf> = modules
+            .iter()
+            .filter(|module| module.extension == "py")
+            .flat_map(|module| module.output.ancestors().skip(1))
+            .filter(|dir| !dir.as_os_str().is_empty())
+            .map(|dir| dir.join("__init__.py"))
+            .filter(|init| !outputs.contains(init.as_path()))
+            .collect();
+        Ok(packages
+            .into_iter()
+            .map(|path| GeneratedFile {
+                path,
+                content: String::new(),
+            })
+            .collect())
+    }
+}
+
+/// Imports the exported functions and classes with `require`
+pub struct JavaScriptPlugin {
+    manifest: Manifest,
+    export_function: Regex,
+    export_class: Regex,
+    require: Regex,
+    import: Regex,
+    backslashes: Regex,
+}
+
+impl JavaScriptPlugin {
+    pub fn new() -> Self {
+        JavaScriptPlugin {
+            manifest: manifest(
+                "javascript",
+                &["mjs", "cjs"],
+                &["get_import_code", "clean_code", "get_exports"],
+            ),
+            export_function: Regex::new(r"^export\s+function\s+(\w+)\(").unwrap(),
+            export_class: Regex::new(r"^export\s+class\s+(\w+)").unwrap(),
+            require: Regex::new(r#"^const\s+[\w{},= ]*?\s*=\s*require\(['"]([^'"]+)['"]\)"#)
+                .unwrap(),
+            import: Regex::new(r#"^import.*from\s+['"]([^'"]+)['"]"#).unwrap(),
+            backslashes: Regex::new(r"\\+").unwrap(),
+        }
+    }
+
+    fn definitions(&self, code_block: &str) -> Vec<String> {
+        non_empty_lines(code_block)
+            .filter_map(|line| {
+                self.export_function
+                    .captures(line)
+                    .or_else(|| self.export_class.captures(line))
+                    .map(|captures| captures[1].to_string())
+            })
+            .collect()
+    }
+}
+
+impl LanguagePlugin for JavaScriptPlugin {
+    fn manifest(&self) -> &Manifest {
+        &self.manifest
+    }
+
+    /// The built-in plugins change only with the system itself
+    fn fingerprint(&self) -> &[u8] {
+        env!("CARGO_PKG_VERSION").as_bytes()
+    }
+
+    fn get_import_code(
+        &self,
+        _current_path: &Path,
+        referenced_path: &Path,
+        code_block: &str,
+    ) -> Result<ImportCode, LPError> {
+        let definitions = self.definitions(code_block);
+        if definitions.is_empty() {
+            return Ok(ImportCode::default());
+        }
+        let referenced_path = referenced_path.to_string_lossy();
+        let referenced_module = self
+            .backslashes
+            .replace_all(
+                referenced_path
+                    .strip_suffix(".js")
+                    .unwrap_or(&referenced_path),
+                "/",
+            )
+            .to_string();
+        Ok(ImportCode {
+            imports: vec![format!(
+                "const {{ {} }} = require('./{}');",
+                definitions.join(", "),
+                referenced_module
+            )],
+            ..ImportCode::default()
+        })
+    }
+
+    /// Removes the duplicate `require` calls and `import` statements of the same modules
+    fn clean_code(&self, code: &str) -> Result<String, LPError> {
+        let mut seen_requires = HashSet::new();
+        let mut seen_imports = HashSet::new();
+        let clean_lines: Vec<&str> = non_empty_lines(code)
+            .filter(|line| {
+                let trimmed = line.trim();
+                if let Some(captures) = self.require.captures(trimmed) {
+                    seen_requires.insert(captures[1].to_string())
+                } else if let Some(captures) = self.import.captures(trimmed) {
+                    seen_imports.insert(captures[1].to_string())
+                } else {
+                    true
+                }
+            })
+            .collect();
+        Ok(clean_lines.join("\n"))
+    }
+
+    fn get_exports(
+        &self,
+        _module_path: &Path,
+        code_block: &str,
+    ) -> Result<Option<Vec<String>>, LPError> {
+        Ok(Some(self.definitions(code_block)))
+    }
+}
+
+/// Allows documenting JSON files: nothing is imported and the code is kept as is
+pub struct JsonPlugin {
+    manifest: Manifest,
+}
+
+impl JsonPlugin {
+    pub fn new() -> Self {
+        JsonPlugin {
+            manifest: manifest("json", &[], &["get_import_code", "clean_code"]),
+        }
+    }
+}
+
+impl LanguagePlugin for JsonPlugin {
+    fn manifest(&self) -> &Manifest {
+        &self.manifest
+    }
+
+    /// The built-in plugins change only with the system itself
+    fn fingerprint(&self) -> &[u8] {
+        env!("CARGO_PKG_VERSION").as_bytes()
+    }
+
+    fn get_import_code(
+        &self,
+        _current_path: &Path,
+        _referenced_path: &Path,
+        _code_block: &str,
+    ) -> Result<ImportCode, LPError> {
+        Ok(ImportCode::default())
+    }
+
+    fn clean_code(&self, code: &str) -> Result<String, LPError> {
+        Ok(code.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_python() {
+        let plugin = PythonPlugin::new();
+        let import = plugin
+            .get_import_code(
+                Path::new("main.py"),
+                Path::new("lib/primes.py"),
+                "class Sieve:\n    pass\ndef is_prime(n):\n    pass",
+            )
+            .unwrap();
+        assert_eq!(
+            import.imports,
+            vec!["from lib.primes import Sieve, is_prime"]
+        );
+        assert_eq!(import.placement, Placement::AfterHeader);
+
+        let cleaned = plugin
+            .clean_code(
+                "import sys\nfrom a import f, g\n\nimport sys\nfrom a import g, h\nprint(1)",
+            )
+            .unwrap();
+        assert_eq!(
+            cleaned,
+            "import sys\nfrom a import f, g\nfrom a import h\nprint(1)"
+        );
+
+        assert_eq!(
+            plugin
+                .get_exports(Path::new("a.py"), "def f():\n    def inner(): pass")
+                .unwrap(),
+            Some(vec!["f".to_string()])
+        );
+    }
+
+    #[test]
+    fn test_python_packages() {
+        let module = |output: &str, extension: &str| BuildOutput {
+            module: PathBuf::from(format!("{}.lpnb", output)),
+            output: PathBuf::from(output),
+            extension: extension.to_string(),
+        };
+        let modules = vec![
+            module("main.py", "py"),
+            module("app/core/app.py", "py"),
+            module("app/__init__.py", "py"),
+            module("web/index.js", "js"),
+        ];
+        let plugin = PythonPlugin::new();
+        assert!(plugin
+            .call_build_hook(BuildHook::BeforeBuild, &modules)
+            .unwrap()
+            .is_empty());
+        let files: Vec<PathBuf> = plugin
+            .call_build_hook(BuildHook::AfterBuild, &modules)
+            .unwrap()
+            .into_iter()
+            .map(|file| file.path)
+            .collect();
+        assert_eq!(files, vec![PathBuf::from("app/core/__init__.py")]);
+    }
+
+    #[test]
+    fn test_javascript() {
+        let plugin = JavaScriptPlugin::new();
+        let import = plugin
+            .get_import_code(
+                Path::new("app.js"),
+                Path::new("lib/math.js"),
+                "export function add(a, b) {}\nexport class Vector {}\nfunction hidden() {}",
+            )
+            .unwrap();
+        assert_eq!(
+            import.imports,
+            vec!["const { add, Vector } = require('./lib/math');"]
+        );
+
+        let cleaned = plugin
+            .clean_code("const { a } = require('./a');\nconst { b } = require('./a');\nimport x from 'x';\nimport y from 'x';\nrun();")
+            .unwrap();
+        assert_eq!(
+            cleaned,
+            "const { a } = require('./a');\nimport x from 'x';\nrun();"
+        );
+    }
+
+    #[test]
+    fn test_json() {
+        let plugin = JsonPlugin::new();
+        assert!(plugin
+            .get_import_code(Path::new("a.json"), Path::new("b.json"), "{}")
+            .unwrap()
+            .imports
+            .is_empty());
+        assert_eq!(plugin.clean_code("{\n\n}").unwrap(), "{\n\n}");
+    }
+}
@@ -2,79 +2,210 @@
 
 use std::{collections::HashMap, fs, path::Path, rc::Rc};
 
-use crate::error::LPError;
-use mlua::{Function, Lua};
-
-use super::plugin::get_plugin_funcs;
+use regex::Regex;
+
+use crate::{
+    builds::index::ProjectIndex,
+    config::project_file::{Fallback, PluginsSettings},
+    error::LPError,
+};
+
+use super::{
+    builtin::builtin_plugins,
+    import_code::ImportCode,
+    language_plugin::LanguagePlugin,
+    library::{PluginLog, PluginMessage},
+    lifecycle::{BuildHook, BuildOutput, GeneratedFile},
+    plugin::LuaPlugin,
+    wasm_plugin::{WasmPlugin, WASM_PLUGIN_EXTENSION},
+};
 
 /// Calls the functions of the plugins.
 pub struct PluginsCaller {
-    plugin_import_functions: HashMap<String, Function>,
-    plugin_cleaning_functions: HashMap<String, Function>,
+    plugins: HashMap<String, Box<dyn LanguagePlugin>>,
+    settings: PluginsSettings,
+    /// the plugins handling the extensions declared in their manifests
+    aliases: HashMap<String, String>,
+    /// messages logged by the plugins with the `lp` library
+    log: PluginLog,
 }
 
-const PLUGIN_EXTENSION: &str = "lua";
-const PLUGIN_IMPORT_CODE_FUNC_NAME: &str = "get_import_code";
-const PLUGIN_CLEANIONG_CODE_FUNC_NAME: &str = "clean_code";
+pub const PLUGIN_EXTENSION: &str = "lua";
 
 impl PluginsCaller {
-    /// Scans the plugins directory (`dir`) for files named `*.lua` (without descending into subdirs).
-    /// Each plugin file is loaded, the necessary functions are extracted and stored in the struct.
+    /// Scans the plugins directory (`dir`) for files named `*.lua` and `*.wasm` (without descending into subdirs).
+    /// The built-in plugins are added first if enabled in `settings`, the plugins with the same names override them.
+    /// Each Lua plugin file is loaded into its own Lua state, the necessary functions are extracted and stored in the struct.
+    /// Each WebAssembly plugin is instantiated in its own store, see `WasmPlugin`.
+    /// A plugin may also declare the optional `line_directive` template string.
     ///
-    /// The `lua` parameter must stay alive as long as the `PluginsCaller` instance is used.
-    pub fn new(lua: Rc<Lua>, dir: &Path) -> Result<Self, LPError> {
-        let mut plugin_import_functions = HashMap::new();
-        let mut plugin_cleaning_functions = HashMap::new();
-
-        let entries = match fs::read_dir(dir) {
-            Ok(e) => e,
-            Err(_) => {
-                println!("No plugins found in {}", dir.display());
-                return Ok(PluginsCaller {
-                    plugin_import_functions,
-                    plugin_cleaning_functions,
-                });
-            }
-        };
-
-        for entry in entries {
-            let entry = entry.map_err(|_| LPError::CannotReadFile(dir.display().to_string()))?;
-            let path = entry.path();
-
-            if path.is_file() {
-                if let Some(ext) = path.extension() {
-                    if ext == PLUGIN_EXTENSION {
-                        let filename = path
-                            .file_stem()
-                            .unwrap_or_default()
-                            .to_string_lossy()
-                            .to_string();
-
-                        println!("Loading plugin: {}", filename);
+    /// The plugins' manifests are validated and their capabilities are printed,
+    /// an incompatible plugin or two plugins declaring the same extension are errors.
+    ///
+    /// `settings` maps the files extensions to the plugins and hold the options passed to the plugins' functions.
+    ///
+    /// The plugins get read-only access to the `index` through the `lp` library.
+    pub fn new(
+        dir: &Path,
+        settings: &PluginsSettings,
+        index: Rc<ProjectIndex>,
+    ) -> Result<Self, LPError> {
+        let mut plugins: HashMap<String, Box<dyn LanguagePlugin>> = HashMap::new();
+        let log = PluginLog::default();
+        if settings.builtin {
+            plugins.extend(builtin_plugins());
+        }
 
-                        let plugin_funcs = get_plugin_funcs(
-                            &lua,
+        let mut loaded_names = vec![];
+        match fs::read_dir(dir) {
+            Ok(entries) => {
+                for entry in entries {
+                    let entry =
+                        entry.map_err(|_| LPError::CannotReadFile(dir.display().to_string()))?;
+                    let path = entry.path();
+
+                    let Some(ext) = path.extension().filter(|_| path.is_file()) else {
+                        continue;
+                    };
+                    let filename = path
+                        .file_stem()
+                        .unwrap_or_default()
+                        .to_string_lossy()
+                        .to_string();
+
+                    let plugin: Box<dyn LanguagePlugin> = if ext == PLUGIN_EXTENSION {
+                        Box::new(LuaPlugin::load(
+                            &filename,
                             &path,
-                            vec![
-                                PLUGIN_IMPORT_CODE_FUNC_NAME,
-                                PLUGIN_CLEANIONG_CODE_FUNC_NAME,
-                            ],
-                        )?;
-
-                        plugin_import_functions.insert(filename.clone(), plugin_funcs[0].clone());
-                        plugin_cleaning_functions.insert(filename.clone(), plugin_funcs[1].clone());
+                            settings,
+                            Rc::clone(&index),
+                            log.clone(),
+                        )?)
+                    } else if ext == WASM_PLUGIN_EXTENSION {
+                        Box::new(WasmPlugin::load(&filename, &path, &settings.sandbox)?)
+                    } else {
+                        continue;
+                    };
+                    if loaded_names.contains(&filename) {
+                        return Err(LPError::InvalidPlugin {
+                            plugin: filename,
+                            message: "defined by several files".to_string(),
+                        });
                     }
+                    eprintln!("Loading plugin: {} ({})", filename, plugin.manifest());
+                    plugins.insert(filename.clone(), plugin);
+                    loaded_names.push(filename);
+                }
+            }
+            Err(_) => eprintln!("No plugins found in {}", dir.display()),
+        }
+
+        loaded_names.sort();
+        let mut aliases: HashMap<String, String> = HashMap::new();
+        for name in &loaded_names {
+            for extension in &plugins[name].manifest().extensions {
+                if let Some(other) = aliases.insert(extension.clone(), name.clone()) {
+                    return Err(LPError::InvalidPlugin {
+                        plugin: name.clone(),
+                        message: format!(
+                            "the extension {} is also handled by {}",
+                            extension, other
+                        ),
+                    });
+                }
+            }
+        }
+        // the extensions of the built-in plugins don't take over the ones of the loaded plugins
+        for (name, plugin) in &plugins {
+            if !loaded_names.contains(name) {
+                for extension in &plugin.manifest().extensions {
+                    aliases
+                        .entry(extension.clone())
+                        .or_insert_with(|| name.clone());
                 }
             }
         }
 
         Ok(PluginsCaller {
-            plugin_import_functions,
-            plugin_cleaning_functions,
+            plugins,
+            settings: settings.clone(),
+            aliases,
+            log,
         })
     }
 
-    /// Each plugin correspons to some files extension.
+    /// Returns the name of the plugin used for the files with the given extension.
+    ///
+    /// The mapping in the settings comes first, then the plugin named as the extension,
+    /// then the plugin declaring the extension in its manifest.
+    pub fn plugin_name<'a>(&'a self, extension: &'a str) -> &'a str {
+        if let Some(name) = self.settings.extensions.get(extension) {
+            return name;
+        }
+        if self.plugins.contains_key(extension) {
+            return extension;
+        }
+        self.aliases
+            .get(extension)
+            .map(String::as_str)
+            .unwrap_or(extension)
+    }
+
+    /// Returns the fingerprint of the plugin for the given extension together with its options,
+    /// empty if there is no such plugin
+    pub fn plugin_fingerprint(&self, extension: &str) -> Vec<u8> {
+        let Ok(plugin) = self.get_plugin(extension) else {
+            return vec![];
+        };
+        let mut fingerprint = plugin.fingerprint().to_vec();
+        if let Some(options) = self.settings.options.get(self.plugin_name(extension)) {
+            fingerprint.extend(serde_yaml::to_string(options).unwrap_or_default().bytes());
+        }
+        fingerprint
+    }
+
+    /// Returns the messages logged by the plugins since the previous call
+    pub fn take_messages(&self) -> Vec<PluginMessage> {
+        self.log.take()
+    }
+
+    /// Returns the plugin used for the files with the given extension
+    fn get_plugin(&self, extension: &str) -> Result<&dyn LanguagePlugin, LPError> {
+        self.plugins
+            .get(self.plugin_name(extension))
+            .map(Box::as_ref)
+            .ok_or_else(|| LPError::PluginNotFound(extension.to_string()))
+    }
+
+    /// Returns the plugin used for the files with the given extension,
+    /// None if there is no such plugin and the modules without plugins are built as is
+    fn get_plugin_or_fallback(
+        &self,
+        extension: &str,
+    ) -> Result<Option<&dyn LanguagePlugin>, LPError> {
+        match self.get_plugin(extension) {
+            Ok(plugin) => Ok(Some(plugin)),
+            Err(_) if self.settings.fallback == Fallback::Raw => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Returns the line directive template declared by the plugin for the given extension, if any.
+    ///
+    /// `{line}` and `{file}` in the template are replaced with the line number and the file path,
+    /// e.g. `#line {line} "{file}"` for C.
+    pub fn get_line_directive(&self, extension: &str) -> Option<&str> {
+        self.get_plugin(extension).ok()?.line_directive()
+    }
+
+    /// Returns the pattern of the lines of the file's header declared by the plugin for the given extension, if any.
+    ///
+    /// The imports placed `after_header` are put after the leading lines matching it, e.g. `^(#!|//|package )` for Go.
+    pub fn get_header_pattern(&self, extension: &str) -> Option<&Regex> {
+        self.get_plugin(extension).ok()?.header_pattern()
+    }
+
+    /// Each plugin correspons to some files extension, unless it's mapped to another plugin in the settings.
     /// This function calls the function to import code of the plugin that corresponds to the given extension.
     /// Other parameters are passed to the plugin's function.
     ///
@@ -85,32 +216,64 @@ impl PluginsCaller {
     /// Both of them should have the same extension
     ///
     /// `code_block` - the code block that should be imported
+    ///
+    /// Nothing is imported if there is no such plugin and the fallback is `raw`
     pub fn call_plugin_import_func(
         &self,
         extension: &str,
         current_path: &Path,
         referenced_path: &Path,
         code_block: &str,
-    ) -> Result<String, LPError> {
-        if let Some(plugin_func) = self.plugin_import_functions.get(extension) {
-            let current_path_str = current_path.to_string_lossy();
-            let referenced_path_str = referenced_path.to_string_lossy();
-
-            let result: String = plugin_func
-                .call((
-                    current_path_str.as_ref(),
-                    referenced_path_str.as_ref(),
-                    code_block,
-                ))
-                .map_err(|e| LPError::LuaRuntime(e.to_string()))?;
-
-            Ok(result)
-        } else {
-            Err(LPError::PluginNotFound(extension.to_string()))
+    ) -> Result<ImportCode, LPError> {
+        match self.get_plugin_or_fallback(extension)? {
+            Some(plugin) => plugin.get_import_code(current_path, referenced_path, code_block),
+            None => Ok(ImportCode::default()),
         }
     }
 
-    /// Each plugin correspons to some files extension.
+    /// Calls the optional `get_exports` function of the plugin that corresponds to the given extension.
+    ///
+    /// `module_path` - path to the module the section belongs to
+    ///
+    /// `code_block` - the code of the section
+    ///
+    /// Returns None if there is no such plugin or it doesn't declare the function
+    pub fn call_plugin_exports_func(
+        &self,
+        extension: &str,
+        module_path: &Path,
+        code_block: &str,
+    ) -> Result<Option<Vec<String>>, LPError> {
+        match self.get_plugin(extension) {
+            Ok(plugin) => plugin.get_exports(module_path, code_block),
+            Err(_) => Ok(None),
+        }
+    }
+
+    /// Calls the optional `render_section_docs` function of the plugin that corresponds to the given extension.
+    ///
+    /// `module_path` - path to the module the section belongs to
+    ///
+    /// `header` - the section's header as written, e.g. `# Main`, if any
+    ///
+    /// `docs` and `code` - the section's docs, with the references already pointing to the docs pages, and its code
+    ///
+    /// Returns None if there is no such plugin, it doesn't declare the function or keeps the default rendering
+    pub fn call_plugin_render_docs_func(
+        &self,
+        extension: &str,
+        module_path: &Path,
+        header: Option<&str>,
+        docs: &str,
+        code: &str,
+    ) -> Result<Option<String>, LPError> {
+        match self.get_plugin(extension) {
+            Ok(plugin) => plugin.render_section_docs(module_path, header, docs, code),
+            Err(_) => Ok(None),
+        }
+    }
+
+    /// Each plugin correspons to some files extension, unless it's mapped to another plugin in the settings.
     /// This function calls the function to clean code of the plugin that corresponds to the given extension.
     /// Other parameters are passed to the plugin's function.
     ///
@@ -118,30 +281,72 @@ impl PluginsCaller {
     ///
     /// `code` - code to clean
     ///
-    /// Returns cleaned code
+    /// Returns cleaned code, the code is returned as is if there is no such plugin and the fallback is `raw`
     pub fn call_plugin_cleaning_func(
         &self,
         extension: &str,
         code: &str,
     ) -> Result<String, LPError> {
-        if let Some(plugin_func) = self.plugin_cleaning_functions.get(extension) {
-            let result: String = plugin_func
-                .call(code)
-                .map_err(|e| LPError::LuaRuntime(e.to_string()))?;
-
-            Ok(result)
-        } else {
-            Err(LPError::PluginNotFound(extension.to_string()))
+        match self.get_plugin_or_fallback(extension)? {
+            Some(plugin) => plugin.clean_code(code),
+            None => Ok(code.to_string()),
+        }
+    }
+
+    /// Calls the `before_build` or `after_build` hook of every plugin declaring it, in the order of the plugins' names.
+    ///
+    /// `modules` - all the modules of the project with their outputs
+    ///
+    /// Returns the files the plugins generated together with the names of the plugins
+    pub fn call_build_hook(
+        &self,
+        hook: BuildHook,
+        modules: &[BuildOutput],
+    ) -> Result<Vec<(String, GeneratedFile)>, LPError> {
+        let mut names: Vec<&String> = self.plugins.keys().collect();
+        names.sort();
+        let mut files = vec![];
+        for name in names {
+            for file in self.plugins[name].call_build_hook(hook, modules)? {
+                files.push((name.clone(), file));
+            }
         }
+        Ok(files)
+    }
+
+    /// Calls the `after_module` hook of the plugin that corresponds to the module's extension, if any.
+    ///
+    /// `code` - the content of the built file
+    ///
+    /// Returns the files the plugin generated together with the plugin's name
+    pub fn call_after_module_hook(
+        &self,
+        module: &BuildOutput,
+        code: &str,
+    ) -> Result<Vec<(String, GeneratedFile)>, LPError> {
+        let Ok(plugin) = self.get_plugin(&module.extension) else {
+            return Ok(vec![]);
+        };
+        let name = self.plugin_name(&module.extension).to_string();
+        Ok(plugin
+            .after_module(module, code)?
+            .into_iter()
+            .map(|file| (name.clone(), file))
+            .collect())
     }
 }
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::builds::{code::plugins::import_code::Placement, spec::structs::Project};
     use std::io::Write;
     use std::path::PathBuf;
     use tempfile::TempDir;
 
+    fn empty_index() -> Rc<ProjectIndex> {
+        Rc::new(ProjectIndex::new(Rc::new(Project { modules: vec![] })))
+    }
+
     fn create_temp_plugin(dir: &Path, filename: &str, content: &str) -> PathBuf {
         let file_path = dir.join(format!("{}.lua", filename));
         let mut file = std::fs::File::create(&file_path).expect("Failed to create temp file");
@@ -152,7 +357,6 @@ mod tests {
 
     #[test]
     fn test_new_with_valid_plugins() {
-        let lua = Rc::new(Lua::new());
         let temp_dir = TempDir::new().expect("Failed to create temp dir");
 
         let plugin_code = r#"
@@ -167,38 +371,267 @@ mod tests {
 
         create_temp_plugin(temp_dir.path(), "test_plugin", plugin_code);
 
-        let result = PluginsCaller::new(Rc::clone(&lua), temp_dir.path());
+        let result =
+            PluginsCaller::new(temp_dir.path(), &PluginsSettings::default(), empty_index());
         assert!(result.is_ok());
 
         let caller = result.unwrap();
-        assert_eq!(caller.plugin_import_functions.len(), 1);
-        assert_eq!(caller.plugin_cleaning_functions.len(), 1);
-        assert!(caller.plugin_import_functions.contains_key("test_plugin"));
-        assert!(caller.plugin_cleaning_functions.contains_key("test_plugin"));
+        assert_eq!(caller.plugins.len(), 1);
+        assert!(caller.plugins.contains_key("test_plugin"));
+    }
+
+    #[test]
+    fn test_settings() {
+        let temp_dir = TempDir::new().expect("Failed to create temp dir");
+
+        let plugin_code = r#"
+        function get_import_code(current_path, referenced_path, code_block, options)
+            return options.prefix .. referenced_path
+        end
+
+        function clean_code(code, options)
+            return code .. " " .. #options.suffixes
+        end
+        "#;
+        create_temp_plugin(temp_dir.path(), "c", plugin_code);
+
+        let settings: PluginsSettings = serde_yaml::from_str(
+            "extensions:\n  h: c\noptions:\n  c:\n    prefix: '#include '\n    suffixes: [a, b]\n",
+        )
+        .unwrap();
+        let caller = PluginsCaller::new(temp_dir.path(), &settings, empty_index()).unwrap();
+
+        let result = caller
+            .call_plugin_import_func("h", Path::new("a.h"), Path::new("b.h"), "")
+            .unwrap();
+        assert_eq!(result.imports, vec!["#include b.h"]);
+        assert_eq!(caller.call_plugin_cleaning_func("c", "x").unwrap(), "x 2");
+        assert!(matches!(
+            caller.call_plugin_cleaning_func("py", "x"),
+            Err(LPError::PluginNotFound(_))
+        ));
+    }
+
+    #[test]
+    fn test_plugins_are_isolated() {
+        let temp_dir = TempDir::new().expect("Failed to create temp dir");
+
+        for (name, prefix) in [("py", "from"), ("js", "import")] {
+            let plugin_code = format!(
+                r#"
+        counter = 0
+        function helper(path)
+            counter = counter + 1
+            return "{} " .. path
+        end
+
+        function get_import_code(current_path, referenced_path, code_block)
+            return helper(referenced_path)
+        end
+
+        function clean_code(code)
+            return code .. " " .. counter
+        end
+        "#,
+                prefix
+            );
+            create_temp_plugin(temp_dir.path(), name, &plugin_code);
+        }
+
+        let caller =
+            PluginsCaller::new(temp_dir.path(), &PluginsSettings::default(), empty_index())
+                .unwrap();
+        let import = |extension: &str| {
+            caller
+                .call_plugin_import_func(extension, Path::new("a"), Path::new("b"), "")
+                .unwrap()
+                .imports
+                .join("\n")
+        };
+        assert_eq!(import("py"), "from b");
+        assert_eq!(import("js"), "import b");
+        assert_eq!(import("js"), "import b");
+        assert_eq!(caller.call_plugin_cleaning_func("py", "x").unwrap(), "x 1");
+        assert_eq!(caller.call_plugin_cleaning_func("js", "x").unwrap(), "x 2");
+    }
+
+    #[test]
+    fn test_sandbox_aborts_runaway_call() {
+        let temp_dir = TempDir::new().expect("Failed to create temp dir");
+
+        let plugin_code = r#"
+        function get_import_code(current_path, referenced_path, code_block)
+            while true do end
+        end
+
+        function clean_code(code)
+            return code
+        end
+        "#;
+        create_temp_plugin(temp_dir.path(), "py", plugin_code);
+
+        let settings: PluginsSettings =
+            serde_yaml::from_str("sandbox:\n  instruction_limit: 100000\n").unwrap();
+        let caller = PluginsCaller::new(temp_dir.path(), &settings, empty_index()).unwrap();
+
+        match caller.call_plugin_import_func("py", Path::new("a"), Path::new("b"), "") {
+            Err(LPError::PluginFailed(failure)) => {
+                assert_eq!(failure.plugin, "py");
+                assert_eq!(failure.hook, "get_import_code");
+                assert!(
+                    failure.message.contains("instruction limit"),
+                    "{}",
+                    failure.message
+                );
+            }
+            _ => panic!("Expected PluginFailed error"),
+        }
+        assert_eq!(caller.call_plugin_cleaning_func("py", "x").unwrap(), "x");
+    }
+
+    #[test]
+    fn test_exports_func() {
+        let temp_dir = TempDir::new().expect("Failed to create temp dir");
+
+        let plugin_code = r#"
+        function get_exports(module_path, code_block)
+            local names = {}
+            for _, captures in ipairs(lp.captures_all("(?m)^def (\\w+)", code_block)) do
+                table.insert(names, captures[1])
+            end
+            return names
+        end
+
+        function get_import_code(current_path, referenced_path, code_block)
+            return ""
+        end
+
+        function clean_code(code)
+            return code
+        end
+        "#;
+        let other_plugin_code = r#"
+        function get_import_code(current_path, referenced_path, code_block)
+            return ""
+        end
+
+        function clean_code(code)
+            return code
+        end
+        "#;
+        create_temp_plugin(temp_dir.path(), "py", plugin_code);
+        create_temp_plugin(temp_dir.path(), "js", other_plugin_code);
+
+        let caller =
+            PluginsCaller::new(temp_dir.path(), &PluginsSettings::default(), empty_index())
+                .unwrap();
+        let exports = |extension: &str| {
+            caller
+                .call_plugin_exports_func(extension, Path::new("a"), "def f():\ndef g():")
+                .unwrap()
+        };
+        assert_eq!(exports("py"), Some(vec!["f".to_string(), "g".to_string()]));
+        assert_eq!(exports("js"), None);
+        assert_eq!(exports("rs"), None);
+    }
+
+    #[test]
+    fn test_structured_import_code() {
+        let temp_dir = TempDir::new().expect("Failed to create temp dir");
+
+        let plugin_code = r#"
+        header_pattern = "^(#!|//|package )"
+
+        function get_import_code(current_path, referenced_path, code_block)
+            return { imports = { 'import "' .. referenced_path .. '"' }, exports = { "F" }, placement = "after_header" }
+        end
+
+        function clean_code(code)
+            return code
+        end
+        "#;
+        create_temp_plugin(temp_dir.path(), "go", plugin_code);
+        create_temp_plugin(temp_dir.path(), "bad", "header_pattern = '('");
+
+        let caller =
+            PluginsCaller::new(temp_dir.path(), &PluginsSettings::default(), empty_index());
+        assert!(matches!(caller, Err(LPError::LuaRuntime(_))));
+
+        std::fs::remove_file(temp_dir.path().join("bad.lua")).unwrap();
+        let caller =
+            PluginsCaller::new(temp_dir.path(), &PluginsSettings::default(), empty_index())
+                .unwrap();
+        let import = caller
+            .call_plugin_import_func("go", Path::new("a.go"), Path::new("b.go"), "")
+            .unwrap();
+        assert_eq!(import.imports, vec!["import \"b.go\""]);
+        assert_eq!(import.exports, vec!["F"]);
+        assert_eq!(import.placement, Placement::AfterHeader);
+        assert!(caller
+            .get_header_pattern("go")
+            .unwrap()
+            .is_match("package main"));
+    }
+
+    #[test]
+    fn test_line_directive() {
+        let temp_dir = TempDir::new().expect("Failed to create temp dir");
+
+        let plugin_code = r#"
+        line_directive = '#line {line} "{file}"'
+
+        function get_import_code(current_path, referenced_path, code_block)
+            return ""
+        end
+
+        function clean_code(code)
+            return code
+        end
+        "#;
+        let other_plugin_code = r#"
+        function get_import_code(current_path, referenced_path, code_block)
+            return ""
+        end
+
+        function clean_code(code)
+            return code
+        end
+        "#;
+
+        create_temp_plugin(temp_dir.path(), "c", plugin_code);
+        create_temp_plugin(temp_dir.path(), "py", other_plugin_code);
+
+        let caller =
+            PluginsCaller::new(temp_dir.path(), &PluginsSettings::default(), empty_index())
+                .unwrap();
+        assert_eq!(
+            caller.get_line_directive("c"),
+            Some("#line {line} \"{file}\"")
+        );
+        assert_eq!(caller.get_line_directive("py"), None);
     }
 
     #[test]
     fn test_new_with_empty_directory() {
-        let lua = Rc::new(Lua::new());
         let temp_dir = TempDir::new().expect("Failed to create temp dir");
 
-        let result = PluginsCaller::new(Rc::clone(&lua), temp_dir.path());
+        let result =
+            PluginsCaller::new(temp_dir.path(), &PluginsSettings::default(), empty_index());
         assert!(result.is_ok());
 
         let caller = result.unwrap();
-        assert_eq!(caller.plugin_import_functions.len(), 0);
-        assert_eq!(caller.plugin_cleaning_functions.len(), 0);
+        assert_eq!(caller.plugins.len(), 0);
     }
 
     #[test]
     fn test_new_with_invalid_plugin() {
-        let lua = Rc::new(Lua::new());
         let temp_dir = TempDir::new().expect("Failed to create temp dir");
 
         let invalid_plugin_code = "garbage code that will fail";
         create_temp_plugin(temp_dir.path(), "invalid_plugin", invalid_plugin_code);
 
-        let result = PluginsCaller::new(Rc::clone(&lua), temp_dir.path());
+        let result =
+            PluginsCaller::new(temp_dir.path(), &PluginsSettings::default(), empty_index());
         assert!(result.is_err());
 
         match result {
@@ -209,7 +642,6 @@ mod tests {
 
     #[test]
     fn test_call_plugin_import_func_success() {
-        let lua = Rc::new(Lua::new());
         let temp_dir = TempDir::new().expect("Failed to create temp dir");
 
         let plugin_code = r#"
@@ -224,7 +656,9 @@ mod tests {
 
         create_temp_plugin(temp_dir.path(), "rs", plugin_code);
 
-        let caller = PluginsCaller::new(Rc::clone(&lua), temp_dir.path()).unwrap();
+        let caller =
+            PluginsCaller::new(temp_dir.path(), &PluginsSettings::default(), empty_index())
+                .unwrap();
 
         let current_path = PathBuf::from("/path/to/current.rs");
         let referenced_path = PathBuf::from("/path/to/referenced.rs");
@@ -234,7 +668,7 @@ mod tests {
             caller.call_plugin_import_func("rs", &current_path, &referenced_path, code_block);
         assert!(result.is_ok());
 
-        let import_code = result.unwrap();
+        let import_code = result.unwrap().imports.join("\n");
         assert!(import_code.contains("import"));
         assert!(import_code.contains("/path/to/current.rs"));
         assert!(import_code.contains("/path/to/referenced.rs"));
@@ -243,7 +677,6 @@ mod tests {
 
     #[test]
     fn test_call_plugin_import_func_not_found() {
-        let lua = Rc::new(Lua::new());
         let temp_dir = TempDir::new().expect("Failed to create temp dir");
 
         let plugin_code = r#"
@@ -258,7 +691,9 @@ mod tests {
 
         create_temp_plugin(temp_dir.path(), "rust", plugin_code);
 
-        let caller = PluginsCaller::new(Rc::clone(&lua), temp_dir.path()).unwrap();
+        let caller =
+            PluginsCaller::new(temp_dir.path(), &PluginsSettings::default(), empty_index())
+                .unwrap();
 
         let result = caller.call_plugin_import_func(
             "python",
@@ -276,7 +711,6 @@ mod tests {
 
     #[test]
     fn test_call_plugin_cleaning_func_success() {
-        let lua = Rc::new(Lua::new());
         let temp_dir = TempDir::new().expect("Failed to create temp dir");
 
         let plugin_code = r#"
@@ -291,7 +725,9 @@ mod tests {
 
         create_temp_plugin(temp_dir.path(), "rust", plugin_code);
 
-        let caller = PluginsCaller::new(Rc::clone(&lua), temp_dir.path()).unwrap();
+        let caller =
+            PluginsCaller::new(temp_dir.path(), &PluginsSettings::default(), empty_index())
+                .unwrap();
 
         let result = caller.call_plugin_cleaning_func("rust", "fn main() {}");
         assert!(result.is_ok());
@@ -300,7 +736,6 @@ mod tests {
 
     #[test]
     fn test_call_plugin_cleaning_func_not_found() {
-        let lua = Rc::new(Lua::new());
         let temp_dir = TempDir::new().expect("Failed to create temp dir");
 
         let plugin_code = r#"
@@ -315,7 +750,9 @@ mod tests {
 
         create_temp_plugin(temp_dir.path(), "rust", plugin_code);
 
-        let caller = PluginsCaller::new(Rc::clone(&lua), temp_dir.path()).unwrap();
+        let caller =
+            PluginsCaller::new(temp_dir.path(), &PluginsSettings::default(), empty_index())
+                .unwrap();
 
         let result = caller.call_plugin_cleaning_func("py", "def main(): pass");
         assert!(result.is_err());
@@ -326,9 +763,189 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_manifest_extensions() {
+        let temp_dir = TempDir::new().expect("Failed to create temp dir");
+
+        let plugin_code = |name: &str, extensions: &str| {
+            format!(
+                r#"
+        function get_import_code(current_path, referenced_path, code_block)
+            return ""
+        end
+
+        function clean_code(code)
+            return "{}"
+        end
+
+        return {{ extensions = {{ {} }} }}
+        "#,
+                name, extensions
+            )
+        };
+        create_temp_plugin(temp_dir.path(), "js", &plugin_code("js", "'mjs', 'cjs'"));
+        create_temp_plugin(temp_dir.path(), "ts", &plugin_code("ts", "'tsx'"));
+
+        let settings: PluginsSettings = serde_yaml::from_str("extensions:\n  cjs: ts\n").unwrap();
+        let caller = PluginsCaller::new(temp_dir.path(), &settings, empty_index()).unwrap();
+        let clean = |extension: &str| caller.call_plugin_cleaning_func(extension, "").unwrap();
+        assert_eq!(clean("mjs"), "js");
+        assert_eq!(clean("tsx"), "ts");
+        assert_eq!(clean("cjs"), "ts");
+        assert_eq!(caller.plugin_name("tsx"), "ts");
+
+        create_temp_plugin(temp_dir.path(), "es", &plugin_code("es", "'mjs'"));
+        assert!(matches!(
+            PluginsCaller::new(temp_dir.path(), &settings, empty_index()),
+            Err(LPError::InvalidPlugin { .. })
+        ));
+    }
+
+    #[test]
+    fn test_raw_fallback() {
+        let temp_dir = TempDir::new().expect("Failed to create temp dir");
+
+        let settings: PluginsSettings = serde_yaml::from_str("fallback: raw\n").unwrap();
+        let caller = PluginsCaller::new(temp_dir.path(), &settings, empty_index()).unwrap();
+
+        let import = caller
+            .call_plugin_import_func("Makefile", Path::new("Makefile"), Path::new("a.mk"), "all:")
+            .unwrap();
+        assert!(import.imports.is_empty());
+        assert_eq!(
+            caller
+                .call_plugin_cleaning_func("Makefile", "all:")
+                .unwrap(),
+            "all:"
+        );
+    }
+
+    #[test]
+    fn test_builtin_plugins() {
+        let temp_dir = TempDir::new().expect("Failed to create temp dir");
+        create_temp_plugin(
+            temp_dir.path(),
+            "js",
+            r#"
+        function get_import_code(current_path, referenced_path, code_block)
+            return "lua import"
+        end
+
+        function clean_code(code)
+            return code
+        end
+        "#,
+        );
+
+        let settings: PluginsSettings = serde_yaml::from_str("builtin: true\n").unwrap();
+        let caller = PluginsCaller::new(temp_dir.path(), &settings, empty_index()).unwrap();
+
+        let import = caller
+            .call_plugin_import_func(
+                "pyw",
+                Path::new("main.pyw"),
+                Path::new("lib/util.py"),
+                "def f():\n    pass",
+            )
+            .unwrap();
+        assert_eq!(import.imports, vec!["from lib.util import f"]);
+        assert!(caller.get_header_pattern("py").is_some());
+
+        let import = caller
+            .call_plugin_import_func("js", Path::new("a.js"), Path::new("b.js"), "")
+            .unwrap();
+        assert_eq!(import.imports, vec!["lua import"]);
+
+        let caller =
+            PluginsCaller::new(temp_dir.path(), &PluginsSettings::default(), empty_index())
+                .unwrap();
+        assert!(matches!(
+            caller.call_plugin_cleaning_func("py", ""),
+            Err(LPError::PluginNotFound(_))
+        ));
+        let caller =
+            PluginsCaller::new(&temp_dir.path().join("missing"), &settings, empty_index()).unwrap();
+        assert_eq!(caller.plugin_name("mjs"), "js");
+    }
+
+    #[test]
+    fn test_lifecycle_hooks() {
+        let temp_dir = TempDir::new().expect("Failed to create temp dir");
+        create_temp_plugin(
+            temp_dir.path(),
+            "js",
+            r#"
+        function get_import_code() return "" end
+        function clean_code(code) return code end
+
+        function before_build(modules)
+            return { ["modules.txt"] = tostring(#modules) }
+        end
+
+        function after_module(module, code)
+            return { [module.output .. ".size"] = tostring(#code) }
+        end
+
+        function after_build(modules)
+            local lines = {}
+            for _, module in ipairs(modules) do
+                if module.extension == "js" then
+                    table.insert(lines, "require('./" .. module.output .. "');")
+                end
+            end
+            return { ["index.js"] = table.concat(lines, "\n") }
+        end
+        "#,
+        );
+        create_temp_plugin(
+            temp_dir.path(),
+            "py",
+            r#"
+        function get_import_code() return "" end
+        function clean_code(code) return code end
+        function after_module() return { ["../outside.py"] = "" } end
+        "#,
+        );
+        let caller =
+            PluginsCaller::new(temp_dir.path(), &PluginsSettings::default(), empty_index())
+                .unwrap();
+
+        let module = |output: &str, extension: &str| BuildOutput {
+            module: PathBuf::from(format!("{}.lpnb", output)),
+            output: PathBuf::from(output),
+            extension: extension.to_string(),
+        };
+        let modules = vec![module("app.js", "js"), module("main.py", "py")];
+
+        let files = caller
+            .call_build_hook(BuildHook::BeforeBuild, &modules)
+            .unwrap();
+        assert_eq!(files.len(), 1);
+        assert_eq!(files[0].0, "js");
+        assert_eq!(files[0].1.content, "2");
+
+        let files = caller
+            .call_after_module_hook(&modules[0], "let a;")
+            .unwrap();
+        assert_eq!(files[0].1.path, PathBuf::from("app.js.size"));
+        assert_eq!(files[0].1.content, "6");
+        match caller.call_after_module_hook(&modules[1], "") {
+            Err(LPError::PluginFailed(failure)) => {
+                assert_eq!(failure.plugin, "py");
+                assert_eq!(failure.hook, "after_module");
+            }
+            _ => panic!("Expected PluginFailed error"),
+        }
+
+        let files = caller
+            .call_build_hook(BuildHook::AfterBuild, &modules)
+            .unwrap();
+        assert_eq!(files[0].1.path, PathBuf::from("index.js"));
+        assert_eq!(files[0].1.content, "require('./app.js');");
+    }
+
     #[test]
     fn test_multiple_plugins() {
-        let lua = Rc::new(Lua::new());
         let temp_dir = TempDir::new().expect("Failed to create temp dir");
 
         create_temp_plugin(
@@ -359,10 +976,11 @@ mod tests {
         "#,
         );
 
-        let caller = PluginsCaller::new(Rc::clone(&lua), temp_dir.path()).unwrap();
+        let caller =
+            PluginsCaller::new(temp_dir.path(), &PluginsSettings::default(), empty_index())
+                .unwrap();
 
-        assert_eq!(caller.plugin_import_functions.len(), 2);
-        assert_eq!(caller.plugin_cleaning_functions.len(), 2);
+        assert_eq!(caller.plugins.len(), 2);
 
         let rust_import = caller
             .call_plugin_import_func(
@@ -373,7 +991,7 @@ mod tests {
             )
             .unwrap();
 
-        assert_eq!(rust_import, "rust import: fn main() {}");
+        assert_eq!(rust_import.imports, vec!["rust import: fn main() {}"]);
 
         let python_cleaned = caller
             .call_plugin_cleaning_func("python", "def main(): pass")
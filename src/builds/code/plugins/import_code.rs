@@ -0,0 +1,108 @@
+#![forbid(unsafe_code)]
+
+use mlua::{FromLua, Lua, Value};
+
+/// Where the imports are put in the built file
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum Placement {
+    /// before everything else, as it was the only option before
+    #[default]
+    Top,
+    /// after the file's header, e.g. a shebang, a license comment or a `package` declaration,
+    /// see `PluginsCaller::get_header_pattern`
+    AfterHeader,
+}
+
+/// What the plugin's `get_import_code` returned.
+///
+/// It's either a plain string, which is a single import placed at the top of the file,
+/// or a table `{ imports = {...}, exports = {...}, placement = "top" | "after_header" }`, where every field is optional.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ImportCode {
+    /// the import statements, the code builder deduplicates them
+    pub imports: Vec<String>,
+    /// the names the referenced section exports, they are listed in its documentation
+    pub exports: Vec<String>,
+    pub placement: Placement,
+}
+
+impl FromLua for ImportCode {
+    fn from_lua(value: Value, lua: &Lua) -> mlua::Result<Self> {
+        match value {
+            Value::Nil => Ok(ImportCode::default()),
+            Value::Table(table) => {
+                let placement = match table.get::<Option<String>>("placement")?.as_deref() {
+                    None | Some("top") => Placement::Top,
+                    Some("after_header") => Placement::AfterHeader,
+                    Some(other) => {
+                        return Err(mlua::Error::runtime(format!(
+                            "unknown placement \"{}\", expected \"top\" or \"after_header\"",
+                            other
+                        )))
+                    }
+                };
+                Ok(ImportCode {
+                    imports: table
+                        .get::<Option<Vec<String>>>("imports")?
+                        .unwrap_or_default(),
+                    exports: table
+                        .get::<Option<Vec<String>>>("exports")?
+                        .unwrap_or_default(),
+                    placement,
+                })
+            }
+            value => {
+                let import = String::from_lua(value, lua)?;
+                Ok(ImportCode {
+                    imports: if import.is_empty() {
+                        vec![]
+                    } else {
+                        vec![import]
+                    },
+                    ..ImportCode::default()
+                })
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn eval(code: &str) -> mlua::Result<ImportCode> {
+        Lua::new().load(code).eval()
+    }
+
+    #[test]
+    fn test_plain_string() {
+        assert_eq!(
+            eval("return 'import a'").unwrap(),
+            ImportCode {
+                imports: vec!["import a".to_string()],
+                ..ImportCode::default()
+            }
+        );
+        assert_eq!(eval("return ''").unwrap(), ImportCode::default());
+    }
+
+    #[test]
+    fn test_table() {
+        assert_eq!(
+            eval("return { imports = { 'import a' }, exports = { 'f', 'g' }, placement = 'after_header' }")
+                .unwrap(),
+            ImportCode {
+                imports: vec!["import a".to_string()],
+                exports: vec!["f".to_string(), "g".to_string()],
+                placement: Placement::AfterHeader,
+            }
+        );
+        assert_eq!(eval("return {}").unwrap(), ImportCode::default());
+    }
+
+    #[test]
+    fn test_unknown_placement() {
+        let error = eval("return { placement = 'bottom' }").unwrap_err();
+        assert!(error.to_string().contains("unknown placement"), "{}", error);
+    }
+}
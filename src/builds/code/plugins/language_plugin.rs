@@ -0,0 +1,89 @@
+#![forbid(unsafe_code)]
+
+use std::path::Path;
+
+use regex::Regex;
+
+use crate::error::LPError;
+
+use super::{
+    import_code::ImportCode,
+    lifecycle::{BuildHook, BuildOutput, GeneratedFile},
+    manifest::Manifest,
+};
+
+/// Support of a language by the build system, `PluginsCaller` dispatches through it.
+///
+/// It's implemented by the Lua plugins from the plugins directory and by the built-in plugins.
+pub trait LanguagePlugin {
+    /// Returns the plugin's manifest, either declared or inferred
+    fn manifest(&self) -> &Manifest;
+
+    /// Returns what the plugin's outputs depend on besides its options, e.g. the plugin's file,
+    /// the build cache rebuilds the modules when it changes
+    fn fingerprint(&self) -> &[u8];
+
+    /// Returns the code importing the section `code_block` of the module `referenced_path`
+    /// into the module `current_path`, see `ImportCode`
+    fn get_import_code(
+        &self,
+        current_path: &Path,
+        referenced_path: &Path,
+        code_block: &str,
+    ) -> Result<ImportCode, LPError>;
+
+    /// Returns the cleaned code of the whole module, e.g. with the duplicate imports removed
+    fn clean_code(&self, code: &str) -> Result<String, LPError>;
+
+    /// Returns the names the section `code_block` of the module `module_path` exports,
+    /// None if the plugin doesn't find them
+    fn get_exports(
+        &self,
+        _module_path: &Path,
+        _code_block: &str,
+    ) -> Result<Option<Vec<String>>, LPError> {
+        Ok(None)
+    }
+
+    /// Returns the markdown of the section's documentation page part: its docs and its code,
+    /// None keeps the default rendering, the docs followed by a fenced code block
+    fn render_section_docs(
+        &self,
+        _module_path: &Path,
+        _header: Option<&str>,
+        _docs: &str,
+        _code: &str,
+    ) -> Result<Option<String>, LPError> {
+        Ok(None)
+    }
+
+    /// Returns the line directive template, if the language has line directives
+    fn line_directive(&self) -> Option<&str> {
+        None
+    }
+
+    /// Returns the pattern of the lines of the file's header, if the language has one
+    fn header_pattern(&self) -> Option<&Regex> {
+        None
+    }
+
+    /// Calls the hook called once per build with all the modules of the project,
+    /// returns the files to write into the target code directory
+    fn call_build_hook(
+        &self,
+        _hook: BuildHook,
+        _modules: &[BuildOutput],
+    ) -> Result<Vec<GeneratedFile>, LPError> {
+        Ok(vec![])
+    }
+
+    /// Called after the module handled by the plugin is built, `code` is the content of the built file.
+    /// Returns the files to write into the target code directory
+    fn after_module(
+        &self,
+        _module: &BuildOutput,
+        _code: &str,
+    ) -> Result<Vec<GeneratedFile>, LPError> {
+        Ok(vec![])
+    }
+}
@@ -0,0 +1,404 @@
+#![forbid(unsafe_code)]
+
+use std::{
+    cell::RefCell,
+    collections::{HashMap, HashSet},
+    fmt,
+    path::{Path, PathBuf},
+    rc::Rc,
+};
+
+use mlua::{Lua, Table, Value};
+use regex::Regex;
+
+use crate::{
+    builds::{
+        index::{IndexRead, ProjectIndex},
+        spec::{structs::Module, utils},
+    },
+    error::LPError,
+};
+
+/// the name of the global table with the helpers
+const LIBRARY_NAME: &str = "lp";
+
+/// How many compiled patterns a plugin keeps, the cache is cleared when it's full
+const MAX_CACHED_REGEXES: usize = 64;
+
+/// How important a message logged by a plugin is
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MessageLevel {
+    Info,
+    /// warnings are reported as problems by the `check` subcommand
+    Warning,
+}
+
+/// A message logged by a plugin with `lp.log` or `lp.warn`
+#[derive(Debug, Clone, PartialEq)]
+pub struct PluginMessage {
+    pub plugin: String,
+    pub level: MessageLevel,
+    pub message: String,
+}
+
+impl fmt::Display for PluginMessage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let level = match self.level {
+            MessageLevel::Info => "info",
+            MessageLevel::Warning => "warning",
+        };
+        write!(f, "[{}] plugin {}: {}", level, self.plugin, self.message)
+    }
+}
+
+/// Messages logged by all the plugins during the build, in order
+#[derive(Clone, Default)]
+pub struct PluginLog(Rc<RefCell<Vec<PluginMessage>>>);
+
+impl PluginLog {
+    fn push(&self, plugin: &str, level: MessageLevel, message: String) {
+        self.0.borrow_mut().push(PluginMessage {
+            plugin: plugin.to_string(),
+            level,
+            message,
+        });
+    }
+
+    /// Returns the messages logged since the previous call
+    pub fn take(&self) -> Vec<PluginMessage> {
+        std::mem::take(&mut self.0.borrow_mut())
+    }
+}
+
+/// Returns the table of the regex's capture groups, the whole match is at index 0
+fn captures_table(lua: &Lua, regex: &Regex, captures: regex::Captures) -> mlua::Result<Table> {
+    let table = lua.create_table()?;
+    for (i, group) in captures.iter().enumerate() {
+        if let Some(group) = group {
+            table.raw_set(i, group.as_str())?;
+        }
+    }
+    for name in regex.capture_names().flatten() {
+        if let Some(group) = captures.name(name) {
+            table.raw_set(name, group.as_str())?;
+        }
+    }
+    Ok(table)
+}
+
+/// Converts a section into a table with its `header`, `code` and `docs`
+fn section_table(
+    lua: &Lua,
+    section: &crate::builds::spec::structs::Section,
+) -> mlua::Result<Table> {
+    let table = lua.create_table()?;
+    table.raw_set("header", section.header.clone())?;
+    table.raw_set("code", section.code.as_str())?;
+    table.raw_set("docs", section.docs.as_str())?;
+    Ok(table)
+}
+
+fn path_string(path: &Path) -> String {
+    path.to_string_lossy().to_string()
+}
+
+/// Injects the `lp` table with the helpers implemented in Rust into the plugin's state.
+///
+/// The paths are the paths of the modules relative to the source directory, as they are passed to the plugins.
+/// The messages the plugin logs are collected into the `log` under the plugin's `name`.
+pub fn register(
+    lua: &Lua,
+    name: &str,
+    index: Rc<ProjectIndex>,
+    log: PluginLog,
+) -> Result<(), LPError> {
+    let lua_error = |e: mlua::Error| LPError::LuaRuntime(e.to_string());
+    let lp = lua.create_table().map_err(lua_error)?;
+    let regexes: Rc<RefCell<HashMap<String, Regex>>> = Rc::default();
+
+    let compile = move |pattern: String| -> mlua::Result<Regex> {
+        let mut regexes = regexes.borrow_mut();
+        if let Some(regex) = regexes.get(&pattern) {
+            return Ok(regex.clone());
+        }
+        let regex = Regex::new(&pattern).map_err(mlua::Error::external)?;
+        if regexes.len() >= MAX_CACHED_REGEXES {
+            regexes.clear();
+        }
+        regexes.insert(pattern, regex.clone());
+        Ok(regex)
+    };
+    let compile = Rc::new(compile);
+
+    let functions = [
+        (
+            "module_name",
+            lua.create_function(|_, path: String| {
+                Ok(path_string(&utils::module_name(Path::new(&path))))
+            }),
+        ),
+        (
+            "resolve",
+            lua.create_function(|_, (module_path, path): (String, String)| {
+                let module = Module {
+                    path: PathBuf::from(module_path),
+                    sections: None,
+                };
+                Ok(path_string(
+                    &module.resolve_relative_module_path(Path::new(&path)),
+                ))
+            }),
+        ),
+        (
+            "relative_path",
+            lua.create_function(|_, (from, to): (String, String)| {
+                let from_dir = Path::new(&from).parent().unwrap_or(Path::new(""));
+                Ok(utils::relative_path(from_dir, Path::new(&to)))
+            }),
+        ),
+        (
+            "lines",
+            lua.create_function(|_, text: String| {
+                Ok(text.lines().map(str::to_string).collect::<Vec<String>>())
+            }),
+        ),
+        (
+            "trim",
+            lua.create_function(|_, text: String| Ok(text.trim().to_string())),
+        ),
+        (
+            "unique",
+            lua.create_function(|_, items: Vec<String>| {
+                let mut seen = HashSet::new();
+                Ok(items
+                    .into_iter()
+                    .filter(|item| seen.insert(item.clone()))
+                    .collect::<Vec<String>>())
+            }),
+        ),
+        ("is_match", {
+            let compile = Rc::clone(&compile);
+            lua.create_function(move |_, (pattern, text): (String, String)| {
+                Ok(compile(pattern)?.is_match(&text))
+            })
+        }),
+        ("captures", {
+            let compile = Rc::clone(&compile);
+            lua.create_function(move |lua, (pattern, text): (String, String)| {
+                let regex = compile(pattern)?;
+                match regex.captures(&text) {
+                    Some(captures) => Ok(Value::Table(captures_table(lua, &regex, captures)?)),
+                    None => Ok(Value::Nil),
+                }
+            })
+        }),
+        ("captures_all", {
+            let compile = Rc::clone(&compile);
+            lua.create_function(move |lua, (pattern, text): (String, String)| {
+                let regex = compile(pattern)?;
+                regex
+                    .captures_iter(&text)
+                    .map(|captures| captures_table(lua, &regex, captures))
+                    .collect::<mlua::Result<Vec<Table>>>()
+            })
+        }),
+        ("log", {
+            let (log, name) = (log.clone(), name.to_string());
+            lua.create_function(move |_, message: String| {
+                log.push(&name, MessageLevel::Info, message);
+                Ok(())
+            })
+        }),
+        ("warn", {
+            let (log, name) = (log.clone(), name.to_string());
+            lua.create_function(move |_, message: String| {
+                log.push(&name, MessageLevel::Warning, message);
+                Ok(())
+            })
+        }),
+        ("modules", {
+            let index = Rc::clone(&index);
+            lua.create_function(move |_, ()| {
+                index.record_read(IndexRead::Modules);
+                Ok(index
+                    .get_modules()
+                    .iter()
+                    .map(|module| path_string(&module.path))
+                    .collect::<Vec<String>>())
+            })
+        }),
+        ("get_section", {
+            let index = Rc::clone(&index);
+            lua.create_function(move |lua, (module_path, header): (String, String)| {
+                let module_name = utils::module_name(Path::new(&module_path));
+                index.record_read(IndexRead::Section {
+                    module: module_name.clone(),
+                    header: header.clone(),
+                });
+                match index.get_section(&module_name, &header) {
+                    Some(section) => Ok(Value::Table(section_table(lua, section)?)),
+                    None => Ok(Value::Nil),
+                }
+            })
+        }),
+    ];
+
+    for (function_name, function) in functions {
+        lp.raw_set(function_name, function.map_err(lua_error)?)
+            .map_err(lua_error)?;
+    }
+    lua.globals().raw_set(LIBRARY_NAME, lp).map_err(lua_error)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builds::spec::structs::{Project, Section};
+
+    fn create_lua() -> (Lua, PluginLog) {
+        let (lua, log, _) = create_lua_with_index();
+        (lua, log)
+    }
+
+    fn create_lua_with_index() -> (Lua, PluginLog, Rc<ProjectIndex>) {
+        let project = Rc::new(Project {
+            modules: vec![Rc::new(Module {
+                path: PathBuf::from("lib/primes.py.lpnb"),
+                sections: Some(vec![Rc::new(Section {
+                    code: "def is_prime(n): pass".to_string(),
+                    docs: "# Is prime".to_string(),
+                    header: Some("Is prime".to_string()),
+                    references: vec![],
+                })]),
+            })],
+        });
+        let lua = Lua::new();
+        let log = PluginLog::default();
+        let index = Rc::new(ProjectIndex::new(project));
+        register(&lua, "py", Rc::clone(&index), log.clone()).unwrap();
+        (lua, log, index)
+    }
+
+    fn eval(lua: &Lua, code: &str) -> String {
+        lua.load(code).eval().unwrap()
+    }
+
+    #[test]
+    fn test_paths() {
+        let (lua, _) = create_lua();
+        assert_eq!(
+            eval(&lua, "return lp.module_name('dir/a.py.lpnb')"),
+            "dir/a"
+        );
+        assert_eq!(
+            eval(&lua, "return lp.resolve('dir/a.py.lpnb', '../b.py.lpnb')"),
+            "b.py.lpnb"
+        );
+        assert_eq!(
+            eval(
+                &lua,
+                "return lp.relative_path('app/main.py', 'lib/primes.py')"
+            ),
+            "../lib/primes.py"
+        );
+    }
+
+    #[test]
+    fn test_text_helpers() {
+        let (lua, _) = create_lua();
+        assert_eq!(
+            eval(
+                &lua,
+                "return table.concat(lp.unique(lp.lines('a\\nb\\na')), ',') .. lp.trim('  c ')"
+            ),
+            "a,bc"
+        );
+    }
+
+    #[test]
+    fn test_regex() {
+        let (lua, _) = create_lua();
+        assert_eq!(
+            eval(
+                &lua,
+                r#"
+                local c = lp.captures("^def\\s+(?P<name>\\w+)", "def main():")
+                return c[0] .. "|" .. c[1] .. "|" .. c.name
+                "#
+            ),
+            "def main|main|main"
+        );
+        assert_eq!(
+            eval(
+                &lua,
+                r#"
+                local names = {}
+                for _, c in ipairs(lp.captures_all("def (\\w+)", "def a():\ndef b():")) do
+                    table.insert(names, c[1])
+                end
+                return table.concat(names, ",") .. tostring(lp.is_match("^\\d+$", "12a"))
+                "#
+            ),
+            "a,bfalse"
+        );
+        assert!(lua.load("lp.is_match('(', '')").exec().is_err());
+        assert!(lua
+            .load("for i = 1, 1000 do assert(lp.is_match('^' .. i .. '$', tostring(i))) end")
+            .exec()
+            .is_ok());
+    }
+
+    #[test]
+    fn test_index_access() {
+        let (lua, _) = create_lua();
+        assert_eq!(eval(&lua, "return lp.modules()[1]"), "lib/primes.py.lpnb");
+        assert_eq!(
+            eval(
+                &lua,
+                "return lp.get_section('lib/primes.py.lpnb', 'Is-prime').code"
+            ),
+            "def is_prime(n): pass"
+        );
+        assert!(lua
+            .load("return lp.get_section('lib/primes', 'Missing') == nil")
+            .eval::<bool>()
+            .unwrap());
+    }
+
+    #[test]
+    fn test_index_reads() {
+        let (lua, _, index) = create_lua_with_index();
+        lua.load("lp.get_section('lib/primes.py.lpnb', 'Is-prime') lp.get_section('lib/primes', 'Missing') lp.modules()")
+            .exec()
+            .unwrap();
+        assert_eq!(
+            index.take_reads(),
+            vec![
+                IndexRead::Modules,
+                IndexRead::Section {
+                    module: PathBuf::from("lib/primes"),
+                    header: "Is-prime".to_string(),
+                },
+                IndexRead::Section {
+                    module: PathBuf::from("lib/primes"),
+                    header: "Missing".to_string(),
+                },
+            ]
+        );
+        assert!(index.take_reads().is_empty());
+    }
+
+    #[test]
+    fn test_log() {
+        let (lua, log) = create_lua();
+        lua.load("lp.log('loaded') lp.warn('deprecated')")
+            .exec()
+            .unwrap();
+
+        let messages = log.take();
+        assert_eq!(messages.len(), 2);
+        assert_eq!(messages[1].level, MessageLevel::Warning);
+        assert_eq!(messages[1].to_string(), "[warning] plugin py: deprecated");
+        assert!(log.take().is_empty());
+    }
+}
@@ -0,0 +1,151 @@
+#![forbid(unsafe_code)]
+
+use std::path::{Component, Path, PathBuf};
+
+use mlua::{FromLua, IntoLua, Lua, Table, Value};
+
+/// The hooks called once per build
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BuildHook {
+    /// before any module is built
+    BeforeBuild,
+    /// after all the modules are built
+    AfterBuild,
+}
+
+impl BuildHook {
+    /// the name of the plugin's function
+    pub fn name(&self) -> &'static str {
+        match self {
+            BuildHook::BeforeBuild => "before_build",
+            BuildHook::AfterBuild => "after_build",
+        }
+    }
+}
+
+pub const AFTER_MODULE_HOOK_NAME: &str = "after_module";
+
+/// A module of the project as the lifecycle hooks see it, passed to Lua as `{ module = ..., output = ..., extension = ... }`
+#[derive(Debug, Clone, PartialEq)]
+pub struct BuildOutput {
+    /// the module's path relative to the source directory, e.g. `lib/primes.py.lpnb`
+    pub module: PathBuf,
+    /// the built file's path relative to the target code directory, e.g. `lib/primes.py`
+    pub output: PathBuf,
+    /// the extension the module's plugin is chosen by, e.g. `py`
+    pub extension: String,
+}
+
+impl IntoLua for &BuildOutput {
+    fn into_lua(self, lua: &Lua) -> mlua::Result<Value> {
+        let table = lua.create_table()?;
+        table.set("module", self.module.to_string_lossy())?;
+        table.set("output", self.output.to_string_lossy())?;
+        table.set("extension", self.extension.as_str())?;
+        Ok(Value::Table(table))
+    }
+}
+
+/// A file a lifecycle hook asks to write into the target code directory
+#[derive(Debug, Clone, PartialEq)]
+pub struct GeneratedFile {
+    /// relative to the target code directory
+    pub path: PathBuf,
+    pub content: String,
+}
+
+/// What a lifecycle hook returned: nil or a table from the files' paths to their contents,
+/// e.g. `{ ["lib/__init__.py"] = "" }`.
+///
+/// The paths must stay inside the target code directory.
+#[derive(Debug, Default, PartialEq)]
+pub struct GeneratedFiles(pub Vec<GeneratedFile>);
+
+impl FromLua for GeneratedFiles {
+    fn from_lua(value: Value, lua: &Lua) -> mlua::Result<Self> {
+        if value.is_nil() {
+            return Ok(GeneratedFiles::default());
+        }
+        let mut files = vec![];
+        for pair in Table::from_lua(value, lua)?.pairs::<String, String>() {
+            let (path, content) = pair?;
+            let path = PathBuf::from(path);
+            if !is_inside(&path) {
+                return Err(mlua::Error::runtime(format!(
+                    "the generated file {} must be inside the target directory",
+                    path.display()
+                )));
+            }
+            files.push(GeneratedFile { path, content });
+        }
+        files.sort_by(|a, b| a.path.cmp(&b.path));
+        Ok(GeneratedFiles(files))
+    }
+}
+
+/// Whether the relative path can't lead out of the directory it's relative to
+fn is_inside(path: &Path) -> bool {
+    path.components().next().is_some()
+        && path
+            .components()
+            .all(|component| matches!(component, Component::Normal(_)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn eval(code: &str) -> mlua::Result<GeneratedFiles> {
+        Lua::new().load(code).eval()
+    }
+
+    #[test]
+    fn test_generated_files() {
+        assert_eq!(eval("return nil").unwrap(), GeneratedFiles::default());
+        assert_eq!(
+            eval(r#"return { ["lib/__init__.py"] = "", ["index.js"] = "module.exports = {}" }"#)
+                .unwrap(),
+            GeneratedFiles(vec![
+                GeneratedFile {
+                    path: PathBuf::from("index.js"),
+                    content: "module.exports = {}".to_string(),
+                },
+                GeneratedFile {
+                    path: PathBuf::from("lib/__init__.py"),
+                    content: "".to_string(),
+                },
+            ])
+        );
+    }
+
+    #[test]
+    fn test_generated_files_outside() {
+        for path in ["../x", "/etc/x", "a/../../x", ""] {
+            let error = eval(&format!("return {{ [{:?}] = '' }}", path)).unwrap_err();
+            assert!(
+                error
+                    .to_string()
+                    .contains("must be inside the target directory"),
+                "{}",
+                error
+            );
+        }
+        assert!(eval("return 'index.js'").is_err());
+    }
+
+    #[test]
+    fn test_build_output_into_lua() {
+        let lua = Lua::new();
+        let output = BuildOutput {
+            module: PathBuf::from("lib/primes.py.lpnb"),
+            output: PathBuf::from("lib/primes.py"),
+            extension: "py".to_string(),
+        };
+        lua.globals().set("m", &output).unwrap();
+        let summary: String = lua
+            .load("return m.module .. ' ' .. m.output .. ' ' .. m.extension")
+            .eval()
+            .unwrap();
+        assert_eq!(summary, "lib/primes.py.lpnb lib/primes.py py");
+    }
+}
@@ -0,0 +1,212 @@
+#![forbid(unsafe_code)]
+
+use std::fmt;
+
+use mlua::{FromLua, Function, Lua, Table, Value};
+
+use crate::error::LPError;
+
+/// The version of the API the plugins are called with: the hooks, their arguments and the `lp` library.
+///
+/// It's increased on incompatible changes, a plugin requiring a newer version is rejected.
+pub const LP_API_VERSION: u32 = 1;
+
+/// the name of the global table with the plugin's manifest, namespaced not to clash with the plugin's own globals
+pub const PLUGIN_MANIFEST_NAME: &str = "lp_manifest";
+
+/// all the hooks a plugin may implement
+pub const KNOWN_HOOKS: &[&str] = &[
+    "get_import_code",
+    "clean_code",
+    "get_exports",
+    "before_build",
+    "after_module",
+    "after_build",
+    "render_section_docs",
+];
+
+/// Metadata of a plugin.
+///
+/// A plugin declares it as the global `lp_manifest` table or returns it from the file, e.g.
+/// `return { name = "python", version = "1.2.0", extensions = { "py", "pyi" }, api_version = 1, hooks = { "get_exports" } }`.
+/// Every field is optional, a plugin without a manifest targets the first version of the API.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Manifest {
+    pub name: Option<String>,
+    pub version: Option<String>,
+    /// the extensions (or the names of the files without an extension) the plugin handles besides its own name
+    pub extensions: Vec<String>,
+    /// the version of the API the plugin requires
+    pub api_version: u32,
+    /// the hooks the plugin implements, the ones it defines if not declared
+    pub hooks: Vec<String>,
+}
+
+impl Default for Manifest {
+    fn default() -> Self {
+        Manifest {
+            name: None,
+            version: None,
+            extensions: vec![],
+            api_version: 1,
+            hooks: vec![],
+        }
+    }
+}
+
+impl FromLua for Manifest {
+    fn from_lua(value: Value, _: &Lua) -> mlua::Result<Self> {
+        let table = match value {
+            Value::Nil => return Ok(Manifest::default()),
+            Value::Table(table) => table,
+            value => {
+                return Err(mlua::Error::runtime(format!(
+                    "the manifest must be a table, not {}",
+                    value.type_name()
+                )))
+            }
+        };
+        Ok(Manifest {
+            name: table.get("name")?,
+            version: table.get("version")?,
+            extensions: table
+                .get::<Option<Vec<String>>>("extensions")?
+                .unwrap_or_default(),
+            api_version: table.get::<Option<u32>>("api_version")?.unwrap_or(1),
+            hooks: table
+                .get::<Option<Vec<String>>>("hooks")?
+                .unwrap_or_default(),
+        })
+    }
+}
+
+impl Manifest {
+    /// Reads the manifest of the plugin loaded into the state and checks it against the plugin's code.
+    ///
+    /// The declared hooks must be known and defined, the required API version must be supported.
+    /// The hooks the plugin defines are recorded if it doesn't declare them.
+    pub fn load(name: &str, globals: &Table) -> Result<Self, LPError> {
+        let invalid = |message: String| LPError::InvalidPlugin {
+            plugin: name.to_string(),
+            message,
+        };
+        let mut manifest: Manifest = globals
+            .get(PLUGIN_MANIFEST_NAME)
+            .map_err(|e| invalid(e.to_string()))?;
+
+        if manifest.api_version > LP_API_VERSION {
+            return Err(invalid(format!(
+                "requires the API version {}, but only {} is supported, update the system",
+                manifest.api_version, LP_API_VERSION
+            )));
+        }
+
+        let is_defined = |hook: &str| {
+            globals
+                .get::<Option<Function>>(hook)
+                .is_ok_and(|f| f.is_some())
+        };
+        if manifest.hooks.is_empty() {
+            manifest.hooks = KNOWN_HOOKS
+                .iter()
+                .filter(|hook| is_defined(hook))
+                .map(|hook| hook.to_string())
+                .collect();
+        }
+        for hook in &manifest.hooks {
+            if !KNOWN_HOOKS.contains(&hook.as_str()) {
+                return Err(invalid(format!(
+                    "unknown hook {}, the known ones are {}",
+                    hook,
+                    KNOWN_HOOKS.join(", ")
+                )));
+            }
+            if !is_defined(hook) {
+                return Err(invalid(format!(
+                    "declares the hook {}, but doesn't define it",
+                    hook
+                )));
+            }
+        }
+        Ok(manifest)
+    }
+}
+
+impl fmt::Display for Manifest {
+    /// The capability summary, e.g. `python 1.2.0, api 1, extensions: py, pyi, hooks: get_import_code, clean_code`
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut parts = vec![];
+        match (&self.name, &self.version) {
+            (Some(name), Some(version)) => parts.push(format!("{} {}", name, version)),
+            (Some(name), None) => parts.push(name.clone()),
+            (None, Some(version)) => parts.push(version.clone()),
+            (None, None) => {}
+        }
+        parts.push(format!("api {}", self.api_version));
+        if !self.extensions.is_empty() {
+            parts.push(format!("extensions: {}", self.extensions.join(", ")));
+        }
+        parts.push(format!("hooks: {}", self.hooks.join(", ")));
+        write!(f, "{}", parts.join(", "))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn load(code: &str) -> Result<Manifest, LPError> {
+        let lua = Lua::new();
+        lua.load(code).exec().unwrap();
+        Manifest::load("py", &lua.globals())
+    }
+
+    const HOOKS: &str = r#"
+        function get_import_code() return "" end
+        function clean_code(code) return code end
+    "#;
+
+    #[test]
+    fn test_without_manifest() {
+        let manifest = load(HOOKS).unwrap();
+        assert_eq!(manifest.api_version, LP_API_VERSION);
+        assert_eq!(manifest.hooks, vec!["get_import_code", "clean_code"]);
+        assert_eq!(
+            load(&format!("{}\nmanifest = 'a global of the plugin'", HOOKS)).unwrap(),
+            manifest
+        );
+        assert_eq!(
+            manifest.to_string(),
+            "api 1, hooks: get_import_code, clean_code"
+        );
+    }
+
+    #[test]
+    fn test_manifest() {
+        let manifest = load(&format!(
+            r#"{}
+            function get_exports() return {{}} end
+            lp_manifest = {{ name = "python", version = "1.2.0", extensions = {{ "pyi" }}, hooks = {{ "get_exports" }} }}
+            "#,
+            HOOKS
+        ))
+        .unwrap();
+        assert_eq!(manifest.extensions, vec!["pyi"]);
+        assert_eq!(
+            manifest.to_string(),
+            "python 1.2.0, api 1, extensions: pyi, hooks: get_exports"
+        );
+    }
+
+    #[test]
+    fn test_incompatible_manifest() {
+        let error = |code: &str| match load(&format!("{}\n{}", HOOKS, code)) {
+            Err(LPError::InvalidPlugin { message, .. }) => message,
+            _ => panic!("Expected InvalidPlugin error"),
+        };
+        assert!(error("lp_manifest = { api_version = 99 }").contains("requires the API version 99"));
+        assert!(error("lp_manifest = { hooks = { 'get_exports' } }").contains("doesn't define"));
+        assert!(error("lp_manifest = { hooks = { 'on_save' } }").contains("unknown hook on_save"));
+        assert!(error("lp_manifest = 'py'").contains("must be a table"));
+    }
+}
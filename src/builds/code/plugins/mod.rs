@@ -1,4 +1,12 @@
 #![forbid(unsafe_code)]
 
+pub mod builtin;
 pub mod caller;
+pub mod import_code;
+pub mod language_plugin;
+pub mod library;
+pub mod lifecycle;
+pub mod manifest;
 pub mod plugin;
+pub mod sandbox;
+pub mod wasm_plugin;
@@ -1,12 +1,330 @@
 #![forbid(unsafe_code)]
 
-use crate::error::LPError;
-use mlua::{Function, Lua};
-use std::{path::PathBuf, rc::Rc};
+use crate::{
+    builds::index::ProjectIndex,
+    config::project_file::PluginsSettings,
+    error::{LPError, PluginFailure},
+};
+use mlua::{FromLuaMulti, Function, IntoLuaMulti, Lua, Table, Value};
+use regex::Regex;
+use std::{
+    path::{Path, PathBuf},
+    rc::Rc,
+};
+
+use super::{
+    import_code::ImportCode,
+    language_plugin::LanguagePlugin,
+    library::{self, PluginLog},
+    lifecycle::{BuildHook, BuildOutput, GeneratedFile, GeneratedFiles, AFTER_MODULE_HOOK_NAME},
+    manifest::{Manifest, PLUGIN_MANIFEST_NAME},
+    sandbox::{create_state, InstructionCounter},
+};
+
+const PLUGIN_IMPORT_CODE_FUNC_NAME: &str = "get_import_code";
+const PLUGIN_CLEANIONG_CODE_FUNC_NAME: &str = "clean_code";
+const PLUGIN_EXPORTS_FUNC_NAME: &str = "get_exports";
+const PLUGIN_RENDER_DOCS_FUNC_NAME: &str = "render_section_docs";
+const PLUGIN_LINE_DIRECTIVE_NAME: &str = "line_directive";
+const PLUGIN_HEADER_PATTERN_NAME: &str = "header_pattern";
+
+/// A plugin written in Lua, loaded into its own Lua state,
+/// so plugins can't clobber each other's helper globals and module-level tables.
+///
+/// The state lives as long as the plugin, loading the plugin again gives a fresh state.
+/// Unless disabled in the settings, the state is sandboxed, see `create_state`.
+pub struct LuaPlugin {
+    name: String,
+    manifest: Manifest,
+    /// the content of the plugin's file
+    source: Vec<u8>,
+    /// the functions hold only weak references to the state, so it's kept here
+    lua: Lua,
+    instructions: InstructionCounter,
+    import_func: Function,
+    cleaning_func: Function,
+    /// the optional function to find the names exported by a section
+    exports_func: Option<Function>,
+    /// the optional lifecycle hooks
+    before_build_func: Option<Function>,
+    after_module_func: Option<Function>,
+    after_build_func: Option<Function>,
+    /// the optional function to render a section's docs
+    render_docs_func: Option<Function>,
+    line_directive: Option<String>,
+    header_pattern: Option<Regex>,
+    /// passed as the last argument to the plugin's functions
+    options: Table,
+}
+
+impl LuaPlugin {
+    /// Loads the plugin named `name` from `path` into a new Lua state.
+    ///
+    /// The plugin's options come from the `settings`, the plugin gets an empty table if there are none.
+    /// The `lp` library is available to the plugin while it's loaded, its messages go to the `log`.
+    pub fn load(
+        name: &str,
+        path: &PathBuf,
+        settings: &PluginsSettings,
+        index: Rc<ProjectIndex>,
+        log: PluginLog,
+    ) -> Result<Self, LPError> {
+        let (lua, instructions) = create_state(&settings.sandbox)?;
+        library::register(&lua, name, index, log)?;
+        let funcs = get_plugin_funcs(
+            &lua,
+            path,
+            vec![
+                PLUGIN_IMPORT_CODE_FUNC_NAME,
+                PLUGIN_CLEANIONG_CODE_FUNC_NAME,
+            ],
+        )?;
+
+        let manifest = Manifest::load(name, &lua.globals())?;
+        let source =
+            std::fs::read(path).map_err(|_| LPError::CannotReadFile(path.display().to_string()))?;
+
+        let optional_func = |func_name: &str| -> Result<Option<Function>, LPError> {
+            lua.globals().get(func_name).map_err(|e| {
+                LPError::LuaRuntime(format!("{} must be a function: {}", func_name, e))
+            })
+        };
+        let exports_func = optional_func(PLUGIN_EXPORTS_FUNC_NAME)?;
+        let before_build_func = optional_func(BuildHook::BeforeBuild.name())?;
+        let after_module_func = optional_func(AFTER_MODULE_HOOK_NAME)?;
+        let after_build_func = optional_func(BuildHook::AfterBuild.name())?;
+        let render_docs_func = optional_func(PLUGIN_RENDER_DOCS_FUNC_NAME)?;
+
+        let line_directive: Option<String> =
+            lua.globals().get(PLUGIN_LINE_DIRECTIVE_NAME).map_err(|e| {
+                LPError::LuaRuntime(format!(
+                    "{} must be a string: {}",
+                    PLUGIN_LINE_DIRECTIVE_NAME, e
+                ))
+            })?;
+
+        let header_pattern = lua
+            .globals()
+            .get::<Option<String>>(PLUGIN_HEADER_PATTERN_NAME)
+            .map_err(|e| e.to_string())
+            .and_then(|pattern| {
+                pattern
+                    .map(|pattern| Regex::new(&pattern).map_err(|e| e.to_string()))
+                    .transpose()
+            })
+            .map_err(|e| {
+                LPError::LuaRuntime(format!(
+                    "{} must be a regular expression: {}",
+                    PLUGIN_HEADER_PATTERN_NAME, e
+                ))
+            })?;
+
+        let options = match settings.options.get(name) {
+            Some(options) => yaml_to_lua(&lua, options),
+            None => lua.create_table().map(Value::Table),
+        }
+        .map_err(|e| LPError::LuaRuntime(e.to_string()))?;
+        let Value::Table(options) = options else {
+            return Err(LPError::LuaRuntime(format!(
+                "Options of the plugin {} must be a mapping",
+                name
+            )));
+        };
+
+        Ok(LuaPlugin {
+            name: name.to_string(),
+            manifest,
+            source,
+            lua,
+            instructions,
+            import_func: funcs[0].clone(),
+            cleaning_func: funcs[1].clone(),
+            exports_func,
+            before_build_func,
+            after_module_func,
+            after_build_func,
+            render_docs_func,
+            line_directive,
+            header_pattern,
+            options,
+        })
+    }
+
+    /// Calls one of the plugin's functions, the error names the plugin and the function and holds the Lua traceback
+    fn call<R: FromLuaMulti>(
+        &self,
+        func: &Function,
+        func_name: &str,
+        args: impl IntoLuaMulti,
+    ) -> Result<R, LPError> {
+        self.instructions.reset();
+        func.call(args).map_err(|e| {
+            let (message, traceback) = split_traceback(&e);
+            LPError::PluginFailed(Box::new(PluginFailure {
+                plugin: self.name.clone(),
+                hook: func_name.to_string(),
+                message,
+                traceback,
+                ..PluginFailure::default()
+            }))
+        })
+    }
+}
+
+/// Splits a Lua error into the message and the stack traceback, if the error has one
+fn split_traceback(error: &mlua::Error) -> (String, Option<String>) {
+    match error {
+        mlua::Error::CallbackError { traceback, cause } => {
+            (split_traceback(cause).0, Some(traceback.clone()))
+        }
+        mlua::Error::RuntimeError(message) => match message.split_once("\nstack traceback:") {
+            Some((message, traceback)) => (
+                message.to_string(),
+                Some(format!("stack traceback:{}", traceback)),
+            ),
+            None => (message.clone(), None),
+        },
+        error => (error.to_string(), None),
+    }
+}
+
+impl LanguagePlugin for LuaPlugin {
+    /// Returns the plugin's manifest, either declared or inferred
+    fn manifest(&self) -> &Manifest {
+        &self.manifest
+    }
+
+    fn fingerprint(&self) -> &[u8] {
+        &self.source
+    }
+
+    /// Returns the line directive template declared by the plugin, if any
+    fn line_directive(&self) -> Option<&str> {
+        self.line_directive.as_deref()
+    }
+
+    /// Returns the pattern of the lines of the file's header declared by the plugin, if any
+    fn header_pattern(&self) -> Option<&Regex> {
+        self.header_pattern.as_ref()
+    }
+
+    /// Calls the plugin's `get_import_code` function
+    fn get_import_code(
+        &self,
+        current_path: &Path,
+        referenced_path: &Path,
+        code_block: &str,
+    ) -> Result<ImportCode, LPError> {
+        self.call(
+            &self.import_func,
+            PLUGIN_IMPORT_CODE_FUNC_NAME,
+            (
+                current_path.to_string_lossy().as_ref(),
+                referenced_path.to_string_lossy().as_ref(),
+                code_block,
+                self.options.clone(),
+            ),
+        )
+    }
+
+    /// Calls the plugin's `get_exports` function, returns None if the plugin doesn't declare it
+    fn get_exports(
+        &self,
+        module_path: &Path,
+        code_block: &str,
+    ) -> Result<Option<Vec<String>>, LPError> {
+        let Some(exports_func) = &self.exports_func else {
+            return Ok(None);
+        };
+        self.call(
+            exports_func,
+            PLUGIN_EXPORTS_FUNC_NAME,
+            (
+                module_path.to_string_lossy().as_ref(),
+                code_block,
+                self.options.clone(),
+            ),
+        )
+        .map(Some)
+    }
+
+    /// Calls the plugin's `clean_code` function
+    fn clean_code(&self, code: &str) -> Result<String, LPError> {
+        self.call(
+            &self.cleaning_func,
+            PLUGIN_CLEANIONG_CODE_FUNC_NAME,
+            (code, self.options.clone()),
+        )
+    }
+
+    /// Calls the plugin's `render_section_docs` function, returns None if the plugin doesn't declare it
+    /// or the function returns nil
+    fn render_section_docs(
+        &self,
+        module_path: &Path,
+        header: Option<&str>,
+        docs: &str,
+        code: &str,
+    ) -> Result<Option<String>, LPError> {
+        let Some(render_docs_func) = &self.render_docs_func else {
+            return Ok(None);
+        };
+        self.call(
+            render_docs_func,
+            PLUGIN_RENDER_DOCS_FUNC_NAME,
+            (
+                module_path.to_string_lossy().as_ref(),
+                header,
+                docs,
+                code,
+                self.options.clone(),
+            ),
+        )
+    }
+
+    /// Calls the plugin's `before_build` or `after_build` function, if declared
+    fn call_build_hook(
+        &self,
+        hook: BuildHook,
+        modules: &[BuildOutput],
+    ) -> Result<Vec<GeneratedFile>, LPError> {
+        let func = match hook {
+            BuildHook::BeforeBuild => &self.before_build_func,
+            BuildHook::AfterBuild => &self.after_build_func,
+        };
+        let Some(func) = func else {
+            return Ok(vec![]);
+        };
+        let modules = self
+            .lua
+            .create_sequence_from(modules)
+            .map_err(|e| LPError::LuaRuntime(e.to_string()))?;
+        self.call(func, hook.name(), (modules, self.options.clone()))
+            .map(|files: GeneratedFiles| files.0)
+    }
+
+    /// Calls the plugin's `after_module` function, if declared
+    fn after_module(
+        &self,
+        module: &BuildOutput,
+        code: &str,
+    ) -> Result<Vec<GeneratedFile>, LPError> {
+        let Some(func) = &self.after_module_func else {
+            return Ok(vec![]);
+        };
+        self.call(
+            func,
+            AFTER_MODULE_HOOK_NAME,
+            (module, code, self.options.clone()),
+        )
+        .map(|files: GeneratedFiles| files.0)
+    }
+}
 
 /// Get the plugin functions.
 ///
 /// Compiles the plugin code and gets the functions with the given names.
+/// If the plugin returns a table, it becomes the plugin's manifest, unless the plugin declares one.
 ///
 /// # Arguments
 /// * `lua` - the Lua instance.
@@ -15,17 +333,29 @@ use std::{path::PathBuf, rc::Rc};
 /// # Returns
 /// Returns either a vector of functions in the same order as the given list of their names or an LPError.
 pub fn get_plugin_funcs(
-    lua: &Rc<Lua>,
+    lua: &Lua,
     plugin_path: &PathBuf,
     func_names: Vec<&str>,
 ) -> Result<Vec<Function>, LPError> {
     let code = std::fs::read_to_string(plugin_path)
         .map_err(|_| LPError::CannotReadFile(plugin_path.display().to_string()))?;
 
-    lua.load(&code)
-        .set_name(plugin_path.to_string_lossy().as_ref())
-        .exec()
+    let returned: Value = lua
+        .load(&code)
+        .set_name(format!("@{}", plugin_path.display()))
+        .eval()
         .map_err(|e| LPError::LuaRuntime(e.to_string()))?;
+    if let Value::Table(manifest) = returned {
+        let globals = lua.globals();
+        if !globals
+            .contains_key(PLUGIN_MANIFEST_NAME)
+            .map_err(|e| LPError::LuaRuntime(e.to_string()))?
+        {
+            globals
+                .set(PLUGIN_MANIFEST_NAME, manifest)
+                .map_err(|e| LPError::LuaRuntime(e.to_string()))?;
+        }
+    }
 
     let mut funcs = Vec::new();
 
@@ -40,10 +370,41 @@ pub fn get_plugin_funcs(
 
     Ok(funcs)
 }
+/// Converts a YAML value (e.g. plugin options from the project configuration) into a Lua value.
+///
+/// Mappings and sequences become tables, sequences are 1-based as usual in Lua.
+pub fn yaml_to_lua(lua: &Lua, value: &serde_yaml::Value) -> mlua::Result<Value> {
+    Ok(match value {
+        serde_yaml::Value::Null => Value::Nil,
+        serde_yaml::Value::Bool(b) => Value::Boolean(*b),
+        serde_yaml::Value::Number(n) => match n.as_i64() {
+            Some(i) => Value::Integer(i),
+            None => Value::Number(n.as_f64().unwrap_or_default()),
+        },
+        serde_yaml::Value::String(s) => Value::String(lua.create_string(s)?),
+        serde_yaml::Value::Sequence(items) => {
+            let table = lua.create_table()?;
+            for item in items {
+                table.push(yaml_to_lua(lua, item)?)?;
+            }
+            Value::Table(table)
+        }
+        serde_yaml::Value::Mapping(mapping) => {
+            let table = lua.create_table()?;
+            for (key, item) in mapping {
+                table.set(yaml_to_lua(lua, key)?, yaml_to_lua(lua, item)?)?;
+            }
+            Value::Table(table)
+        }
+        serde_yaml::Value::Tagged(tagged) => yaml_to_lua(lua, &tagged.value)?,
+    })
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use std::io::Write;
+    use std::rc::Rc;
     use tempfile::NamedTempFile;
 
     fn create_temp_file(content: &str) -> PathBuf {
@@ -196,4 +557,58 @@ mod tests {
 
         std::fs::remove_file(plugin_path).expect("Failed to remove temp file");
     }
+
+    #[test]
+    fn test_call_error_traceback() {
+        let plugin_path = create_temp_file(
+            r#"
+        local function find_exports(code_block)
+            error("unexpected code: " .. code_block)
+        end
+
+        function get_import_code(current_path, referenced_path, code_block)
+            local exports = find_exports(code_block)
+            return exports
+        end
+
+        function clean_code(code)
+            return {}
+        end
+        "#,
+        );
+        let index = Rc::new(ProjectIndex::new(Rc::new(
+            crate::builds::spec::structs::Project { modules: vec![] },
+        )));
+        let plugin = LuaPlugin::load(
+            "py",
+            &plugin_path,
+            &PluginsSettings::default(),
+            index,
+            PluginLog::default(),
+        )
+        .unwrap();
+
+        match plugin.get_import_code(Path::new("a.py"), Path::new("b.py"), "x = 1") {
+            Err(LPError::PluginFailed(failure)) => {
+                assert_eq!(failure.plugin, "py");
+                assert_eq!(failure.hook, "get_import_code");
+                assert!(failure.message.contains("unexpected code: x = 1"));
+                let traceback = failure.traceback.unwrap();
+                assert!(traceback.starts_with("stack traceback:"), "{}", traceback);
+                assert!(traceback.contains("get_import_code"), "{}", traceback);
+                assert!(
+                    traceback.contains(&format!("{}:3:", plugin_path.display())),
+                    "{}",
+                    traceback
+                );
+            }
+            _ => panic!("Expected PluginFailed error"),
+        }
+        match plugin.clean_code("") {
+            Err(LPError::PluginFailed(failure)) => assert_eq!(failure.hook, "clean_code"),
+            _ => panic!("Expected PluginFailed error"),
+        }
+
+        std::fs::remove_file(plugin_path).expect("Failed to remove temp file");
+    }
 }
@@ -0,0 +1,122 @@
+#![forbid(unsafe_code)]
+
+use std::{cell::Cell, rc::Rc};
+
+use mlua::{HookTriggers, Lua, LuaOptions, StdLib, VmState};
+
+use crate::{config::project_file::SandboxSettings, error::LPError};
+
+/// instructions executed between the checks of the instruction limit
+const INSTRUCTIONS_PER_CHECK: u32 = 1000;
+
+/// base library functions which give access to the file system
+const UNSAFE_GLOBALS: [&str; 2] = ["dofile", "loadfile"];
+
+/// Counts the instructions executed by a plugin since the last reset
+#[derive(Clone, Default)]
+pub struct InstructionCounter(Rc<Cell<u64>>);
+
+impl InstructionCounter {
+    /// Starts counting from zero, called before each call of a plugin's function
+    pub fn reset(&self) {
+        self.0.set(0);
+    }
+}
+
+/// Creates a Lua state for a plugin.
+///
+/// A sandboxed state has only the libraries which can't reach the outside world (no `io`, `os`, `package` and `debug`),
+/// a memory cap, and aborts any call which executes more instructions than allowed.
+pub fn create_state(settings: &SandboxSettings) -> Result<(Lua, InstructionCounter), LPError> {
+    let counter = InstructionCounter::default();
+    if !settings.enabled {
+        return Ok((Lua::new(), counter));
+    }
+
+    let lua_error = |e: mlua::Error| LPError::LuaRuntime(e.to_string());
+    let lua = Lua::new_with(
+        StdLib::COROUTINE | StdLib::TABLE | StdLib::STRING | StdLib::UTF8 | StdLib::MATH,
+        LuaOptions::default(),
+    )
+    .map_err(lua_error)?;
+    for name in UNSAFE_GLOBALS {
+        lua.globals().raw_remove(name).map_err(lua_error)?;
+    }
+    lua.set_memory_limit(settings.memory_limit)
+        .map_err(lua_error)?;
+
+    let executed = counter.clone();
+    let limit = settings.instruction_limit;
+    lua.set_hook(
+        HookTriggers::new().every_nth_instruction(INSTRUCTIONS_PER_CHECK),
+        move |_, _| {
+            executed
+                .0
+                .set(executed.0.get() + INSTRUCTIONS_PER_CHECK as u64);
+            if executed.0.get() > limit {
+                return Err(mlua::Error::runtime(format!(
+                    "instruction limit of {} exceeded",
+                    limit
+                )));
+            }
+            Ok(VmState::Continue)
+        },
+    );
+
+    Ok((lua, counter))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sandbox() -> SandboxSettings {
+        SandboxSettings {
+            instruction_limit: 100_000,
+            memory_limit: 4 * 1024 * 1024,
+            ..SandboxSettings::default()
+        }
+    }
+
+    #[test]
+    fn test_sandbox_libraries() {
+        let (lua, _) = create_state(&sandbox()).unwrap();
+        let missing: bool = lua
+            .load("return io == nil and os == nil and require == nil and dofile == nil")
+            .eval()
+            .unwrap();
+        assert!(missing);
+        let available: String = lua.load("return string.upper('ok')").eval().unwrap();
+        assert_eq!(available, "OK");
+
+        let (lua, _) = create_state(&SandboxSettings {
+            enabled: false,
+            ..sandbox()
+        })
+        .unwrap();
+        assert!(lua.load("return os ~= nil").eval::<bool>().unwrap());
+    }
+
+    #[test]
+    fn test_sandbox_instruction_limit() {
+        let (lua, counter) = create_state(&sandbox()).unwrap();
+        let error = lua.load("while true do end").exec().unwrap_err();
+        assert!(error.to_string().contains("instruction limit"));
+
+        counter.reset();
+        lua.load("for i = 1, 1000 do end").exec().unwrap();
+    }
+
+    #[test]
+    fn test_sandbox_memory_limit() {
+        let (lua, _) = create_state(&SandboxSettings {
+            instruction_limit: u64::MAX,
+            ..sandbox()
+        })
+        .unwrap();
+        let result = lua
+            .load("local t = {} for i = 1, 10000000 do t[i] = i end")
+            .exec();
+        assert!(matches!(result, Err(mlua::Error::MemoryError(_))));
+    }
+}
@@ -0,0 +1,375 @@
+#![forbid(unsafe_code)]
+//! plugins compiled to WebAssembly, e.g. from Rust or Go
+//!
+//! The ABI is string in, string out. Strings are UTF-8, passed as a pointer and a length in the plugin's memory.
+//! A plugin exports:
+//! - `memory`, its linear memory;
+//! - `lp_alloc(len: i32) -> i32`, returns a buffer of `len` bytes the arguments are written into;
+//! - `get_import_code(current_ptr, current_len, referenced_ptr, referenced_len, code_ptr, code_len: i32) -> i64`,
+//!   returns the import code, an empty string imports nothing;
+//! - `clean_code(code_ptr, code_len: i32) -> i64`, returns the cleaned code;
+//! - optionally `get_exports(module_ptr, module_len, code_ptr, code_len: i32) -> i64`,
+//!   returns the exported names, one per line;
+//! - optionally `lp_reset()`, called before the arguments of each call are written,
+//!   so a bump allocator can free everything it has allocated.
+//!
+//! A returned string is packed into `i64` as `(ptr << 32) | len`. A trap is reported as the plugin's error.
+
+use std::{cell::RefCell, path::Path};
+
+use wasmi::{
+    Config, Engine, Instance, Linker, Memory, Module, Store, StoreLimits, StoreLimitsBuilder,
+    TypedFunc,
+};
+
+use crate::{
+    config::project_file::SandboxSettings,
+    error::{LPError, PluginFailure},
+};
+
+use super::{import_code::ImportCode, language_plugin::LanguagePlugin, manifest::Manifest};
+
+pub const WASM_PLUGIN_EXTENSION: &str = "wasm";
+
+const MEMORY_EXPORT_NAME: &str = "memory";
+const ALLOC_FUNC_NAME: &str = "lp_alloc";
+const RESET_FUNC_NAME: &str = "lp_reset";
+const IMPORT_CODE_FUNC_NAME: &str = "get_import_code";
+const CLEANING_CODE_FUNC_NAME: &str = "clean_code";
+const EXPORTS_FUNC_NAME: &str = "get_exports";
+
+/// A plugin compiled to WebAssembly, instantiated in its own store.
+///
+/// The plugin gets no imports, so it can't reach the outside world.
+/// Unless disabled in the settings, its memory is capped and each call gets limited fuel.
+pub struct WasmPlugin {
+    name: String,
+    manifest: Manifest,
+    /// the content of the plugin's file
+    source: Vec<u8>,
+    store: RefCell<Store<StoreLimits>>,
+    memory: Memory,
+    alloc_func: TypedFunc<i32, i32>,
+    reset_func: Option<TypedFunc<(), ()>>,
+    import_func: TypedFunc<(i32, i32, i32, i32, i32, i32), i64>,
+    cleaning_func: TypedFunc<(i32, i32), i64>,
+    exports_func: Option<TypedFunc<(i32, i32, i32, i32), i64>>,
+    /// fuel given to each call, None if the plugin isn't sandboxed
+    fuel: Option<u64>,
+}
+
+impl WasmPlugin {
+    /// Compiles and instantiates the plugin named `name` from `path`
+    pub fn load(name: &str, path: &Path, sandbox: &SandboxSettings) -> Result<Self, LPError> {
+        let invalid = |message: String| LPError::InvalidPlugin {
+            plugin: name.to_string(),
+            message,
+        };
+        let wasm =
+            std::fs::read(path).map_err(|_| LPError::CannotReadFile(path.display().to_string()))?;
+
+        let mut config = Config::default();
+        config.consume_fuel(sandbox.enabled);
+        let engine = Engine::new(&config);
+        let module = Module::new(&engine, &wasm).map_err(|e| invalid(e.to_string()))?;
+
+        let limits = if sandbox.enabled {
+            StoreLimitsBuilder::new()
+                .memory_size(sandbox.memory_limit)
+                .build()
+        } else {
+            StoreLimits::default()
+        };
+        let mut store = Store::new(&engine, limits);
+        store.limiter(|limits| limits);
+        let fuel = sandbox.enabled.then_some(sandbox.instruction_limit);
+        if let Some(fuel) = fuel {
+            store
+                .set_fuel(fuel)
+                .map_err(|e| LPError::WasmRuntime(e.to_string()))?;
+        }
+
+        let instance = Linker::new(&engine)
+            .instantiate_and_start(&mut store, &module)
+            .map_err(|e| invalid(e.to_string()))?;
+
+        let memory = instance
+            .get_memory(&store, MEMORY_EXPORT_NAME)
+            .ok_or_else(|| invalid(format!("doesn't export {}", MEMORY_EXPORT_NAME)))?;
+        let required =
+            |func_name: &str, e: wasmi::Error| invalid(format!("must export {}: {}", func_name, e));
+        let alloc_func = instance
+            .get_typed_func(&store, ALLOC_FUNC_NAME)
+            .map_err(|e| required(ALLOC_FUNC_NAME, e))?;
+        let import_func = instance
+            .get_typed_func(&store, IMPORT_CODE_FUNC_NAME)
+            .map_err(|e| required(IMPORT_CODE_FUNC_NAME, e))?;
+        let cleaning_func = instance
+            .get_typed_func(&store, CLEANING_CODE_FUNC_NAME)
+            .map_err(|e| required(CLEANING_CODE_FUNC_NAME, e))?;
+        let reset_func = optional_func(&instance, &store, RESET_FUNC_NAME).map_err(invalid)?;
+        let exports_func = optional_func(&instance, &store, EXPORTS_FUNC_NAME).map_err(invalid)?;
+
+        let mut hooks = vec![IMPORT_CODE_FUNC_NAME, CLEANING_CODE_FUNC_NAME];
+        if exports_func.is_some() {
+            hooks.push(EXPORTS_FUNC_NAME);
+        }
+        let manifest = Manifest {
+            name: Some(name.to_string()),
+            hooks: hooks.into_iter().map(String::from).collect(),
+            ..Manifest::default()
+        };
+
+        Ok(WasmPlugin {
+            name: name.to_string(),
+            manifest,
+            source: wasm,
+            store: RefCell::new(store),
+            memory,
+            alloc_func,
+            reset_func,
+            import_func,
+            cleaning_func,
+            exports_func,
+            fuel,
+        })
+    }
+
+    /// Copies the arguments into the plugin's memory, calls the function and reads the returned string.
+    /// The error names the plugin and the function.
+    fn call(
+        &self,
+        func_name: &str,
+        args: &[&str],
+        func: impl FnOnce(&mut Store<StoreLimits>, &[i32]) -> Result<i64, wasmi::Error>,
+    ) -> Result<String, LPError> {
+        let error = |message: String| {
+            LPError::PluginFailed(Box::new(PluginFailure {
+                plugin: self.name.clone(),
+                hook: func_name.to_string(),
+                message,
+                ..PluginFailure::default()
+            }))
+        };
+        let mut store = self.store.borrow_mut();
+        if let Some(fuel) = self.fuel {
+            store.set_fuel(fuel).map_err(|e| error(e.to_string()))?;
+        }
+        if let Some(reset_func) = &self.reset_func {
+            reset_func
+                .call(&mut *store, ())
+                .map_err(|e| error(e.to_string()))?;
+        }
+
+        let mut params = vec![];
+        for arg in args {
+            let len = i32::try_from(arg.len()).map_err(|e| error(e.to_string()))?;
+            let ptr = self
+                .alloc_func
+                .call(&mut *store, len)
+                .map_err(|e| error(e.to_string()))?;
+            self.memory
+                .write(&mut *store, ptr as u32 as usize, arg.as_bytes())
+                .map_err(|e| {
+                    error(format!(
+                        "{} returned an invalid buffer: {}",
+                        ALLOC_FUNC_NAME, e
+                    ))
+                })?;
+            params.extend([ptr, len]);
+        }
+
+        let result = func(&mut store, &params).map_err(|e| error(e.to_string()))?;
+        let (ptr, len) = ((result >> 32) as u32 as usize, result as u32 as usize);
+        // the length is checked before allocating, so a plugin can't make the host allocate more than its memory
+        if ptr
+            .checked_add(len)
+            .is_none_or(|end| end > self.memory.data_size(&*store))
+        {
+            return Err(error(format!(
+                "invalid result: {} bytes at {} are out of the plugin's memory",
+                len, ptr
+            )));
+        }
+        let mut bytes = vec![0; len];
+        self.memory
+            .read(&*store, ptr, &mut bytes)
+            .map_err(|e| error(format!("invalid result: {}", e)))?;
+        String::from_utf8(bytes).map_err(|e| error(format!("invalid result: {}", e)))
+    }
+}
+
+/// Returns the optional exported function, an error if it has the wrong type
+fn optional_func<Params: wasmi::WasmParams, Results: wasmi::WasmResults>(
+    instance: &Instance,
+    store: &Store<StoreLimits>,
+    name: &str,
+) -> Result<Option<TypedFunc<Params, Results>>, String> {
+    let Some(func) = instance.get_func(store, name) else {
+        return Ok(None);
+    };
+    func.typed(store)
+        .map(Some)
+        .map_err(|e| format!("{} has a wrong type: {}", name, e))
+}
+
+impl LanguagePlugin for WasmPlugin {
+    /// Returns the manifest inferred from the plugin's exports
+    fn manifest(&self) -> &Manifest {
+        &self.manifest
+    }
+
+    fn fingerprint(&self) -> &[u8] {
+        &self.source
+    }
+
+    /// Calls the plugin's `get_import_code` function
+    fn get_import_code(
+        &self,
+        current_path: &Path,
+        referenced_path: &Path,
+        code_block: &str,
+    ) -> Result<ImportCode, LPError> {
+        let import = self.call(
+            IMPORT_CODE_FUNC_NAME,
+            &[
+                &current_path.to_string_lossy(),
+                &referenced_path.to_string_lossy(),
+                code_block,
+            ],
+            |store, p| {
+                self.import_func
+                    .call(store, (p[0], p[1], p[2], p[3], p[4], p[5]))
+            },
+        )?;
+        Ok(ImportCode {
+            imports: if import.is_empty() {
+                vec![]
+            } else {
+                vec![import]
+            },
+            ..ImportCode::default()
+        })
+    }
+
+    /// Calls the plugin's `get_exports` function, returns None if the plugin doesn't export it
+    fn get_exports(
+        &self,
+        module_path: &Path,
+        code_block: &str,
+    ) -> Result<Option<Vec<String>>, LPError> {
+        let Some(exports_func) = &self.exports_func else {
+            return Ok(None);
+        };
+        let exports = self.call(
+            EXPORTS_FUNC_NAME,
+            &[&module_path.to_string_lossy(), code_block],
+            |store, p| exports_func.call(store, (p[0], p[1], p[2], p[3])),
+        )?;
+        Ok(Some(
+            exports
+                .lines()
+                .filter(|name| !name.is_empty())
+                .map(String::from)
+                .collect(),
+        ))
+    }
+
+    /// Calls the plugin's `clean_code` function
+    fn clean_code(&self, code: &str) -> Result<String, LPError> {
+        self.call(CLEANING_CODE_FUNC_NAME, &[code], |store, p| {
+            self.cleaning_func.call(store, (p[0], p[1]))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    const FIXTURE: &str = include_str!("../../../../tests/fixtures/plugins/comment.wat");
+
+    fn load(wat: &str, sandbox: &SandboxSettings) -> Result<WasmPlugin, LPError> {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("comment.wasm");
+        std::fs::write(&path, wat::parse_str(wat).unwrap()).unwrap();
+        WasmPlugin::load("comment", &path, sandbox)
+    }
+
+    #[test]
+    fn test_fixture() {
+        let plugin = load(FIXTURE, &SandboxSettings::default()).unwrap();
+        assert_eq!(
+            plugin.manifest().to_string(),
+            "comment, api 1, hooks: get_import_code, clean_code, get_exports"
+        );
+
+        for _ in 0..3 {
+            let import = plugin
+                .get_import_code(Path::new("main.txt"), Path::new("lib/util.txt"), "code")
+                .unwrap();
+            assert_eq!(import.imports, vec!["# import lib/util.txt"]);
+        }
+        let code = "line\n".repeat(100_000);
+        assert_eq!(plugin.clean_code(&code).unwrap(), code);
+        assert_eq!(
+            plugin
+                .get_exports(Path::new("a.txt"), "first\n\nsecond")
+                .unwrap(),
+            Some(vec!["first".to_string(), "second".to_string()])
+        );
+    }
+
+    #[test]
+    fn test_sandbox() {
+        let looping = r#"
+        (module
+          (memory (export "memory") 1)
+          (func (export "lp_alloc") (param i32) (result i32) i32.const 0)
+          (func (export "get_import_code") (param i32 i32 i32 i32 i32 i32) (result i64) i64.const 0)
+          (func (export "clean_code") (param i32 i32) (result i64)
+            (loop $forever (br $forever))
+            i64.const 0))
+        "#;
+        let sandbox = SandboxSettings {
+            instruction_limit: 100_000,
+            ..SandboxSettings::default()
+        };
+        let plugin = load(looping, &sandbox).unwrap();
+        match plugin.clean_code("") {
+            Err(LPError::PluginFailed(failure)) => {
+                assert_eq!(failure.plugin, "comment");
+                assert_eq!(failure.hook, "clean_code");
+            }
+            _ => panic!("Expected PluginFailed error"),
+        }
+
+        let out_of_bounds = r#"
+        (module
+          (memory (export "memory") 1)
+          (func (export "lp_alloc") (param i32) (result i32) i32.const 0)
+          (func (export "get_import_code") (param i32 i32 i32 i32 i32 i32) (result i64) i64.const 0)
+          (func (export "clean_code") (param i32 i32) (result i64) i64.const 9223372036854775807))
+        "#;
+        match load(out_of_bounds, &sandbox).unwrap().clean_code("") {
+            Err(LPError::PluginFailed(failure)) => {
+                assert!(failure.message.contains("out of the plugin's memory"))
+            }
+            _ => panic!("Expected PluginFailed error"),
+        }
+
+        let importing = r#"(module (import "env" "open" (func)))"#;
+        assert!(matches!(
+            load(importing, &sandbox),
+            Err(LPError::InvalidPlugin { .. })
+        ));
+        let without_alloc = r#"(module (memory (export "memory") 1))"#;
+        match load(without_alloc, &sandbox) {
+            Err(LPError::InvalidPlugin { message, .. }) => {
+                assert!(message.contains("must export lp_alloc"), "{}", message)
+            }
+            _ => panic!("Expected InvalidPlugin error"),
+        }
+    }
+}
@@ -0,0 +1,593 @@
+#![forbid(unsafe_code)]
+
+use std::{
+    collections::{BTreeSet, HashMap},
+    path::{Component, Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    builds::spec::{chunks::TangledLine, sections::CodeLines, structs::Module, utils},
+    config::constants::SOURCE_MAP_EXTENSION,
+    error::LPError,
+};
+
+/// Where a line of the generated code comes from
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LineOrigin {
+    /// index of the section within the module
+    pub section: usize,
+    /// 1-based line number within the section's code,
+    /// None for the lines generated from the section's references, e.g. imports
+    pub line: Option<usize>,
+}
+
+/// A line of the code being built together with its origin, if known
+#[derive(Debug, Clone, PartialEq)]
+pub struct TracedLine {
+    pub text: String,
+    pub origin: Option<LineOrigin>,
+}
+
+impl From<TangledLine> for TracedLine {
+    fn from(line: TangledLine) -> Self {
+        TracedLine {
+            text: line.text,
+            origin: Some(LineOrigin {
+                section: line.section,
+                line: Some(line.line),
+            }),
+        }
+    }
+}
+
+/// Splits the text into lines, all of them having the same origin
+pub fn trace_text(text: &str, origin: Option<LineOrigin>) -> Vec<TracedLine> {
+    text.split('\n')
+        .map(|line| TracedLine {
+            text: line.to_string(),
+            origin,
+        })
+        .collect()
+}
+
+/// Joins the lines back into a text
+pub fn join_traced(lines: &[TracedLine]) -> String {
+    lines
+        .iter()
+        .map(|line| line.text.as_str())
+        .collect::<Vec<&str>>()
+        .join("\n")
+}
+
+/// Restores the origins of the lines of the code returned by a plugin.
+///
+/// Plugins don't keep the origins, so each line of the `cleaned` code is matched with an equal line of the `original` one.
+/// Lines are expected to keep their order, but removed and moved lines are handled as well.
+/// Lines the plugin has changed get no origin.
+///
+/// The search for each line starts after the previously matched one, looking up the unused equal lines by their text,
+/// so it doesn't get slower with the size of the file.
+pub fn trace_cleaned_code(cleaned: &str, original: &[TracedLine]) -> Vec<TracedLine> {
+    let mut unused: HashMap<&str, BTreeSet<usize>> = HashMap::new();
+    for (i, line) in original.iter().enumerate() {
+        unused.entry(line.text.as_str()).or_default().insert(i);
+    }
+    let mut cursor = 0;
+
+    cleaned
+        .split('\n')
+        .map(|text| {
+            let found = unused.get_mut(text).and_then(|indices| {
+                let i = *indices.range(cursor..).next().or_else(|| indices.first())?;
+                indices.remove(&i);
+                Some(i)
+            });
+
+            let origin = found.and_then(|i| {
+                cursor = i + 1;
+                original[i].origin
+            });
+            TracedLine {
+                text: text.to_string(),
+                origin,
+            }
+        })
+        .collect()
+}
+
+/// Renders the plugin's line directive template, e.g. `#line {line} "{file}"`
+fn render_line_directive(template: &str, line: usize, file: &Path) -> String {
+    template
+        .replace("{line}", &line.to_string())
+        .replace("{file}", &file.to_string_lossy())
+}
+
+/// Inserts line directives, so the compiler attributes the lines of the final code to the literate module.
+///
+/// A directive pointing to `source_file` is inserted wherever the next line doesn't continue the section's code,
+/// e.g. at section boundaries or where the plugin has reordered lines.
+/// Lines without a known origin in the section's code are attributed back to `generated_file`.
+///
+/// `code_lines` holds where each section's code is in `source_file`, see `locate_code_lines`.
+/// If only the first line of a section's code is known there, its other lines are attributed to `generated_file`.
+pub fn insert_line_directives(
+    lines: Vec<TracedLine>,
+    template: &str,
+    source_file: &Path,
+    generated_file: &Path,
+    code_lines: &[CodeLines],
+) -> Vec<TracedLine> {
+    let mut result: Vec<TracedLine> = Vec::with_capacity(lines.len());
+    // the line of the source file the compiler would assign to the next line, None if it's the generated file
+    let mut expected_source_line: Option<usize> = None;
+
+    for line in lines {
+        let source_line = line.origin.and_then(|origin| {
+            let lines = code_lines.get(origin.section)?;
+            let line = origin.line?;
+            (lines.exact || line == 1).then_some(lines.start + line - 1)
+        });
+
+        let directive = match (source_line, expected_source_line) {
+            (Some(current), expected) if expected != Some(current) => {
+                Some(render_line_directive(template, current, source_file))
+            }
+            (None, Some(_)) => Some(render_line_directive(
+                template,
+                result.len() + 2,
+                generated_file,
+            )),
+            _ => None,
+        };
+        if let Some(directive) = directive {
+            result.push(TracedLine {
+                text: directive,
+                origin: None,
+            });
+        }
+
+        expected_source_line = source_line.map(|current| current + 1);
+        result.push(line);
+    }
+    result
+}
+
+/// A range of the generated file's lines which come from the same section
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SourceMapping {
+    /// the first line of the range, 1-based
+    pub start: usize,
+    /// the last line of the range, inclusive
+    pub end: usize,
+    /// the module's path, relative to the source directory
+    pub module: PathBuf,
+    /// index of the section within the module
+    pub section: usize,
+    /// the section's header without `#`s
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub header: Option<String>,
+    /// line within the section's code corresponding to the `start` line,
+    /// absent for the lines generated from the section's references
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub section_line: Option<usize>,
+}
+
+/// Maps the lines of a generated file back to the sections of the module.
+///
+/// It's stored next to the generated file as `<file>.lpmap`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SourceMap {
+    /// the source directory of the project, relative to the directory of the generated file,
+    /// so the map is read the same way from any current directory
+    pub source_dir: PathBuf,
+    pub mappings: Vec<SourceMapping>,
+}
+
+impl SourceMap {
+    /// Creates a source map for the lines of the final code of the module written to `generated_file`
+    pub fn new(
+        source_dir: &Path,
+        generated_file: &Path,
+        module: &Module,
+        lines: &[TracedLine],
+    ) -> Self {
+        let sections = module.sections.as_deref().unwrap_or_default();
+        let mut mappings: Vec<SourceMapping> = Vec::new();
+
+        for (index, line) in lines.iter().enumerate() {
+            let Some(origin) = line.origin else {
+                continue;
+            };
+            let line_number = index + 1;
+
+            if let Some(last) = mappings.last_mut() {
+                let continues_section_code = match (last.section_line, origin.line) {
+                    (Some(start), Some(current)) => start + (line_number - last.start) == current,
+                    (None, None) => true,
+                    _ => false,
+                };
+                if last.end + 1 == line_number
+                    && last.section == origin.section
+                    && continues_section_code
+                {
+                    last.end = line_number;
+                    continue;
+                }
+            }
+
+            mappings.push(SourceMapping {
+                start: line_number,
+                end: line_number,
+                module: module.path.clone(),
+                section: origin.section,
+                header: sections.get(origin.section).and_then(|section| {
+                    section
+                        .header
+                        .as_ref()
+                        .map(|h| h.trim_matches('#').trim().to_string())
+                }),
+                section_line: origin.line,
+            });
+        }
+
+        let generated_dir = generated_file.parent().unwrap_or(Path::new(""));
+        SourceMap {
+            source_dir: PathBuf::from(utils::relative_path(
+                &absolute(generated_dir),
+                &absolute(source_dir),
+            )),
+            mappings,
+        }
+    }
+
+    /// Returns the path of the source map for the generated file
+    pub fn path_for(generated_file: &Path) -> PathBuf {
+        let mut path = generated_file.as_os_str().to_owned();
+        path.push(format!(".{}", SOURCE_MAP_EXTENSION));
+        PathBuf::from(path)
+    }
+
+    /// Serializes the source map as it's written next to the generated file
+    pub fn to_json(&self) -> Result<String, LPError> {
+        serde_json::to_string(self).map_err(|e| LPError::Io(std::io::Error::other(e)))
+    }
+
+    /// Reads the source map of the generated file
+    pub fn read(generated_file: &Path) -> Result<Self, LPError> {
+        let path = Self::path_for(generated_file);
+        let content = std::fs::read_to_string(&path)
+            .map_err(|_| LPError::CannotReadFile(path.display().to_string()))?;
+        serde_json::from_str(&content)
+            .map_err(|_| LPError::CannotReadFile(path.display().to_string()))
+    }
+
+    /// Returns the mapping containing the line (1-based) of the generated file
+    /// and the corresponding line within the section's code, if any
+    pub fn locate(&self, line: usize) -> Option<(&SourceMapping, Option<usize>)> {
+        let mapping = self
+            .mappings
+            .iter()
+            .find(|mapping| mapping.start <= line && line <= mapping.end)?;
+        let section_line = mapping
+            .section_line
+            .map(|start| start + (line - mapping.start));
+        Some((mapping, section_line))
+    }
+}
+
+/// Returns the absolute path without touching the file system, the path itself if it can't be made absolute
+fn absolute(path: &Path) -> PathBuf {
+    std::path::absolute(Path::new(".").join(path)).unwrap_or_else(|_| path.to_path_buf())
+}
+
+/// Removes the `.` and `dir/..` components of the path without touching the file system
+fn normalize(path: &Path) -> PathBuf {
+    let mut result = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir
+                if matches!(result.components().next_back(), Some(Component::Normal(_))) =>
+            {
+                result.pop();
+            }
+            _ => result.push(component),
+        }
+    }
+    result
+}
+
+/// Answers where the line of a generated file comes from.
+///
+/// `location` is formatted as `<file>:<line>`, e.g. `targets/code/app/app.py:42`.
+pub fn locate(location: &str) -> Result<String, LPError> {
+    let invalid = || LPError::InvalidLocation(location.to_string());
+    let (file, line) = location.rsplit_once(':').ok_or_else(invalid)?;
+    let line: usize = line.parse().map_err(|_| invalid())?;
+
+    let source_map = SourceMap::read(Path::new(file))?;
+    let (mapping, section_line) = source_map.locate(line).ok_or_else(invalid)?;
+
+    let generated_dir = Path::new(file).parent().unwrap_or(Path::new(""));
+    let module = normalize(
+        &generated_dir
+            .join(&source_map.source_dir)
+            .join(&mapping.module),
+    );
+    let header = mapping
+        .header
+        .as_ref()
+        .map(|h| format!(" \"{}\"", h))
+        .unwrap_or_default();
+    Ok(match section_line {
+        Some(section_line) => format!(
+            "{}: section #{}{}, line {} of its code",
+            module.display(),
+            mapping.section,
+            header,
+            section_line
+        ),
+        None => format!(
+            "{}: section #{}{}, generated from its references",
+            module.display(),
+            mapping.section,
+            header
+        ),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builds::spec::structs::Section;
+    use std::rc::Rc;
+    use tempfile::tempdir;
+
+    fn origin(section: usize, line: Option<usize>) -> Option<LineOrigin> {
+        Some(LineOrigin { section, line })
+    }
+
+    fn traced(text: &str, origin: Option<LineOrigin>) -> TracedLine {
+        TracedLine {
+            text: text.to_string(),
+            origin,
+        }
+    }
+
+    fn create_module() -> Module {
+        let section = |header: Option<&str>| {
+            Rc::new(Section {
+                code: "".to_string(),
+                docs: "".to_string(),
+                header: header.map(|h| h.to_string()),
+                references: vec![],
+            })
+        };
+        Module {
+            path: PathBuf::from("app/app.py.lpnb"),
+            sections: Some(vec![section(Some("## Main App")), section(None)]),
+        }
+    }
+
+    #[test]
+    fn test_trace_text_and_join() {
+        let lines = trace_text("a\nb\n", origin(1, None));
+        assert_eq!(lines.len(), 3);
+        assert!(lines.iter().all(|l| l.origin == origin(1, None)));
+        assert_eq!(join_traced(&lines), "a\nb\n");
+    }
+
+    #[test]
+    fn test_trace_cleaned_code() {
+        let original = vec![
+            traced("import os", origin(0, None)),
+            traced("import os", origin(1, None)),
+            traced("x = 1", origin(0, Some(1))),
+            traced("", origin(0, Some(2))),
+            traced("y = 2", origin(1, Some(1))),
+        ];
+
+        let cleaned = trace_cleaned_code("import os\nx = 1\ny = 2\nimport sys", &original);
+        let origins: Vec<Option<LineOrigin>> = cleaned.iter().map(|l| l.origin).collect();
+        assert_eq!(
+            origins,
+            vec![
+                origin(0, None),
+                origin(0, Some(1)),
+                origin(1, Some(1)),
+                None
+            ]
+        );
+
+        let reordered = trace_cleaned_code("y = 2\nx = 1", &original);
+        assert_eq!(reordered[0].origin, origin(1, Some(1)));
+        assert_eq!(reordered[1].origin, origin(0, Some(1)));
+    }
+
+    #[test]
+    fn test_trace_cleaned_code_large_file() {
+        let original: Vec<TracedLine> = (1..=100_000)
+            .map(|i| traced(&format!("line {}", i % 10), origin(0, Some(i))))
+            .collect();
+        let cleaned = format!(
+            "changed\n{}",
+            join_traced(&original).replace("line 5", "five")
+        );
+
+        let traced_lines = trace_cleaned_code(&cleaned, &original);
+        assert_eq!(traced_lines[0].origin, None);
+        assert_eq!(traced_lines[1].origin, origin(0, Some(1)));
+        assert_eq!(traced_lines[5].origin, None);
+        assert_eq!(traced_lines[100_000].origin, origin(0, Some(100_000)));
+    }
+
+    #[test]
+    fn test_insert_line_directives() {
+        let lines = vec![
+            traced("#include \"lib.h\"", origin(0, None)),
+            traced("int main() {", origin(0, Some(1))),
+            traced("    return f();", origin(0, Some(2))),
+            traced("}", origin(0, Some(3))),
+            traced("int f() { return 0; }", origin(1, Some(1))),
+            traced("// added by plugin", None),
+        ];
+
+        let result = insert_line_directives(
+            lines,
+            "#line {line} \"{file}\"",
+            Path::new("src/main.c.lpnb"),
+            Path::new("targets/code/main.c"),
+            &[
+                CodeLines {
+                    start: 10,
+                    exact: true,
+                },
+                CodeLines {
+                    start: 20,
+                    exact: false,
+                },
+            ],
+        );
+        assert_eq!(
+            join_traced(&result),
+            [
+                "#include \"lib.h\"",
+                "#line 10 \"src/main.c.lpnb\"",
+                "int main() {",
+                "    return f();",
+                "}",
+                "#line 20 \"src/main.c.lpnb\"",
+                "int f() { return 0; }",
+                "#line 9 \"targets/code/main.c\"",
+                "// added by plugin",
+            ]
+            .join("\n")
+        );
+    }
+
+    #[test]
+    fn test_insert_line_directives_after_reordering() {
+        let lines = vec![
+            traced("b", origin(0, Some(2))),
+            traced("a", origin(0, Some(1))),
+            traced("c", origin(0, Some(3))),
+        ];
+
+        let result = insert_line_directives(
+            lines,
+            "# {line}",
+            Path::new("m.lpnb"),
+            Path::new("m"),
+            &[CodeLines {
+                start: 5,
+                exact: true,
+            }],
+        );
+        assert_eq!(join_traced(&result), "# 6\nb\n# 5\na\n# 7\nc");
+    }
+
+    #[test]
+    fn test_insert_line_directives_first_line_only() {
+        let lines = vec![
+            traced("a", origin(0, Some(1))),
+            traced("b", origin(0, Some(2))),
+        ];
+
+        let result = insert_line_directives(
+            lines,
+            "# {line} {file}",
+            Path::new("m.lpnb"),
+            Path::new("m"),
+            &[CodeLines {
+                start: 5,
+                exact: false,
+            }],
+        );
+        assert_eq!(join_traced(&result), "# 5 m.lpnb\na\n# 4 m\nb");
+    }
+
+    #[test]
+    fn test_source_map_new() {
+        let lines = vec![
+            traced("from lib import f", origin(0, None)),
+            traced("def main():", origin(0, Some(1))),
+            traced("    f()", origin(0, Some(2))),
+            traced("# changed by plugin", None),
+            traced("main()", origin(1, Some(1))),
+            traced("exit()", origin(1, Some(3))),
+        ];
+
+        let source_map = SourceMap::new(
+            Path::new("src"),
+            Path::new("targets/code/app/app.py"),
+            &create_module(),
+            &lines,
+        );
+        assert_eq!(source_map.source_dir, PathBuf::from("../../../src"));
+        let ranges: Vec<(usize, usize, usize, Option<usize>)> = source_map
+            .mappings
+            .iter()
+            .map(|m| (m.start, m.end, m.section, m.section_line))
+            .collect();
+        assert_eq!(
+            ranges,
+            vec![
+                (1, 1, 0, None),
+                (2, 3, 0, Some(1)),
+                (5, 5, 1, Some(1)),
+                (6, 6, 1, Some(3))
+            ]
+        );
+        assert_eq!(source_map.mappings[0].header, Some("Main App".to_string()));
+        assert_eq!(source_map.mappings[2].header, None);
+
+        assert_eq!(source_map.locate(3).unwrap().1, Some(2));
+        assert_eq!(source_map.locate(1).unwrap().1, None);
+        assert!(source_map.locate(4).is_none());
+    }
+
+    #[test]
+    fn test_write_read_and_locate() {
+        let dir = tempdir().unwrap();
+        let generated = dir.path().join("app.py");
+        let lines = vec![
+            traced("import lib", origin(0, None)),
+            traced("def main():", origin(0, Some(1))),
+            traced("    pass", origin(0, Some(2))),
+        ];
+        let source_map = SourceMap::new(Path::new("src"), &generated, &create_module(), &lines);
+        assert!(source_map.source_dir.is_relative());
+        std::fs::write(
+            SourceMap::path_for(&generated),
+            source_map.to_json().unwrap(),
+        )
+        .unwrap();
+
+        assert!(dir.path().join("app.py.lpmap").exists());
+        assert_eq!(SourceMap::read(&generated).unwrap(), source_map);
+
+        let location = format!("{}:3", generated.display());
+        assert_eq!(
+            locate(&location).unwrap(),
+            format!(
+                "{}: section #0 \"Main App\", line 2 of its code",
+                absolute(Path::new("src/app/app.py.lpnb")).display()
+            )
+        );
+        let location = format!("{}:1", generated.display());
+        assert!(locate(&location)
+            .unwrap()
+            .ends_with("generated from its references"));
+
+        assert!(matches!(
+            locate(&format!("{}:10", generated.display())),
+            Err(LPError::InvalidLocation(_))
+        ));
+        assert!(matches!(
+            locate("no_line_number"),
+            Err(LPError::InvalidLocation(_))
+        ));
+    }
+}
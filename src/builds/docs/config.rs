@@ -2,10 +2,13 @@
 
 use std::path::PathBuf;
 
+use crate::config::project_file::DocsFormat;
+
 /// Configuration for the documentation build.
 pub struct Config {
     pub target_docs_dir: PathBuf,
     pub source_dir: PathBuf,
+    pub format: DocsFormat,
 }
 
 impl Config {
@@ -13,10 +16,12 @@ impl Config {
     /// # Arguments
     /// * `target_docs_dir` - the directory where the documentation will be generated.
     /// * `source_dir` - the source project directory.
-    pub fn new(target_docs_dir: PathBuf, source_dir: PathBuf) -> Self {
+    /// * `format` - the format of the pages.
+    pub fn new(target_docs_dir: PathBuf, source_dir: PathBuf, format: DocsFormat) -> Self {
         Self {
             target_docs_dir,
             source_dir,
+            format,
         }
     }
 }
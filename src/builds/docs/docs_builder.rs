@@ -1,30 +1,75 @@
 #![forbid(unsafe_code)]
 
-use std::{path::PathBuf, rc::Rc};
+use std::{
+    collections::HashMap,
+    ops::Range,
+    path::{Path, PathBuf},
+    rc::Rc,
+};
 
 use mockall::predicate::str;
+use pulldown_cmark::{Event, LinkType, Parser, Tag};
 
 use crate::{
     builds::{
-        docs::config::Config,
+        cache::{hash_content, hash_index_reads, hash_module_inputs, BuildCache},
+        code::plugins::caller::PluginsCaller,
+        docs::{
+            config::Config,
+            html::{escape, escape_link, markdown_to_html, Site, SiteEntry, TocEntry, STYLE},
+            search::{index_json, search_page_content, standalone_search_page, SearchEntry},
+        },
+        index::ProjectIndex,
         spec::{
-            structs::{Project, Section},
+            chunks::parse_placeholder,
+            structs::{Module, Project, Section},
             utils,
         },
     },
+    config::{
+        constants::{
+            DOCS_INDEX_PAGE_NAME, DOCS_STYLE_FILE_NAME, SEARCH_INDEX_FILE_NAME, SEARCH_PAGE_NAME,
+            SYMBOL_INDEX_PAGE_NAME,
+        },
+        project_file::DocsFormat,
+    },
     error::LPError,
 };
 
+/// An exported name listed on the symbol index page
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord)]
+struct Symbol {
+    name: String,
+    /// the link to the section exporting the name, relative to the docs directory
+    link: String,
+    /// the module and the section's header, as they are displayed
+    module: String,
+    header: String,
+}
+
 /// DocsBuilder is a struct that is responsible for building the documentation from the source project.
 pub struct DocsBuilder {
     config: Config,
     project: Rc<Project>,
+    index: Rc<ProjectIndex>,
+    /// the plugins which may render the sections' docs instead of the default rendering
+    plugins_caller: Rc<PluginsCaller>,
 }
 
 impl DocsBuilder {
     /// Creates a new DocsBuilder instance.
-    pub fn new(config: Config, project: Rc<Project>) -> Self {
-        Self { config, project }
+    pub fn new(
+        config: Config,
+        project: Rc<Project>,
+        index: Rc<ProjectIndex>,
+        plugins_caller: Rc<PluginsCaller>,
+    ) -> Self {
+        Self {
+            config,
+            project,
+            index,
+            plugins_caller,
+        }
     }
 
     /// returns target path and an original extension, file's name if no extension
@@ -43,11 +88,12 @@ impl DocsBuilder {
             .to_str()
             .unwrap_or("")
             .to_string();
+        let page_extension = self.config.format.page_extension();
         if cleaned_res != result {
             if extension.is_empty() {
-                (cleaned_res.with_extension("md"), file_name)
+                (cleaned_res.with_extension(page_extension), file_name)
             } else {
-                (cleaned_res.with_extension("md"), extension)
+                (cleaned_res.with_extension(page_extension), extension)
             }
         } else {
             (cleaned_res, extension)
@@ -60,48 +106,630 @@ impl DocsBuilder {
         result
     }
 
-    fn prepare_final_docs(&self, sections: &[Rc<Section>], extension: &str) -> String {
-        sections
+    /// Returns the link to the referenced section's docs, relative to the current module's docs.
+    ///
+    /// Returns None if the link is not a reference to another module's section.
+    fn resolve_reference_link(&self, module: &Module, dest: &str) -> Option<String> {
+        let (path, header) = dest.split_once('#')?;
+        if path.is_empty() {
+            return None;
+        }
+
+        let referenced_module_path = module.resolve_relative_module_path(path.as_ref());
+        self.index.get_section(&referenced_module_path, header)?;
+        let referenced_module = self.index.get_module(&referenced_module_path)?;
+
+        let (current_target, _) = self.prepare_target_path(&module.path);
+        let (referenced_target, _) = self.prepare_target_path(&referenced_module.path);
+        let relative = utils::relative_path(current_target.parent()?, &referenced_target);
+
+        Some(format!("{}#{}", relative, header))
+    }
+
+    /// Returns the path of the page or the copied file of the module, relative to the docs directory
+    fn site_path(&self, module: &Module) -> PathBuf {
+        let (target_path, _) = self.prepare_target_path(&module.path);
+        target_path
+            .strip_prefix(&self.config.target_docs_dir)
+            .map(Path::to_path_buf)
+            .unwrap_or(target_path)
+    }
+
+    /// Returns the name the module is displayed with, e.g. `lib/primes.py` for `lib/primes.py.lpnb`
+    fn display_name(module: &Module) -> String {
+        utils::prepare_module_file_extension(&module.path)
+            .to_string_lossy()
+            .to_string()
+    }
+
+    /// Returns the site of the HTML documentation listing all the modules, None for the other formats
+    fn prepare_site(&self) -> Option<Site> {
+        if self.config.format != DocsFormat::Html {
+            return None;
+        }
+        let entries = self
+            .project
+            .modules
+            .iter()
+            .map(|module| SiteEntry {
+                path: self.site_path(module),
+                label: utils::prepare_module_file_extension(&module.path)
+                    .file_name()
+                    .unwrap_or_default()
+                    .to_string_lossy()
+                    .to_string(),
+            })
+            .collect();
+        Some(Site::new(entries))
+    }
+
+    /// Returns the table of contents of the module's page, made of the sections' headers
+    fn prepare_toc(module: &Module) -> Vec<TocEntry> {
+        module
+            .sections
+            .as_deref()
+            .unwrap_or_default()
+            .iter()
+            .filter_map(|section| {
+                let header = section.header.as_ref()?.trim();
+                Some(TocEntry {
+                    level: header.chars().take_while(|c| *c == '#').count().max(1),
+                    text: header.trim_matches('#').trim().to_string(),
+                    anchor: section.get_header()?,
+                })
+            })
+            .collect()
+    }
+
+    /// Returns the content of the page at `target_path` in the documentation's format
+    fn prepare_page(
+        &self,
+        site: Option<&Site>,
+        target_path: &Path,
+        title: &str,
+        toc: &[TocEntry],
+        markdown: String,
+    ) -> String {
+        match site {
+            Some(site) => {
+                let page = target_path
+                    .strip_prefix(&self.config.target_docs_dir)
+                    .unwrap_or(target_path);
+                site.render_page(page, title, toc, &markdown_to_html(&markdown))
+            }
+            None => markdown,
+        }
+    }
+
+    /// Returns the entry page of the HTML documentation listing the modules' pages
+    fn prepare_index(&self, with_symbols: bool) -> String {
+        let mut page = "# Documentation\n".to_string();
+        for module in &self.project.modules {
+            if module.sections.is_none() {
+                continue;
+            }
+            let link = utils::relative_path(Path::new(""), &self.site_path(module));
+            page.push_str(&format!("\n- [{}](<{}>)", Self::display_name(module), link));
+        }
+        page.push_str(&format!("\n\n[Search]({}.html)", SEARCH_PAGE_NAME));
+        if with_symbols {
+            page.push_str(&format!(
+                ", [Symbols]({}.{})",
+                SYMBOL_INDEX_PAGE_NAME,
+                self.config.format.page_extension()
+            ));
+        }
+        page
+    }
+
+    /// Returns the search index entries of all the sections of the project
+    fn prepare_search_index(&self) -> Vec<SearchEntry> {
+        let mut entries = vec![];
+        for module in &self.project.modules {
+            let Some(sections) = &module.sections else {
+                continue;
+            };
+            let name = Self::display_name(module);
+            let page = utils::relative_path(Path::new(""), &self.site_path(module));
+            entries.extend(
+                sections
+                    .iter()
+                    .map(|section| SearchEntry::new(&name, &page, section)),
+            );
+        }
+        entries
+    }
+
+    /// Writes the search index and the search page querying it
+    fn write_search(&self, site: Option<&Site>, cache: &mut BuildCache) -> Result<(), LPError> {
+        let index = index_json(&self.prepare_search_index());
+        cache.write(
+            &self.config.target_docs_dir.join(SEARCH_INDEX_FILE_NAME),
+            &hash_content(index.as_bytes()),
+            index.as_bytes(),
+        )?;
+
+        let content = search_page_content(&index);
+        let page_path = PathBuf::from(SEARCH_PAGE_NAME).with_extension("html");
+        let page = match site {
+            Some(site) => site.render_page(&page_path, "Search", &[], &content),
+            None => standalone_search_page(&content),
+        };
+        cache.write(
+            &self.config.target_docs_dir.join(page_path),
+            &hash_content(page.as_bytes()),
+            page.as_bytes(),
+        )
+    }
+
+    /// Rewrites the destinations of the references in the docs, so they point to the generated pages.
+    /// Other links are left as is.
+    ///
+    /// The destinations of the inline links and of the link reference definitions are replaced
+    /// in the source, so the rest of the markdown stays untouched.
+    fn rewrite_links(&self, module: &Module, docs: &str) -> String {
+        let parser = Parser::new(docs);
+        let mut replacements = Vec::new();
+
+        for (_, definition) in parser.reference_definitions().iter() {
+            let Some(new_dest) = self.resolve_reference_link(module, &definition.dest) else {
+                continue;
+            };
+            let label_end = link_label_end(docs, definition.span.start);
+            let dest = label_end
+                .filter(|&end| docs[end..].starts_with("]:"))
+                .and_then(|end| link_destination(docs, end + 2));
+            if let Some((range, angled)) = dest {
+                replacements.push((range, format_destination(&new_dest, angled)));
+            }
+        }
+
+        let events: Vec<_> = parser.into_offset_iter().collect();
+        for (i, (event, range)) in events.iter().enumerate() {
+            let Event::Start(Tag::Link(LinkType::Inline, dest, _)) = event else {
+                continue;
+            };
+            let Some(new_dest) = self.resolve_reference_link(module, dest) else {
+                continue;
+            };
+            let text_end = events[i + 1..]
+                .iter()
+                .take_while(|(event, _)| !matches!(event, Event::End(Tag::Link(..))))
+                .map(|(_, range)| range.end)
+                .max()
+                .unwrap_or(range.start + 1);
+            let dest = docs[text_end..range.end]
+                .starts_with("](")
+                .then(|| link_destination(docs, text_end + 2))
+                .flatten();
+            if let Some((range, angled)) = dest {
+                replacements.push((range, format_destination(&new_dest, angled)));
+            }
+        }
+
+        replacements.sort_by_key(|(range, _)| range.start);
+        let mut result = String::new();
+        let mut copied_until = 0;
+        for (range, new_dest) in replacements {
+            result.push_str(&docs[copied_until..range.start]);
+            result.push_str(&new_dest);
+            copied_until = range.end;
+        }
+        result.push_str(&docs[copied_until..]);
+        result
+    }
+
+    /// Returns the code block of the section's code.
+    ///
+    /// Links can't be put inside a markdown code block, so the code using chunks is rendered as an HTML block
+    /// where each placeholder links to the section defining the chunk.
+    fn prepare_code_block(module: &Module, extension: &str, code: &str) -> String {
+        let chunk_link = |line: &str| {
+            let (indent, name) = parse_placeholder(line)?;
+            let anchor = module.find_chunk(name)?.get_header()?;
+            Some(format!(
+                "{}<a href=\"#{}\">&lt;&lt;{}&gt;&gt;</a>",
+                indent,
+                escape_link(&anchor),
+                escape(name)
+            ))
+        };
+        if !code.lines().any(|line| chunk_link(line).is_some()) {
+            return format!("```{}\n{}\n```", extension, code);
+        }
+
+        let lines = code
+            .split('\n')
+            .map(|line| chunk_link(line).unwrap_or_else(|| escape(line)))
+            .collect::<Vec<String>>()
+            .join("\n");
+        format!(
+            "<pre><code class=\"language-{}\">{}</code></pre>",
+            escape(extension),
+            lines
+        )
+    }
+
+    /// Returns the list of the names the section exports, if the plugins reported any
+    fn prepare_exports(
+        section: &Section,
+        exports: &HashMap<String, Vec<String>>,
+    ) -> Option<String> {
+        let names = exports.get(&section.get_header()?)?;
+        let names = names
             .iter()
-            .map(|s| format!("{}\n```{}\n{}\n```", s.docs, extension, s.code))
+            .map(|name| format!("`{}`", name))
             .collect::<Vec<String>>()
-            .join("\n")
+            .join(", ");
+        Some(format!("Exports: {}", names))
+    }
+
+    /// Returns the links to the sections referencing the headed section, if there are any
+    fn prepare_backlinks(&self, module: &Module, section: &Section) -> Option<String> {
+        let header = section.get_header()?;
+        let backlinks = self
+            .index
+            .get_backlinks(&utils::module_name(&module.path), &header);
+        if backlinks.is_empty() {
+            return None;
+        }
+
+        let (current_target, _) = self.prepare_target_path(&module.path);
+        let current_dir = current_target.parent()?;
+        let links = backlinks
+            .iter()
+            .map(|backlink| {
+                let (target, _) = self.prepare_target_path(&backlink.module.path);
+                let page = utils::relative_path(current_dir, &target);
+                let name = Self::display_name(&backlink.module);
+                match (&backlink.section.header, backlink.section.get_header()) {
+                    (Some(header), Some(anchor)) => format!(
+                        "[{}: {}]({}#{})",
+                        name,
+                        header.trim_matches('#').trim(),
+                        page,
+                        anchor
+                    ),
+                    _ => format!("[{}]({})", name, page),
+                }
+            })
+            .collect::<Vec<String>>()
+            .join(", ");
+        Some(format!("Referenced by: {}", links))
+    }
+
+    /// Returns the module's exported symbols to be listed on the symbol index page
+    fn collect_symbols(
+        &self,
+        module: &Module,
+        exports: &HashMap<String, Vec<String>>,
+    ) -> Vec<Symbol> {
+        let (target_path, _) = self.prepare_target_path(&module.path);
+        let page = utils::relative_path(&self.config.target_docs_dir, &target_path);
+        let mut symbols = vec![];
+        for section in module.sections.as_deref().unwrap_or_default() {
+            let (Some(anchor), Some(header)) = (section.get_header(), &section.header) else {
+                continue;
+            };
+            for name in exports.get(&anchor).map(Vec::as_slice).unwrap_or_default() {
+                symbols.push(Symbol {
+                    name: name.clone(),
+                    link: format!("{}#{}", page, anchor),
+                    module: Self::display_name(module),
+                    header: header.trim_matches('#').trim().to_string(),
+                });
+            }
+        }
+        symbols
+    }
+
+    /// Returns the symbol index page listing all the exported names, sorted
+    fn prepare_symbol_index(mut symbols: Vec<Symbol>) -> String {
+        symbols.sort();
+        let mut page = "# Symbols\n".to_string();
+        for symbol in symbols {
+            page.push_str(&format!(
+                "\n- [`{}`]({}) — `{}`, {}",
+                symbol.name, symbol.link, symbol.module, symbol.header
+            ));
+        }
+        page
+    }
+
+    /// `exports` are the names exported by the module's sections, by the sections' headers.
+    ///
+    /// A section's docs and code are rendered by the plugin's `render_section_docs`, if it declares one,
+    /// or as the docs followed by the code block otherwise.
+    /// The exports and the sections referencing it are listed after each section.
+    fn prepare_final_docs(
+        &self,
+        module: &Module,
+        extension: &str,
+        exports: &HashMap<String, Vec<String>>,
+    ) -> Result<String, LPError> {
+        let sections = module.sections.as_deref().unwrap_or_default();
+        let mut result = Vec::with_capacity(sections.len());
+        for (i, s) in sections.iter().enumerate() {
+            let section_docs = self.rewrite_links(module, &s.docs);
+            let rendered = self
+                .plugins_caller
+                .call_plugin_render_docs_func(
+                    extension,
+                    &module.path,
+                    s.header.as_deref(),
+                    &section_docs,
+                    &s.code,
+                )
+                .map_err(|e| e.in_section(&module.path, Some(i), s.header.as_deref()))?;
+            let mut docs = rendered.unwrap_or_else(|| {
+                format!(
+                    "{}\n{}",
+                    section_docs,
+                    Self::prepare_code_block(module, extension, &s.code)
+                )
+            });
+            if let Some(exports) = Self::prepare_exports(s, exports) {
+                docs.push_str(&format!("\n\n{}", exports));
+            }
+            if let Some(backlinks) = self.prepare_backlinks(module, s) {
+                docs.push_str(&format!("\n\n{}", backlinks));
+            }
+            result.push(docs);
+        }
+        Ok(result.join("\n"))
     }
 
     /// The main method of the DocsBuilder that builds the documentation.
-    /// It prepares the markdown files from the source project and writes them to the target directory.
+    /// It prepares the pages from the source project and writes them to the target directory.
     /// If the module has no sections, it just copies the source file to the target directory.
-    pub fn build(&self) -> Result<(), LPError> {
+    /// A module is rebuilt only if it, a section it references, the exports of its sections,
+    /// the sections referencing it or its plugin changed since the build recorded in `cache`.
+    ///
+    /// The exports are taken from the index, i.e. the plugins' `get_exports`,
+    /// and from the `cache`, i.e. the plugins' `get_import_code`.
+    /// If there are any, the symbol index page listing them all is written as well.
+    /// The search index over all the sections and the search page are written every time.
+    ///
+    /// In the HTML format every page also depends on the tree of the modules shown in its sidebar,
+    /// and the stylesheet and the entry page (unless a module's page takes its place) are written too.
+    pub fn build(&self, cache: &mut BuildCache) -> Result<(), LPError> {
+        let site = self.prepare_site();
+        let site_fingerprint = site.as_ref().map(Site::fingerprint);
+        let mut symbols = vec![];
         for module in &self.project.modules {
             let source_path = self.get_module_source_path(&module.path);
             let (target_path, extension) = self.prepare_target_path(&module.path);
             if let Some(parent) = target_path.parent() {
                 std::fs::create_dir_all(parent)?;
             }
-            if let Some(sections) = &module.sections {
-                std::fs::write(
-                    target_path,
-                    self.prepare_final_docs(sections, extension.as_str()),
-                )?;
+            if module.sections.is_some() {
+                let source = std::fs::read(&source_path)?;
+                let module_name = utils::module_name(&module.path);
+                let mut exports = cache.module_exports(&module_name);
+                exports.extend(self.index.get_exports(&module_name));
+                symbols.extend(self.collect_symbols(module, &exports));
+                let mut hasher = hash_module_inputs(module, &self.index, &source);
+                hasher.update(&self.plugins_caller.plugin_fingerprint(&extension));
+                if let Some(site_fingerprint) = &site_fingerprint {
+                    hasher.update(site_fingerprint.as_bytes());
+                }
+                for section in module.sections.as_deref().unwrap_or_default() {
+                    let Some(header) = section.get_header() else {
+                        continue;
+                    };
+                    for backlink in self.index.get_backlinks(&module_name, &header) {
+                        hasher
+                            .update(backlink.module.path.to_string_lossy().as_bytes())
+                            .update(
+                                backlink
+                                    .section
+                                    .header
+                                    .as_deref()
+                                    .unwrap_or_default()
+                                    .as_bytes(),
+                            );
+                    }
+                }
+                let mut sorted_exports: Vec<_> = exports.iter().collect();
+                sorted_exports.sort();
+                for (header, names) in sorted_exports {
+                    hasher.update(header.as_bytes());
+                    for name in names {
+                        hasher.update(name.as_bytes());
+                    }
+                }
+                let inputs = hasher.finish();
+                let previous_inputs =
+                    hash_index_reads(&inputs, &cache.index_reads(&target_path), &self.index);
+                if cache.is_fresh(&target_path, &previous_inputs) {
+                    continue;
+                }
+                self.index.take_reads();
+                let markdown = self.prepare_final_docs(module, extension.as_str(), &exports)?;
+                let reads = self.index.take_reads();
+                let inputs = hash_index_reads(&inputs, &reads, &self.index);
+                let page = self.prepare_page(
+                    site.as_ref(),
+                    &target_path,
+                    &Self::display_name(module),
+                    &Self::prepare_toc(module),
+                    markdown,
+                );
+                cache.write(&target_path, &inputs, page.as_bytes())?;
+                cache.record_index_reads(&target_path, reads);
             } else {
-                std::fs::copy(source_path, target_path)?;
+                cache.copy(&source_path, &target_path)?;
+            }
+        }
+
+        let with_symbols = !symbols.is_empty();
+        let page_extension = self.config.format.page_extension();
+        let mut extra_pages = vec![];
+        if with_symbols {
+            extra_pages.push((
+                SYMBOL_INDEX_PAGE_NAME,
+                "Symbols",
+                Self::prepare_symbol_index(symbols),
+            ));
+        }
+        if site.is_some() {
+            let index_path = PathBuf::from(DOCS_INDEX_PAGE_NAME).with_extension(page_extension);
+            let index_taken = self
+                .project
+                .modules
+                .iter()
+                .any(|module| self.site_path(module) == index_path);
+            if !index_taken {
+                extra_pages.push((
+                    DOCS_INDEX_PAGE_NAME,
+                    "Documentation",
+                    self.prepare_index(with_symbols),
+                ));
             }
+            cache.write(
+                &self.config.target_docs_dir.join(DOCS_STYLE_FILE_NAME),
+                &hash_content(STYLE.as_bytes()),
+                STYLE.as_bytes(),
+            )?;
+        }
+        for (name, title, markdown) in extra_pages {
+            let path = self
+                .config
+                .target_docs_dir
+                .join(name)
+                .with_extension(page_extension);
+            let page = self.prepare_page(site.as_ref(), &path, title, &[], markdown);
+            cache.write(&path, &hash_content(page.as_bytes()), page.as_bytes())?;
         }
-        Ok(())
+        self.write_search(site.as_ref(), cache)
+    }
+}
+
+/// Returns the position of the `]` closing the link label starting at `start`
+fn link_label_end(source: &str, start: usize) -> Option<usize> {
+    let mut chars = source[start..].char_indices();
+    if chars.next()?.1 != '[' {
+        return None;
+    }
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '\\' => {
+                chars.next();
+            }
+            ']' => return Some(start + i),
+            _ => {}
+        }
+    }
+    None
+}
+
+/// Returns the source range of the link destination after `start` and whether it's enclosed in `<>`.
+///
+/// The whitespaces before the destination, including a line break, are skipped.
+fn link_destination(source: &str, start: usize) -> Option<(Range<usize>, bool)> {
+    let rest = &source[start..];
+    let start = start + (rest.len() - rest.trim_start().len());
+    let mut chars = source[start..].char_indices().peekable();
+
+    if chars.next_if(|&(_, c)| c == '<').is_some() {
+        while let Some((i, c)) = chars.next() {
+            match c {
+                '\\' => {
+                    chars.next();
+                }
+                '>' => return Some((start..start + i + 1, true)),
+                '<' | '\n' => return None,
+                _ => {}
+            }
+        }
+        return None;
+    }
+
+    let mut depth = 0;
+    let mut end = source.len() - start;
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '\\' => {
+                chars.next();
+            }
+            '(' => depth += 1,
+            ')' if depth == 0 => {
+                end = i;
+                break;
+            }
+            ')' => depth -= 1,
+            c if c.is_whitespace() || c.is_control() => {
+                end = i;
+                break;
+            }
+            _ => {}
+        }
+    }
+    (end > 0).then_some((start..start + end, false))
+}
+
+/// Returns the destination as it should be written in the markdown,
+/// it's enclosed in `<>` if the original one was or if it contains spaces
+fn format_destination(dest: &str, angled: bool) -> String {
+    if angled || dest.contains(char::is_whitespace) {
+        format!("<{}>", dest.replace('<', "\\<").replace('>', "\\>"))
+    } else {
+        dest.replace('(', "\\(").replace(')', "\\)")
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::config::constants::SYSTEM_FILES_EXTENSION;
+    use std::path::Path;
+
+    use crate::builds::spec::structs::Reference;
+    use crate::config::{constants::SYSTEM_FILES_EXTENSION, project_file::PluginsSettings};
+    use tempfile::TempDir;
 
     use super::*;
 
+    fn create_builder_with_plugins(project: Rc<Project>, plugins_dir: &Path) -> DocsBuilder {
+        let config = Config::new(
+            PathBuf::from("/target"),
+            PathBuf::from("/source"),
+            DocsFormat::Markdown,
+        );
+        let index = Rc::new(ProjectIndex::new(Rc::clone(&project)));
+        let plugins_caller = Rc::new(
+            PluginsCaller::new(plugins_dir, &PluginsSettings::default(), Rc::clone(&index))
+                .unwrap(),
+        );
+        DocsBuilder::new(config, project, index, plugins_caller)
+    }
+
+    fn create_builder(project: Rc<Project>) -> DocsBuilder {
+        create_builder_with_plugins(project, Path::new("/nonexistent/plugins"))
+    }
+
+    fn module(path: &str, sections: Vec<Rc<Section>>) -> Module {
+        Module {
+            path: PathBuf::from(path),
+            sections: Some(sections),
+        }
+    }
+
+    fn section(
+        header: Option<&str>,
+        docs: &str,
+        code: &str,
+        references: Vec<Reference>,
+    ) -> Rc<Section> {
+        Rc::new(Section {
+            code: code.to_string(),
+            docs: docs.to_string(),
+            header: header.map(String::from),
+            references,
+        })
+    }
+
     #[test]
     fn test_prepare_target_path_with_extension() {
-        let config = Config::new(PathBuf::from("/target"), PathBuf::from("/source"));
-        let project = Rc::new(Project { modules: vec![] });
-        let builder = DocsBuilder::new(config, project);
+        let builder = create_builder(Rc::new(Project { modules: vec![] }));
 
         let path = PathBuf::from(format!("module.rs.{}", SYSTEM_FILES_EXTENSION));
         let (target_path, extension) = builder.prepare_target_path(&path);
@@ -112,9 +740,7 @@ mod tests {
 
     #[test]
     fn test_prepare_target_path_without_extension() {
-        let config = Config::new(PathBuf::from("/target"), PathBuf::from("/source"));
-        let project = Rc::new(Project { modules: vec![] });
-        let builder = DocsBuilder::new(config, project);
+        let builder = create_builder(Rc::new(Project { modules: vec![] }));
 
         let path = PathBuf::from(format!("Makefile.{}", SYSTEM_FILES_EXTENSION));
         let (target_path, extension) = builder.prepare_target_path(&path);
@@ -125,9 +751,7 @@ mod tests {
 
     #[test]
     fn test_prepare_target_path_nested_path() {
-        let config = Config::new(PathBuf::from("/target"), PathBuf::from("/source"));
-        let project = Rc::new(Project { modules: vec![] });
-        let builder = DocsBuilder::new(config, project);
+        let builder = create_builder(Rc::new(Project { modules: vec![] }));
 
         let path = PathBuf::from(format!("dir/subdir/module.py.{}", SYSTEM_FILES_EXTENSION));
         let (target_path, extension) = builder.prepare_target_path(&path);
@@ -138,9 +762,7 @@ mod tests {
 
     #[test]
     fn test_get_module_source_path() {
-        let config = Config::new(PathBuf::from("/target"), PathBuf::from("/source"));
-        let project = Rc::new(Project { modules: vec![] });
-        let builder = DocsBuilder::new(config, project);
+        let builder = create_builder(Rc::new(Project { modules: vec![] }));
 
         let module_path = PathBuf::from(format!("dir/module.rs.{}", SYSTEM_FILES_EXTENSION));
         let source_path = builder.get_module_source_path(&module_path);
@@ -153,9 +775,7 @@ mod tests {
 
     #[test]
     fn test_prepare_final_docs_single_section() {
-        let config = Config::new(PathBuf::from("/target"), PathBuf::from("/source"));
-        let project = Rc::new(Project { modules: vec![] });
-        let builder = DocsBuilder::new(config, project);
+        let builder = create_builder(Rc::new(Project { modules: vec![] }));
 
         let section = Rc::new(Section {
             code: "fn hello() {}".to_string(),
@@ -164,7 +784,13 @@ mod tests {
             references: vec![],
         });
 
-        let result = builder.prepare_final_docs(&[section], "rs");
+        let result = builder
+            .prepare_final_docs(
+                &module("main.rs.lpnb", vec![section]),
+                "rs",
+                &HashMap::new(),
+            )
+            .unwrap();
         let expected = "# Hello Function\n```rs\nfn hello() {}\n```";
 
         assert_eq!(result, expected);
@@ -172,9 +798,7 @@ mod tests {
 
     #[test]
     fn test_prepare_final_docs_multiple_sections() {
-        let config = Config::new(PathBuf::from("/target"), PathBuf::from("/source"));
-        let project = Rc::new(Project { modules: vec![] });
-        let builder = DocsBuilder::new(config, project);
+        let builder = create_builder(Rc::new(Project { modules: vec![] }));
 
         let section1 = Rc::new(Section {
             code: "fn hello() {}".to_string(),
@@ -190,9 +814,295 @@ mod tests {
             references: vec![],
         });
 
-        let result = builder.prepare_final_docs(&[section1, section2], "rs");
+        let result = builder
+            .prepare_final_docs(
+                &module("main.rs.lpnb", vec![section1, section2]),
+                "rs",
+                &HashMap::new(),
+            )
+            .unwrap();
         let expected = "# Hello Function\n```rs\nfn hello() {}\n```\n# World Function\n```rs\nfn world() {}\n```";
 
         assert_eq!(result, expected);
     }
+
+    #[test]
+    fn test_rewrite_links() {
+        let app = Rc::new(Module {
+            path: PathBuf::from("app/app.py.lpnb"),
+            sections: Some(vec![Rc::new(Section {
+                code: "class App: pass".to_string(),
+                docs: "# Main App".to_string(),
+                header: Some("# Main App".to_string()),
+                references: vec![],
+            })]),
+        });
+        let builder = create_builder(Rc::new(Project {
+            modules: vec![Rc::clone(&app)],
+        }));
+        let main = module(
+            "entry/main.py.lpnb",
+            vec![Rc::new(Section {
+                code: "".to_string(),
+                docs: "".to_string(),
+                header: None,
+                references: vec![Reference {
+                    path: PathBuf::from("../app/app"),
+                    header: "Main-App".to_string(),
+                    text: "App".to_string(),
+                }],
+            })],
+        );
+
+        let docs = "Uses [App](../app/app#Main-App), [spec](../static/spec.txt), \
+                    [local](#Local) and [missing](../app/app#Nothing)";
+        assert_eq!(
+            builder.rewrite_links(&main, docs),
+            "Uses [App](../app/app.md#Main-App), [spec](../static/spec.txt), \
+             [local](#Local) and [missing](../app/app#Nothing)"
+        );
+
+        let same_dir = module("app/other.py.lpnb", vec![]);
+        assert_eq!(
+            builder.rewrite_links(&same_dir, "[App](app#Main-App \"title\")"),
+            "[App](app.md#Main-App \"title\")"
+        );
+    }
+
+    #[test]
+    fn test_rewrite_links_forms() {
+        let app = Rc::new(Module {
+            path: PathBuf::from("app/app.py.lpnb"),
+            sections: Some(vec![Rc::new(Section {
+                code: "class App: pass".to_string(),
+                docs: "# Main App".to_string(),
+                header: Some("# Main App".to_string()),
+                references: vec![],
+            })]),
+        });
+        let builder = create_builder(Rc::new(Project {
+            modules: vec![Rc::clone(&app)],
+        }));
+        let main = module("entry/main.py.lpnb", vec![]);
+
+        assert_eq!(
+            builder.rewrite_links(
+                &main,
+                "See [App][app], [the app] and [spec][].\n\n\
+                 [app]: ../app/app#Main-App \"App\"\n\
+                 [The App]:\n  <../app/app#Main-App>\n\
+                 [spec]: ../static/spec.txt\n"
+            ),
+            "See [App][app], [the app] and [spec][].\n\n\
+             [app]: ../app/app.md#Main-App \"App\"\n\
+             [The App]:\n  <../app/app.md#Main-App>\n\
+             [spec]: ../static/spec.txt\n"
+        );
+        assert_eq!(
+            builder.rewrite_links(
+                &main,
+                "[`](`](<../app/app#Main-App>) and [App](../app/app#Main&#45;App)"
+            ),
+            "[`](`](<../app/app.md#Main-App>) and [App](../app/app.md#Main-App)"
+        );
+    }
+
+    #[test]
+    fn test_prepare_final_docs_with_chunks() {
+        let builder = create_builder(Rc::new(Project { modules: vec![] }));
+        let main = module(
+            "main.py.lpnb",
+            vec![
+                section(
+                    Some("# Main"),
+                    "# Main",
+                    "def main():\n    <<parse arguments>>\n    <<run>>",
+                    vec![],
+                ),
+                section(
+                    Some("## Parse arguments"),
+                    "## Parse arguments",
+                    "args = parse()",
+                    vec![],
+                ),
+            ],
+        );
+
+        let result = builder
+            .prepare_final_docs(&main, "py", &HashMap::new())
+            .unwrap();
+        assert_eq!(
+            result,
+            "# Main\n<pre><code class=\"language-py\">def main():\n    \
+             <a href=\"#Parse-arguments\">&lt;&lt;parse arguments&gt;&gt;</a>\n    &lt;&lt;run&gt;&gt;</code></pre>\n\
+             ## Parse arguments\n```py\nargs = parse()\n```"
+        );
+    }
+
+    #[test]
+    fn test_prepare_final_docs_with_exports() {
+        let builder = create_builder(Rc::new(Project { modules: vec![] }));
+        let section = Rc::new(Section {
+            code: "def is_prime(n): pass".to_string(),
+            docs: "# Primes".to_string(),
+            header: Some("Primes".to_string()),
+            references: vec![],
+        });
+        let exports = HashMap::from([(
+            "Primes".to_string(),
+            vec!["is_prime".to_string(), "PRIMES".to_string()],
+        )]);
+
+        let result = builder
+            .prepare_final_docs(&module("lib.py.lpnb", vec![section]), "py", &exports)
+            .unwrap();
+        assert!(result.ends_with("```\n\nExports: `is_prime`, `PRIMES`"));
+    }
+
+    #[test]
+    fn test_prepare_final_docs_rendered_by_plugin() {
+        let temp_dir = TempDir::new().unwrap();
+        std::fs::write(
+            temp_dir.path().join("Makefile.lua"),
+            r#"
+        function get_import_code() return "" end
+        function clean_code(code) return code end
+
+        function render_section_docs(module_path, header, docs, code)
+            if header == nil then
+                return nil
+            end
+            return docs .. "\n```make\n" .. code .. "\n```\n\nDefined in " .. module_path
+        end
+        "#,
+        )
+        .unwrap();
+        let builder =
+            create_builder_with_plugins(Rc::new(Project { modules: vec![] }), temp_dir.path());
+        let makefile = module(
+            "Makefile.lpnb",
+            vec![
+                section(Some("# Build"), "# Build", "all:\n\tcc main.c", vec![]),
+                section(None, "Plain", "", vec![]),
+            ],
+        );
+
+        assert_eq!(
+            builder
+                .prepare_final_docs(&makefile, "Makefile", &HashMap::new())
+                .unwrap(),
+            "# Build\n```make\nall:\n\tcc main.c\n```\n\nDefined in Makefile.lpnb\nPlain\n```Makefile\n\n```"
+        );
+    }
+
+    #[test]
+    fn test_prepare_final_docs_plugin_error() {
+        let temp_dir = TempDir::new().unwrap();
+        std::fs::write(
+            temp_dir.path().join("py.lua"),
+            r#"
+        function get_import_code() return "" end
+        function clean_code(code) return code end
+        function render_section_docs() error("cannot render") end
+        "#,
+        )
+        .unwrap();
+        let builder =
+            create_builder_with_plugins(Rc::new(Project { modules: vec![] }), temp_dir.path());
+        let section = Rc::new(Section {
+            code: "pass".to_string(),
+            docs: "# Main".to_string(),
+            header: Some("# Main".to_string()),
+            references: vec![],
+        });
+
+        let error = builder
+            .prepare_final_docs(
+                &module("main.py.lpnb", vec![section]),
+                "py",
+                &HashMap::new(),
+            )
+            .unwrap_err();
+        let LPError::PluginFailed(failure) = error else {
+            panic!("unexpected error: {}", error);
+        };
+        assert_eq!(failure.hook, "render_section_docs");
+        assert_eq!(failure.module, Some(PathBuf::from("main.py.lpnb")));
+        assert_eq!(failure.section, Some(0));
+        assert!(failure.message.contains("cannot render"));
+    }
+
+    #[test]
+    fn test_prepare_final_docs_with_backlinks() {
+        let reference = |path: &str, header: &str| Reference {
+            path: PathBuf::from(path),
+            header: header.to_string(),
+            text: header.to_string(),
+        };
+        let primes = Rc::new(module(
+            "lib/primes.py.lpnb",
+            vec![
+                section(Some("# Primes"), "# Primes", "pass", vec![]),
+                section(None, "No header", "pass", vec![]),
+            ],
+        ));
+        let main = Rc::new(module(
+            "entry/main.py.lpnb",
+            vec![
+                section(
+                    None,
+                    "Plain",
+                    "pass",
+                    vec![reference("../lib/primes", "Primes")],
+                ),
+                section(
+                    Some("## Main App"),
+                    "## Main App",
+                    "pass",
+                    vec![reference("../lib/primes", "Primes")],
+                ),
+            ],
+        ));
+        let builder = create_builder(Rc::new(Project {
+            modules: vec![Rc::clone(&primes), Rc::clone(&main)],
+        }));
+
+        let result = builder
+            .prepare_final_docs(&primes, "py", &HashMap::new())
+            .unwrap();
+        assert_eq!(
+            result,
+            "# Primes\n```py\npass\n```\n\n\
+             Referenced by: [entry/main.py](../entry/main.md), [entry/main.py: Main App](../entry/main.md#Main-App)\n\
+             No header\n```py\npass\n```"
+        );
+        assert!(!builder
+            .prepare_final_docs(&main, "py", &HashMap::new())
+            .unwrap()
+            .contains("Referenced by"));
+    }
+
+    #[test]
+    fn test_symbol_index() {
+        let builder = create_builder(Rc::new(Project { modules: vec![] }));
+        let section = Rc::new(Section {
+            code: "def is_prime(n): pass".to_string(),
+            docs: "## Checking primes".to_string(),
+            header: Some("## Checking primes".to_string()),
+            references: vec![],
+        });
+        let exports = HashMap::from([(
+            "Checking-primes".to_string(),
+            vec!["is_prime".to_string(), "PRIMES".to_string()],
+        )]);
+
+        let symbols =
+            builder.collect_symbols(&module("lib/primes.py.lpnb", vec![section]), &exports);
+        assert_eq!(
+            DocsBuilder::prepare_symbol_index(symbols),
+            "# Symbols\n\
+             \n- [`PRIMES`](lib/primes.md#Checking-primes) — `lib/primes.py`, Checking primes\
+             \n- [`is_prime`](lib/primes.md#Checking-primes) — `lib/primes.py`, Checking primes"
+        );
+    }
 }
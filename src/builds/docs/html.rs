@@ -0,0 +1,421 @@
+#![forbid(unsafe_code)]
+//! rendering of the documentation as a static HTML site
+
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+};
+
+use pulldown_cmark::{
+    escape::{escape_href, escape_html},
+    html, Event, Options, Parser, Tag,
+};
+
+use crate::{
+    builds::spec::utils,
+    config::constants::{DOCS_INDEX_PAGE_NAME, DOCS_STYLE_FILE_NAME, SEARCH_PAGE_NAME},
+};
+
+/// The stylesheet shared by the pages, written next to them so the site works offline
+pub const STYLE: &str = "\
+body {
+    margin: 0;
+    display: grid;
+    grid-template-columns: 16rem minmax(0, 1fr) 14rem;
+    font-family: -apple-system, \"Segoe UI\", Helvetica, Arial, sans-serif;
+    line-height: 1.5;
+    color: #1f2328;
+}
+nav {
+    position: sticky;
+    top: 0;
+    align-self: start;
+    max-height: 100vh;
+    overflow-y: auto;
+    padding: 1rem;
+    box-sizing: border-box;
+    font-size: 0.9rem;
+}
+nav ul {
+    list-style: none;
+    margin: 0;
+    padding-left: 1rem;
+}
+nav > ul {
+    padding-left: 0;
+}
+nav a {
+    color: inherit;
+    text-decoration: none;
+}
+nav a:hover {
+    text-decoration: underline;
+}
+.sidebar {
+    border-right: 1px solid #d0d7de;
+    background: #f6f8fa;
+}
+.sidebar .home {
+    display: block;
+    font-weight: bold;
+}
+.sidebar .search {
+    display: block;
+    margin-bottom: 0.5rem;
+}
+#search-input {
+    width: 100%;
+    max-width: 32rem;
+    padding: 0.4rem;
+    font-size: 1rem;
+}
+.sidebar .current {
+    font-weight: bold;
+}
+.toc {
+    border-left: 1px solid #d0d7de;
+}
+.toc h2 {
+    font-size: 0.9rem;
+    margin-top: 0;
+}
+.toc-level-2 { padding-left: 0.75rem; }
+.toc-level-3 { padding-left: 1.5rem; }
+.toc-level-4, .toc-level-5, .toc-level-6 { padding-left: 2.25rem; }
+main {
+    padding: 1rem 2rem;
+}
+pre {
+    padding: 0.75rem;
+    overflow-x: auto;
+    background: #f6f8fa;
+    border-radius: 6px;
+}
+code {
+    font-family: ui-monospace, SFMono-Regular, Menlo, Consolas, monospace;
+    font-size: 0.9em;
+}
+table {
+    border-collapse: collapse;
+}
+th, td {
+    border: 1px solid #d0d7de;
+    padding: 0.25rem 0.75rem;
+}
+@media (max-width: 60rem) {
+    body { display: block; }
+    nav { position: static; max-height: none; }
+    .toc { display: none; }
+}
+";
+
+/// A file of the site listed in the sidebar: a module's page or a copied file
+#[derive(Debug, Clone, PartialEq)]
+pub struct SiteEntry {
+    /// relative to the docs directory, e.g. `lib/primes.html`
+    pub path: PathBuf,
+    /// the displayed name, e.g. `primes.py`
+    pub label: String,
+}
+
+/// A heading listed in the page's table of contents
+#[derive(Debug, Clone, PartialEq)]
+pub struct TocEntry {
+    /// 1 for `#`, 2 for `##` and so on
+    pub level: usize,
+    pub text: String,
+    pub anchor: String,
+}
+
+/// A directory of the site
+#[derive(Debug, Default)]
+struct SiteDir {
+    dirs: BTreeMap<String, SiteDir>,
+    files: Vec<SiteEntry>,
+}
+
+/// The static site: the tree of the pages and the copied files, mirroring the source directory
+#[derive(Debug, Default)]
+pub struct Site {
+    root: SiteDir,
+}
+
+impl Site {
+    pub fn new(entries: Vec<SiteEntry>) -> Self {
+        let mut root = SiteDir::default();
+        for entry in entries {
+            let mut dir = &mut root;
+            if let Some(parent) = entry.path.parent() {
+                for component in parent.components() {
+                    dir = dir
+                        .dirs
+                        .entry(component.as_os_str().to_string_lossy().to_string())
+                        .or_default();
+                }
+            }
+            dir.files.push(entry);
+        }
+        Site { root }
+    }
+
+    /// Returns a summary of the tree, every page's sidebar depends on it
+    pub fn fingerprint(&self) -> String {
+        fn walk(dir: &SiteDir, result: &mut String) {
+            for entry in &dir.files {
+                result.push_str(&format!("{}\t{}\n", entry.path.display(), entry.label));
+            }
+            for child in dir.dirs.values() {
+                walk(child, result);
+            }
+        }
+        let mut result = String::new();
+        walk(&self.root, &mut result);
+        result
+    }
+
+    /// Returns the sidebar of the page at `page`, relative to the docs directory.
+    /// Only the directories containing the page are expanded.
+    fn sidebar(&self, page: &Path) -> String {
+        fn render(dir: &SiteDir, dir_path: &Path, page: &Path, result: &mut String) {
+            result.push_str("<ul>");
+            for (name, child) in &dir.dirs {
+                let child_path = dir_path.join(name);
+                let open = if page.starts_with(&child_path) {
+                    " open"
+                } else {
+                    ""
+                };
+                result.push_str(&format!(
+                    "<li><details{}><summary>{}</summary>",
+                    open,
+                    escape(name)
+                ));
+                render(child, &child_path, page, result);
+                result.push_str("</details></li>");
+            }
+            for entry in &dir.files {
+                let current = if entry.path == page {
+                    " class=\"current\""
+                } else {
+                    ""
+                };
+                result.push_str(&format!(
+                    "<li><a href=\"{}\"{}>{}</a></li>",
+                    link(page, &entry.path),
+                    current,
+                    escape(&entry.label)
+                ));
+            }
+            result.push_str("</ul>");
+        }
+
+        let home = PathBuf::from(DOCS_INDEX_PAGE_NAME).with_extension("html");
+        let search = PathBuf::from(SEARCH_PAGE_NAME).with_extension("html");
+        let mut result = format!(
+            "<a class=\"home\" href=\"{}\">Documentation</a><a class=\"search\" href=\"{}\">Search</a>",
+            link(page, &home),
+            link(page, &search)
+        );
+        render(&self.root, Path::new(""), page, &mut result);
+        result
+    }
+
+    /// Returns the whole HTML page at `page`, relative to the docs directory.
+    ///
+    /// `content` is the already rendered HTML of the page.
+    pub fn render_page(&self, page: &Path, title: &str, toc: &[TocEntry], content: &str) -> String {
+        let toc = if toc.is_empty() {
+            String::new()
+        } else {
+            let items: String = toc
+                .iter()
+                .map(|entry| {
+                    format!(
+                        "<li class=\"toc-level-{}\"><a href=\"#{}\">{}</a></li>",
+                        entry.level,
+                        escape_link(&entry.anchor),
+                        escape(&entry.text)
+                    )
+                })
+                .collect();
+            format!(
+                "\n<nav class=\"toc\"><h2>Contents</h2><ul>{}</ul></nav>",
+                items
+            )
+        };
+        format!(
+            "<!DOCTYPE html>\n\
+             <html>\n\
+             <head>\n\
+             <meta charset=\"utf-8\">\n\
+             <meta name=\"viewport\" content=\"width=device-width, initial-scale=1\">\n\
+             <title>{}</title>\n\
+             <link rel=\"stylesheet\" href=\"{}\">\n\
+             </head>\n\
+             <body>\n\
+             <nav class=\"sidebar\">{}</nav>\n\
+             <main>\n{}</main>{}\n\
+             </body>\n\
+             </html>\n",
+            escape(title),
+            link(page, Path::new(DOCS_STYLE_FILE_NAME)),
+            self.sidebar(page),
+            content,
+            toc
+        )
+    }
+}
+
+/// Renders the markdown to HTML.
+///
+/// The headings get the same anchors as the sections' headers, e.g. `## Main App` becomes `<h2 id="Main-App">`,
+/// so the references to the sections resolve to them.
+pub fn markdown_to_html(markdown: &str) -> String {
+    let events: Vec<_> = Parser::new_ext(
+        markdown,
+        Options::ENABLE_TABLES | Options::ENABLE_STRIKETHROUGH | Options::ENABLE_TASKLISTS,
+    )
+    .into_offset_iter()
+    .collect();
+    let anchors: Vec<String> = events
+        .iter()
+        .filter_map(|(event, range)| match event {
+            Event::Start(Tag::Heading(..)) => Some(heading_anchor(&markdown[range.clone()])),
+            _ => None,
+        })
+        .collect();
+
+    let mut anchors = anchors.iter();
+    let events = events.into_iter().map(|(event, _)| match event {
+        Event::Start(Tag::Heading(level, None, classes)) => Event::Start(Tag::Heading(
+            level,
+            anchors.next().map(String::as_str),
+            classes,
+        )),
+        event => event,
+    });
+    let mut result = String::new();
+    html::push_html(&mut result, events);
+    result
+}
+
+/// Returns the anchor of the heading by its source, the same way as `Section::get_header` does
+fn heading_anchor(source: &str) -> String {
+    let first_line = source.lines().next().unwrap_or_default();
+    utils::header_to_anchor(first_line.trim().trim_matches('#'))
+}
+
+/// Returns the escaped link from the page to the file, both relative to the docs directory
+fn link(page: &Path, to: &Path) -> String {
+    let from_dir = page.parent().unwrap_or(Path::new(""));
+    escape_link(&utils::relative_path(from_dir, to))
+}
+
+/// Returns the link escaped for an `href` attribute
+pub fn escape_link(link: &str) -> String {
+    let mut result = String::new();
+    let _ = escape_href(&mut result, link);
+    result
+}
+
+/// Returns the text escaped for HTML
+pub fn escape(text: &str) -> String {
+    let mut result = String::new();
+    let _ = escape_html(&mut result, text);
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(path: &str, label: &str) -> SiteEntry {
+        SiteEntry {
+            path: PathBuf::from(path),
+            label: label.to_string(),
+        }
+    }
+
+    fn site() -> Site {
+        Site::new(vec![
+            entry("main.html", "main.py"),
+            entry("lib/primes.html", "primes.py"),
+            entry("lib/static/spec.txt", "spec.txt"),
+            entry("app/app.html", "app.py"),
+        ])
+    }
+
+    #[test]
+    fn test_markdown_to_html_anchors() {
+        let html = markdown_to_html(
+            "# Main App\ntext with [link](../app/app.html#Main-App)\n\n## The `App` class\n\n| a | b |\n|---|---|\n| 1 | 2 |",
+        );
+        assert!(
+            html.contains("<h1 id=\"Main-App\">Main App</h1>"),
+            "{}",
+            html
+        );
+        assert!(html.contains("<a href=\"../app/app.html#Main-App\">link</a>"));
+        assert!(html.contains("<h2 id=\"The-`App`-class\">The <code>App</code> class</h2>"));
+        assert!(html.contains("<table>"));
+    }
+
+    #[test]
+    fn test_sidebar() {
+        let sidebar = site().sidebar(Path::new("lib/primes.html"));
+        assert_eq!(
+            sidebar,
+            "<a class=\"home\" href=\"../index.html\">Documentation</a>\
+             <a class=\"search\" href=\"../search.html\">Search</a><ul>\
+             <li><details><summary>app</summary><ul><li><a href=\"../app/app.html\">app.py</a></li></ul></details></li>\
+             <li><details open><summary>lib</summary><ul>\
+             <li><details><summary>static</summary><ul><li><a href=\"static/spec.txt\">spec.txt</a></li></ul></details></li>\
+             <li><a href=\"primes.html\" class=\"current\">primes.py</a></li>\
+             </ul></details></li>\
+             <li><a href=\"../main.html\">main.py</a></li></ul>"
+        );
+    }
+
+    #[test]
+    fn test_render_page() {
+        let page = site().render_page(
+            Path::new("main.html"),
+            "main.py <entry>",
+            &[
+                TocEntry {
+                    level: 1,
+                    text: "Main".to_string(),
+                    anchor: "Main".to_string(),
+                },
+                TocEntry {
+                    level: 2,
+                    text: "Parse arguments".to_string(),
+                    anchor: "Parse-arguments".to_string(),
+                },
+            ],
+            "<h1 id=\"Main\">Main</h1>\n",
+        );
+        assert!(page.starts_with("<!DOCTYPE html>"));
+        assert!(page.contains("<title>main.py &lt;entry&gt;</title>"));
+        assert!(page.contains("<link rel=\"stylesheet\" href=\"lp-docs.css\">"));
+        assert!(page.contains("<a href=\"main.html\" class=\"current\">main.py</a>"));
+        assert!(page.contains("<main>\n<h1 id=\"Main\">Main</h1>\n</main>"));
+        assert!(page.contains(
+            "<li class=\"toc-level-2\"><a href=\"#Parse-arguments\">Parse arguments</a></li>"
+        ));
+        assert!(!page.contains("http"));
+
+        let without_toc = site().render_page(Path::new("lib/primes.html"), "primes.py", &[], "");
+        assert!(!without_toc.contains("class=\"toc\""));
+        assert!(without_toc.contains("href=\"../lp-docs.css\""));
+    }
+
+    #[test]
+    fn test_fingerprint() {
+        assert_eq!(site().fingerprint(), site().fingerprint());
+        assert_ne!(
+            site().fingerprint(),
+            Site::new(vec![entry("main.html", "main.py")]).fingerprint()
+        );
+    }
+}
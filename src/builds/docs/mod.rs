@@ -2,3 +2,5 @@
 
 pub mod config;
 pub mod docs_builder;
+pub mod html;
+pub mod search;
@@ -0,0 +1,240 @@
+#![forbid(unsafe_code)]
+//! the client-side full-text search over the documentation
+
+use std::{collections::BTreeSet, sync::LazyLock};
+
+use pulldown_cmark::{Event, Parser, Tag};
+use regex::Regex;
+use serde::Serialize;
+
+use crate::builds::spec::structs::Section;
+
+/// A section as the search sees it
+#[derive(Debug, Serialize, PartialEq)]
+pub struct SearchEntry {
+    /// the module's displayed name, e.g. `lib/primes.py`
+    pub module: String,
+    /// the module's page, relative to the docs directory
+    pub page: String,
+    /// the section's anchor, as made by `header_to_anchor`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub anchor: Option<String>,
+    /// the section's header without the `#`s
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub header: Option<String>,
+    /// the plain text of the section's docs, without its header
+    pub text: String,
+    /// the identifiers found in the section's code, sorted and deduplicated
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub identifiers: Vec<String>,
+}
+
+impl SearchEntry {
+    pub fn new(module: &str, page: &str, section: &Section) -> Self {
+        SearchEntry {
+            module: module.to_string(),
+            page: page.to_string(),
+            anchor: section.get_header(),
+            header: section
+                .header
+                .as_ref()
+                .map(|header| header.trim().trim_matches('#').trim().to_string()),
+            text: plain_text(&section.docs, section.header.is_some()),
+            identifiers: identifiers(&section.code),
+        }
+    }
+}
+
+/// Returns the text of the markdown with the whitespaces collapsed, optionally without its first heading
+fn plain_text(markdown: &str, skip_header: bool) -> String {
+    let mut text = String::new();
+    let mut in_header = false;
+    let mut header_skipped = !skip_header;
+    for event in Parser::new(markdown) {
+        match event {
+            Event::Start(Tag::Heading(..)) if !header_skipped => in_header = true,
+            Event::End(Tag::Heading(..)) if in_header => {
+                in_header = false;
+                header_skipped = true;
+            }
+            _ if in_header => {}
+            Event::Text(part) | Event::Code(part) => text.push_str(&part),
+            Event::SoftBreak
+            | Event::HardBreak
+            | Event::End(
+                Tag::Paragraph
+                | Tag::Heading(..)
+                | Tag::Item
+                | Tag::CodeBlock(_)
+                | Tag::TableCell
+                | Tag::BlockQuote,
+            ) => text.push(' '),
+            _ => {}
+        }
+    }
+    text.split_whitespace().collect::<Vec<&str>>().join(" ")
+}
+
+/// an identifier at least two characters long
+static IDENTIFIER: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"[A-Za-z_][A-Za-z0-9_]+").unwrap());
+
+/// Returns the identifiers of the code, at least two characters long
+fn identifiers(code: &str) -> Vec<String> {
+    IDENTIFIER
+        .find_iter(code)
+        .map(|m| m.as_str().to_string())
+        .collect::<BTreeSet<_>>()
+        .into_iter()
+        .collect()
+}
+
+/// Returns the compact JSON of the search index
+pub fn index_json(entries: &[SearchEntry]) -> String {
+    serde_json::to_string(entries).unwrap_or_default()
+}
+
+/// Returns the search form, the results list and the script querying the index.
+///
+/// The index is embedded into the page, so the search works when the page is opened as a local file.
+/// `<` can only appear inside the JSON's strings, so it's escaped to keep the script element closed.
+pub fn search_page_content(index_json: &str) -> String {
+    format!(
+        "<h1>Search</h1>\n\
+         <input id=\"search-input\" type=\"search\" placeholder=\"Search the documentation\" autofocus>\n\
+         <ul id=\"search-results\"></ul>\n\
+         <script type=\"application/json\" id=\"search-index\">{}</script>\n\
+         <script>\n{}</script>\n",
+        index_json.replace('<', "\\u003c"),
+        SEARCH_SCRIPT
+    )
+}
+
+/// Returns the search page for the documentation without a site around it, e.g. the Markdown one
+pub fn standalone_search_page(content: &str) -> String {
+    format!(
+        "<!DOCTYPE html>\n\
+         <html>\n\
+         <head>\n\
+         <meta charset=\"utf-8\">\n\
+         <meta name=\"viewport\" content=\"width=device-width, initial-scale=1\">\n\
+         <title>Search</title>\n\
+         </head>\n\
+         <body>\n\
+         {}\
+         </body>\n\
+         </html>\n",
+        content
+    )
+}
+
+/// Every term of the query must be found in the section's header, identifiers, module or text,
+/// the sections are ranked by where the terms are found. The query may be passed as `?q=`.
+const SEARCH_SCRIPT: &str = r##"const index = JSON.parse(document.getElementById("search-index").textContent);
+const input = document.getElementById("search-input");
+const results = document.getElementById("search-results");
+
+function score(entry, terms) {
+    let total = 0;
+    for (const term of terms) {
+        let found = 0;
+        if ((entry.header || "").toLowerCase().includes(term)) found += 8;
+        if ((entry.identifiers || []).some((name) => name.toLowerCase().includes(term))) found += 4;
+        if (entry.module.toLowerCase().includes(term)) found += 2;
+        if (entry.text.toLowerCase().includes(term)) found += 1;
+        if (found === 0) return 0;
+        total += found;
+    }
+    return total;
+}
+
+function snippet(text, term) {
+    const position = Math.max(text.toLowerCase().indexOf(term), 0);
+    const start = Math.max(position - 60, 0);
+    return (start > 0 ? "…" : "") + text.slice(start, start + 160) + (start + 160 < text.length ? "…" : "");
+}
+
+function search() {
+    const terms = input.value.toLowerCase().split(/\s+/).filter((term) => term);
+    results.replaceChildren();
+    if (terms.length === 0) return;
+    const found = index
+        .map((entry) => [score(entry, terms), entry])
+        .filter(([points]) => points > 0)
+        .sort((a, b) => b[0] - a[0])
+        .slice(0, 50);
+    if (found.length === 0) {
+        const item = document.createElement("li");
+        item.textContent = "Nothing found";
+        results.append(item);
+    }
+    for (const [, entry] of found) {
+        const item = document.createElement("li");
+        const link = document.createElement("a");
+        link.href = entry.page + (entry.anchor ? "#" + encodeURIComponent(entry.anchor) : "");
+        link.textContent = entry.header || entry.module;
+        const text = document.createElement("p");
+        text.textContent = snippet(entry.text, terms[0]);
+        item.append(link, " — " + entry.module, text);
+        results.append(item);
+    }
+}
+
+input.addEventListener("input", search);
+input.value = new URLSearchParams(location.search).get("q") || "";
+search();
+"##;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_search_entry() {
+        let section = Section {
+            code: "def is_prime(n):\n    return n > 1 and all(n % i for i in range(2, n))"
+                .to_string(),
+            docs: "## Checking primes\nUses the *naive* algorithm, see [`all`](https://docs.python.org).\n\n- fast enough"
+                .to_string(),
+            header: Some("## Checking primes".to_string()),
+            references: vec![],
+        };
+
+        let entry = SearchEntry::new("lib/primes.py", "lib/primes.md", &section);
+        assert_eq!(
+            entry,
+            SearchEntry {
+                module: "lib/primes.py".to_string(),
+                page: "lib/primes.md".to_string(),
+                anchor: Some("Checking-primes".to_string()),
+                header: Some("Checking primes".to_string()),
+                text: "Uses the naive algorithm, see all. fast enough".to_string(),
+                identifiers: ["all", "and", "def", "for", "in", "is_prime", "range", "return"]
+                    .map(String::from)
+                    .to_vec(),
+            }
+        );
+    }
+
+    #[test]
+    fn test_index_json() {
+        let section = Section {
+            code: "".to_string(),
+            docs: "Plain text".to_string(),
+            header: None,
+            references: vec![],
+        };
+
+        assert_eq!(
+            index_json(&[SearchEntry::new("main.py", "main.md", &section)]),
+            r#"[{"module":"main.py","page":"main.md","text":"Plain text"}]"#
+        );
+    }
+
+    #[test]
+    fn test_search_page_content() {
+        let content = search_page_content(r#"[{"text":"</script><script>alert(1)"}]"#);
+        assert!(content.contains(r#"[{"text":"\u003c/script>\u003cscript>alert(1)"}]</script>"#));
+        assert_eq!(content.matches("</script>").count(), 2);
+    }
+}
@@ -1,29 +1,65 @@
 #![forbid(unsafe_code)]
 
-use crate::builds::spec::structs::Section;
-use std::collections::HashMap;
-use std::path::PathBuf;
+use crate::builds::spec::structs::{Module, Section};
+use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
+use std::collections::{BTreeSet, HashMap};
+use std::path::{Path, PathBuf};
 use std::rc::Rc;
 
 use super::spec::structs::Project;
 use super::spec::utils;
 
+/// A part of the index read by a plugin through the `lp` library.
+///
+/// The build cache keeps them per output, since a plugin may read any section, not only the referenced ones.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum IndexRead {
+    /// the list of the modules, `lp.modules`
+    Modules,
+    /// a section, `lp.get_section`, the module path is treated the same way as in `get_section`
+    Section { module: PathBuf, header: String },
+}
+
+/// A section referencing another one
+#[derive(Debug, Clone)]
+pub struct Backlink {
+    /// the module containing the referencing section
+    pub module: Rc<Module>,
+    /// index of the referencing section within its module
+    pub section_index: usize,
+    pub section: Rc<Section>,
+}
+
 /// Index for the project sections.
 ///
 /// It's used to quickly find a section by its header and it's module path.
 pub struct ProjectIndex {
     sections: HashMap<PathBuf, HashMap<String, Rc<Section>>>,
+    modules: HashMap<PathBuf, Rc<Module>>,
+    /// the sections referencing each section, the reverse of the sections' references,
+    /// keyed the same way as `sections`
+    backlinks: HashMap<PathBuf, HashMap<String, Vec<Backlink>>>,
+    /// names exported by the sections as reported by the plugins' `get_exports`,
+    /// filled in after the index is built, since the plugins themselves have access to the index
+    exports: RefCell<HashMap<PathBuf, HashMap<String, Vec<String>>>>,
+    /// the parts of the index read by the plugins since the last `take_reads`
+    reads: RefCell<BTreeSet<IndexRead>>,
 }
 
 impl ProjectIndex {
     /// Creates a new project index from the project.
     pub fn new(project: Rc<Project>) -> ProjectIndex {
         let mut sections = HashMap::new();
+        let mut modules = HashMap::new();
 
         for module in project.modules.iter() {
             let path = utils::module_name(&module.path);
 
             if let Some(module_sections) = &module.sections {
+                modules.insert(path.clone(), module.clone());
+
                 let mut header_map = HashMap::new();
 
                 for section in module_sections {
@@ -38,7 +74,49 @@ impl ProjectIndex {
             }
         }
 
-        ProjectIndex { sections }
+        let mut backlinks: HashMap<PathBuf, HashMap<String, Vec<Backlink>>> = HashMap::new();
+        for module in project.modules.iter() {
+            for (section_index, section) in module.sections.iter().flatten().enumerate() {
+                for reference in &section.references {
+                    let path = module.resolve_relative_module_path(&reference.path);
+                    let Some(header_map) = sections.get(&path) else {
+                        continue;
+                    };
+                    if !header_map.contains_key(&reference.header) {
+                        continue;
+                    }
+                    let referencing = backlinks
+                        .entry(path)
+                        .or_default()
+                        .entry(reference.header.clone())
+                        .or_default();
+                    let already_listed = referencing.iter().any(|backlink| {
+                        Rc::ptr_eq(&backlink.module, module)
+                            && backlink.section_index == section_index
+                    });
+                    if !already_listed {
+                        referencing.push(Backlink {
+                            module: module.clone(),
+                            section_index,
+                            section: section.clone(),
+                        });
+                    }
+                }
+            }
+        }
+        for backlink in backlinks.values_mut().flat_map(HashMap::values_mut) {
+            backlink.sort_by(|a, b| {
+                (&a.module.path, a.section_index).cmp(&(&b.module.path, b.section_index))
+            });
+        }
+
+        ProjectIndex {
+            sections,
+            modules,
+            backlinks,
+            exports: RefCell::default(),
+            reads: RefCell::default(),
+        }
     }
 
     /// The path is treated as a module path without any extension
@@ -47,11 +125,76 @@ impl ProjectIndex {
     pub fn get_section(&self, path: &PathBuf, header: &str) -> Option<&Rc<Section>> {
         self.sections.get(path)?.get(header)
     }
+
+    /// Returns the sections referencing the section with the given header, sorted by their modules' paths.
+    ///
+    /// The path is treated the same way as in `get_section`.
+    pub fn get_backlinks(&self, path: &PathBuf, header: &str) -> &[Backlink] {
+        self.backlinks
+            .get(path)
+            .and_then(|header_map| header_map.get(header))
+            .map(Vec::as_slice)
+            .unwrap_or_default()
+    }
+
+    /// Returns the literate module by its path without any extension.
+    ///
+    /// The path is treated the same way as in `get_section`.
+    pub fn get_module(&self, path: &PathBuf) -> Option<&Rc<Module>> {
+        self.modules.get(path)
+    }
+
+    /// Returns all the literate modules of the project, sorted by their paths.
+    pub fn get_modules(&self) -> Vec<&Rc<Module>> {
+        let mut modules: Vec<&Rc<Module>> = self.modules.values().collect();
+        modules.sort_by(|a, b| a.path.cmp(&b.path));
+        modules
+    }
+
+    /// Records the names exported by the section with the given header.
+    ///
+    /// The path is treated the same way as in `get_section`.
+    pub fn set_exports(&self, path: &Path, header: &str, exports: Vec<String>) {
+        self.exports
+            .borrow_mut()
+            .entry(path.to_path_buf())
+            .or_default()
+            .insert(header.to_string(), exports);
+    }
+
+    /// Returns the names exported by the module's sections, by the sections' headers.
+    ///
+    /// The path is treated the same way as in `get_section`.
+    pub fn get_exports(&self, path: &PathBuf) -> HashMap<String, Vec<String>> {
+        self.exports.borrow().get(path).cloned().unwrap_or_default()
+    }
+
+    /// Records that a plugin read the part of the index
+    pub fn record_read(&self, read: IndexRead) {
+        self.reads.borrow_mut().insert(read);
+    }
+
+    /// Returns the parts of the index read by the plugins since the previous call, sorted
+    pub fn take_reads(&self) -> Vec<IndexRead> {
+        std::mem::take(&mut *self.reads.borrow_mut())
+            .into_iter()
+            .collect()
+    }
+
+    /// Returns the headers (formatted as anchors) of all the referencable sections of the module.
+    ///
+    /// The path is treated the same way as in `get_section`.
+    pub fn get_headers(&self, path: &PathBuf) -> Vec<&String> {
+        self.sections
+            .get(path)
+            .map(|header_map| header_map.keys().collect())
+            .unwrap_or_default()
+    }
 }
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::builds::spec::structs::Module;
+    use crate::builds::spec::structs::Reference;
 
     fn create_test_project() -> Rc<Project> {
         let section1 = Rc::new(Section {
@@ -103,6 +246,23 @@ mod tests {
         })
     }
 
+    /// Returns a section with the references given as `(path, header)` pairs
+    fn section(header: Option<&str>, references: Vec<(&str, &str)>) -> Rc<Section> {
+        Rc::new(Section {
+            code: "".to_string(),
+            docs: "".to_string(),
+            header: header.map(String::from),
+            references: references
+                .into_iter()
+                .map(|(path, header)| Reference {
+                    path: PathBuf::from(path),
+                    header: header.to_string(),
+                    text: header.to_string(),
+                })
+                .collect(),
+        })
+    }
+
     #[test]
     fn test_new_project_index() {
         let project = create_test_project();
@@ -150,4 +310,95 @@ mod tests {
         let section = index.get_section(&PathBuf::from("empty"), "Any-Header");
         assert!(section.is_none());
     }
+
+    #[test]
+    fn test_get_headers() {
+        let project = create_test_project();
+        let index = ProjectIndex::new(project);
+
+        let mut headers = index.get_headers(&PathBuf::from("module1"));
+        headers.sort();
+        assert_eq!(headers, vec!["Header-1", "Header-2"]);
+
+        assert!(index.get_headers(&PathBuf::from("empty")).is_empty());
+    }
+
+    #[test]
+    fn test_get_module() {
+        let project = create_test_project();
+        let index = ProjectIndex::new(project);
+
+        let module = index.get_module(&PathBuf::from("subdir/module2"));
+        assert_eq!(
+            module.unwrap().path,
+            PathBuf::from("subdir/module2.rs.lpnb")
+        );
+        assert!(index.get_module(&PathBuf::from("empty")).is_none());
+        assert!(index.get_module(&PathBuf::from("nonexistent")).is_none());
+    }
+
+    #[test]
+    fn test_get_backlinks() {
+        let lib = Rc::new(Module {
+            path: PathBuf::from("lib/primes.py.lpnb"),
+            sections: Some(vec![
+                section(Some("# Primes"), vec![]),
+                section(Some("# Sieve"), vec![("", "Primes")]),
+            ]),
+        });
+        let main = Rc::new(Module {
+            path: PathBuf::from("main.py.lpnb"),
+            sections: Some(vec![
+                section(
+                    None,
+                    vec![("lib/primes", "Primes"), ("lib/primes", "Primes")],
+                ),
+                section(
+                    Some("# Main"),
+                    vec![("lib/primes", "Sieve"), ("lib/primes", "Missing")],
+                ),
+            ]),
+        });
+        let index = ProjectIndex::new(Rc::new(Project {
+            modules: vec![Rc::clone(&main), Rc::clone(&lib)],
+        }));
+
+        let backlinks = index.get_backlinks(&PathBuf::from("lib/primes"), "Primes");
+        let referencing: Vec<(&PathBuf, usize)> = backlinks
+            .iter()
+            .map(|backlink| (&backlink.module.path, backlink.section_index))
+            .collect();
+        assert_eq!(
+            referencing,
+            vec![
+                (&PathBuf::from("lib/primes.py.lpnb"), 1),
+                (&PathBuf::from("main.py.lpnb"), 0)
+            ]
+        );
+        let backlinks = index.get_backlinks(&PathBuf::from("lib/primes"), "Sieve");
+        assert_eq!(backlinks.len(), 1);
+        assert_eq!(backlinks[0].section.header.as_deref(), Some("# Main"));
+        assert!(index
+            .get_backlinks(&PathBuf::from("lib/primes"), "Missing")
+            .is_empty());
+        assert!(index
+            .get_backlinks(&PathBuf::from("main"), "Main")
+            .is_empty());
+    }
+
+    #[test]
+    fn test_exports() {
+        let project = create_test_project();
+        let index = ProjectIndex::new(project);
+
+        index.set_exports(
+            &PathBuf::from("module1"),
+            "Header-1",
+            vec!["hello".to_string()],
+        );
+
+        let exports = index.get_exports(&PathBuf::from("module1"));
+        assert_eq!(exports["Header-1"], vec!["hello"]);
+        assert!(index.get_exports(&PathBuf::from("empty")).is_empty());
+    }
 }
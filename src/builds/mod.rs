@@ -1,7 +1,11 @@
 #![forbid(unsafe_code)]
 
 pub mod builder;
+pub mod cache;
+pub mod checker;
 pub mod code;
 pub mod docs;
 pub mod index;
 pub mod spec;
+pub mod validation;
+pub mod watcher;
@@ -0,0 +1,293 @@
+#![forbid(unsafe_code)]
+
+use std::{
+    collections::{HashMap, HashSet},
+    rc::Rc,
+};
+
+use crate::error::LPError;
+
+use super::{
+    structs::{Module, Section},
+    utils::header_to_anchor,
+};
+
+/// A line of the tangled code together with the place it comes from
+#[derive(Debug, Clone, PartialEq)]
+pub struct TangledLine {
+    pub text: String,
+    /// index of the section within the module
+    pub section: usize,
+    /// 1-based line number within the section's code
+    pub line: usize,
+}
+
+/// Parses a line of code as a chunk placeholder, e.g. `    <<parse arguments>>`.
+///
+/// Only placeholders occupying the whole line are supported.
+/// A placeholder is a chunk only if a section of the module has a matching header,
+/// other such lines, e.g. of a heredoc, are kept as code.
+/// Returns the indentation of the placeholder and the name of the chunk.
+pub fn parse_placeholder(line: &str) -> Option<(&str, &str)> {
+    let trimmed = line.trim();
+    let name = trimmed.strip_prefix("<<")?.strip_suffix(">>")?.trim();
+    if name.is_empty() || name.contains("<<") || name.contains(">>") {
+        return None;
+    }
+    let indent = &line[..line.len() - line.trim_start().len()];
+    Some((indent, name))
+}
+
+/// Chunks are matched with the section headers case-insensitively,
+/// so `<<parse arguments>>` is defined by the section `## Parse arguments`
+fn chunk_key(name: &str) -> String {
+    header_to_anchor(name).to_lowercase()
+}
+
+/// Returns the names of the placeholders in the code, in order of appearance, whether they are defined or not.
+pub fn used_chunks(code: &str) -> Vec<&str> {
+    code.lines()
+        .filter_map(parse_placeholder)
+        .map(|(_, name)| name)
+        .collect()
+}
+
+impl Module {
+    /// Returns the indices of sections by their chunk keys.
+    fn chunk_definitions(&self) -> HashMap<String, usize> {
+        self.sections
+            .as_deref()
+            .unwrap_or_default()
+            .iter()
+            .enumerate()
+            .filter_map(|(i, section)| section.get_header().map(|header| (chunk_key(&header), i)))
+            .collect()
+    }
+
+    /// Returns the section which defines the chunk with the given name, if any.
+    pub fn find_chunk(&self, name: &str) -> Option<&Rc<Section>> {
+        let index = *self.chunk_definitions().get(&chunk_key(name))?;
+        self.sections.as_ref()?.get(index)
+    }
+
+    fn expand_section(
+        &self,
+        index: usize,
+        definitions: &HashMap<String, usize>,
+        stack: &mut Vec<usize>,
+        indent: &str,
+        output: &mut Vec<TangledLine>,
+    ) -> Result<(), LPError> {
+        let sections = self.sections.as_deref().unwrap_or_default();
+        stack.push(index);
+
+        let mut code = sections[index].code.as_str();
+        if stack.len() > 1 {
+            code = code.strip_suffix('\n').unwrap_or(code);
+        }
+
+        for (line_index, line) in code.split('\n').enumerate() {
+            let chunk = parse_placeholder(line).and_then(|(placeholder_indent, name)| {
+                Some((placeholder_indent, *definitions.get(&chunk_key(name))?))
+            });
+            let Some((placeholder_indent, chunk)) = chunk else {
+                output.push(TangledLine {
+                    text: if line.is_empty() {
+                        String::new()
+                    } else {
+                        format!("{}{}", indent, line)
+                    },
+                    section: index,
+                    line: line_index + 1,
+                });
+                continue;
+            };
+
+            if let Some(start) = stack.iter().position(|&i| i == chunk) {
+                let cycle = stack[start..]
+                    .iter()
+                    .chain(std::iter::once(&chunk))
+                    .map(|&i| sections[i].get_header().unwrap_or_default())
+                    .collect::<Vec<String>>()
+                    .join(" -> ");
+                return Err(LPError::ChunkCycle(self.path.clone(), cycle));
+            }
+
+            let chunk_indent = format!("{}{}", indent, placeholder_indent);
+            self.expand_section(chunk, definitions, stack, &chunk_indent, output)?;
+        }
+
+        stack.pop();
+        Ok(())
+    }
+
+    /// Returns the lines of the module's code with all the chunk placeholders expanded.
+    ///
+    /// Sections used as chunks are placed only where they are used,
+    /// other sections are concatenated in the file order.
+    /// Chunks can be defined only within the same module.
+    pub fn get_tangled_lines(&self) -> Result<Vec<TangledLine>, LPError> {
+        let sections = self.sections.as_deref().unwrap_or_default();
+        let definitions = self.chunk_definitions();
+
+        let used: HashSet<usize> = sections
+            .iter()
+            .flat_map(|section| used_chunks(&section.code))
+            .filter_map(|name| definitions.get(&chunk_key(name)).copied())
+            .collect();
+
+        let mut output = Vec::new();
+        for index in (0..sections.len()).filter(|i| !used.contains(i)) {
+            self.expand_section(index, &definitions, &mut vec![], "", &mut output)?;
+        }
+
+        // chunks which are not reachable from the top level sections still must be valid
+        for index in (0..sections.len()).filter(|i| used.contains(i)) {
+            self.expand_section(index, &definitions, &mut vec![], "", &mut vec![])?;
+        }
+
+        Ok(output)
+    }
+
+    /// Returns the code of the module with all the chunk placeholders expanded.
+    ///
+    /// See `get_tangled_lines` for details.
+    pub fn get_tangled_code(&self) -> Result<String, LPError> {
+        Ok(self
+            .get_tangled_lines()?
+            .into_iter()
+            .map(|line| line.text)
+            .collect::<Vec<String>>()
+            .join("\n"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn module(sections: Vec<(Option<&str>, &str)>) -> Module {
+        Module {
+            path: PathBuf::from("main.py.lpnb"),
+            sections: Some(
+                sections
+                    .into_iter()
+                    .map(|(header, code)| {
+                        Rc::new(Section {
+                            code: code.to_string(),
+                            docs: "".to_string(),
+                            header: header.map(|h| h.to_string()),
+                            references: vec![],
+                        })
+                    })
+                    .collect(),
+            ),
+        }
+    }
+
+    #[test]
+    fn test_parse_placeholder() {
+        assert_eq!(
+            parse_placeholder("    <<parse arguments>>"),
+            Some(("    ", "parse arguments"))
+        );
+        assert_eq!(parse_placeholder("<< main >>  "), Some(("", "main")));
+        assert_eq!(parse_placeholder("x = <<value>>"), None);
+        assert_eq!(parse_placeholder("<<>>"), None);
+        assert_eq!(parse_placeholder("print(1 << 2 >> 1)"), None);
+    }
+
+    #[test]
+    fn test_used_chunks() {
+        assert_eq!(
+            used_chunks("def main():\n    <<parse arguments>>\n    <<run>>\n"),
+            vec!["parse arguments", "run"]
+        );
+    }
+
+    #[test]
+    fn test_find_chunk() {
+        let module = module(vec![(Some("## Parse arguments"), "args = parse()")]);
+        assert_eq!(
+            module.find_chunk("parse arguments").unwrap().code,
+            "args = parse()"
+        );
+        assert!(module.find_chunk("run").is_none());
+    }
+
+    #[test]
+    fn test_get_tangled_code_without_chunks() {
+        let module = module(vec![(Some("# A"), "a = 1\n"), (None, "b = 2")]);
+        assert_eq!(module.get_tangled_code().unwrap(), "a = 1\n\nb = 2");
+    }
+
+    #[test]
+    fn test_get_tangled_code_keeps_indentation() {
+        let module = module(vec![
+            (
+                Some("# Main"),
+                "def main():\n    <<parse arguments>>\n    run(args)",
+            ),
+            (
+                Some("## Parse arguments"),
+                "args = parse()\nif not args:\n    <<fail>>\n",
+            ),
+            (Some("### Fail"), "exit(1)"),
+            (None, "main()"),
+        ]);
+
+        assert_eq!(
+            module.get_tangled_code().unwrap(),
+            "def main():\n    args = parse()\n    if not args:\n        exit(1)\n    run(args)\nmain()"
+        );
+    }
+
+    #[test]
+    fn test_get_tangled_lines_origins() {
+        let module = module(vec![
+            (Some("# Main"), "def main():\n    <<run>>\n    return 0"),
+            (Some("# Run"), "a()\nb()"),
+        ]);
+
+        let origins: Vec<(usize, usize)> = module
+            .get_tangled_lines()
+            .unwrap()
+            .iter()
+            .map(|line| (line.section, line.line))
+            .collect();
+        assert_eq!(origins, vec![(0, 1), (1, 1), (1, 2), (0, 3)]);
+    }
+
+    #[test]
+    fn test_get_tangled_code_undefined_chunk() {
+        let module = module(vec![(Some("# Main"), "cat <<EOF\n<<missing>>\nEOF")]);
+        assert_eq!(
+            module.get_tangled_code().unwrap(),
+            "cat <<EOF\n<<missing>>\nEOF"
+        );
+    }
+
+    #[test]
+    fn test_get_tangled_code_cycle() {
+        let reachable = module(vec![
+            (None, "<<a>>"),
+            (Some("# A"), "<<b>>"),
+            (Some("# B"), "<<a>>"),
+        ]);
+        match reachable.get_tangled_code() {
+            Err(LPError::ChunkCycle(_, cycle)) => assert_eq!(cycle, "A -> B -> A"),
+            _ => panic!("Expected ChunkCycle error"),
+        }
+
+        let unreachable = module(vec![
+            (None, "x = 1"),
+            (Some("# A"), "<<b>>"),
+            (Some("# B"), "<<a>>"),
+        ]);
+        assert!(matches!(
+            unreachable.get_tangled_code(),
+            Err(LPError::ChunkCycle(..))
+        ));
+    }
+}
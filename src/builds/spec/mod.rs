@@ -1,5 +1,6 @@
 #![forbid(unsafe_code)]
 
+pub mod chunks;
 pub mod module;
 pub mod project;
 pub mod sections;
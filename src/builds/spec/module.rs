@@ -35,7 +35,7 @@ impl Module {
             }
         };
 
-        let literate_file = LiterateFile::new(&content)?;
+        let literate_file = LiterateFile::new(&module_path, &content)?;
 
         Ok(Module {
             sections: Some(literate_file.sections.into_iter().map(Rc::new).collect()),
@@ -126,6 +126,26 @@ sections:
         assert!(result.is_err());
     }
 
+    #[test]
+    fn test_module_new_error_malformed_yaml() {
+        let dir = tempdir().unwrap();
+        let file_path = dir
+            .path()
+            .join(format!("nested/test.py.{}", SYSTEM_FILES_EXTENSION));
+        fs::create_dir_all(file_path.parent().unwrap()).unwrap();
+        fs::write(&file_path, "sections:\n  - code: [\n").unwrap();
+
+        match Module::new(dir.path(), &file_path) {
+            Err(LPError::MalformedModule { path, .. }) => {
+                assert_eq!(
+                    path,
+                    PathBuf::from(format!("nested/test.py.{}", SYSTEM_FILES_EXTENSION))
+                );
+            }
+            _ => panic!("Expected MalformedModule error"),
+        }
+    }
+
     #[test]
     fn test_resolve_relative_module_path_same_dir() {
         let module = Module {
@@ -3,6 +3,7 @@
 use std::path::Path;
 use std::rc::Rc;
 
+use globset::GlobSet;
 use walkdir::WalkDir;
 
 use crate::error::LPError;
@@ -11,28 +12,55 @@ use super::structs::{Module, Project};
 
 impl Project {
     /// Creates a new Project instance.
-    pub fn new(source_dir: &Path) -> Result<Self, LPError> {
-        let modules = WalkDir::new(source_dir)
+    ///
+    /// All the modules are parsed even if some of them are broken,
+    /// so the returned error contains the problems of every broken module.
+    ///
+    /// The files and directories matching `ignore` (relative to `source_dir`) are skipped.
+    pub fn new(source_dir: &Path, ignore: &GlobSet) -> Result<Self, LPError> {
+        let (project, errors) = Project::parse(source_dir, ignore);
+        LPError::from_many(errors)?;
+        Ok(project)
+    }
+
+    /// Parses every module, returning the project of the valid ones and the problems of the broken ones.
+    pub fn parse(source_dir: &Path, ignore: &GlobSet) -> (Self, Vec<LPError>) {
+        let mut modules = Vec::new();
+        let mut errors = Vec::new();
+
+        for entry in WalkDir::new(source_dir)
             .into_iter()
+            .filter_entry(|entry| {
+                entry
+                    .path()
+                    .strip_prefix(source_dir)
+                    .map_or(true, |path| !ignore.is_match(path))
+            })
             .filter_map(|entry| entry.ok())
             .filter(|entry| entry.file_type().is_file())
-            .map(|entry| Module::new(source_dir, entry.path()).map(Rc::new))
-            .collect::<Result<Vec<_>, _>>()?;
+        {
+            match Module::new(source_dir, entry.path()) {
+                Ok(module) => modules.push(Rc::new(module)),
+                Err(e) => errors.push(e),
+            }
+        }
 
-        Ok(Project { modules })
+        (Project { modules }, errors)
     }
 }
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::config::constants::SYSTEM_FILES_EXTENSION;
     use std::fs;
+    use std::path::PathBuf;
     use tempfile::tempdir;
 
     #[test]
     fn test_project_new_empty_dir() {
         let dir = tempdir().unwrap();
-        let project = Project::new(dir.path()).unwrap();
+        let project = Project::new(dir.path(), &GlobSet::empty()).unwrap();
         assert!(project.modules.is_empty());
     }
 
@@ -42,7 +70,7 @@ mod tests {
         fs::write(dir.path().join("file1.txt"), "content").unwrap();
         fs::write(dir.path().join("file2.rs"), "fn main() {}").unwrap();
 
-        let project = Project::new(dir.path()).unwrap();
+        let project = Project::new(dir.path(), &GlobSet::empty()).unwrap();
         assert_eq!(project.modules.len(), 2);
         for module in &project.modules {
             assert!(module.sections.is_none());
@@ -67,7 +95,7 @@ sections:
         )
         .unwrap();
 
-        let project = Project::new(dir.path()).unwrap();
+        let project = Project::new(dir.path(), &GlobSet::empty()).unwrap();
         assert_eq!(project.modules.len(), 1);
         let module = &project.modules[0];
         assert!(module.sections.is_some());
@@ -94,7 +122,7 @@ sections:
         )
         .unwrap();
 
-        let project = Project::new(dir.path()).unwrap();
+        let project = Project::new(dir.path(), &GlobSet::empty()).unwrap();
         assert_eq!(project.modules.len(), 2);
 
         let with_sections = project
@@ -122,10 +150,27 @@ sections:
         fs::write(dir.path().join("subdir/file.txt"), "content").unwrap();
         fs::write(dir.path().join("another/nested/file.txt"), "content").unwrap();
 
-        let project = Project::new(dir.path()).unwrap();
+        let project = Project::new(dir.path(), &GlobSet::empty()).unwrap();
         assert_eq!(project.modules.len(), 3);
     }
 
+    #[test]
+    fn test_project_new_ignored_files() {
+        let dir = tempdir().unwrap();
+        fs::create_dir_all(dir.path().join("drafts/nested")).unwrap();
+        fs::write(dir.path().join("file.txt"), "content").unwrap();
+        fs::write(dir.path().join("file.bak"), "content").unwrap();
+        fs::write(dir.path().join("drafts/nested/file.txt"), "content").unwrap();
+
+        let mut ignore = globset::GlobSetBuilder::new();
+        ignore.add(globset::Glob::new("drafts").unwrap());
+        ignore.add(globset::Glob::new("*.bak").unwrap());
+
+        let project = Project::new(dir.path(), &ignore.build().unwrap()).unwrap();
+        assert_eq!(project.modules.len(), 1);
+        assert_eq!(project.modules[0].path, PathBuf::from("file.txt"));
+    }
+
     #[test]
     fn test_project_new_error_propagation() {
         let dir = tempdir().unwrap();
@@ -148,11 +193,54 @@ sections:
         )
         .unwrap();
 
-        let result = Project::new(dir.path());
+        let result = Project::new(dir.path(), &GlobSet::empty());
         assert!(result.is_err());
         match result {
             Err(LPError::DuplicateHeader(_)) => {}
             _ => panic!("Expected DuplicateHeader error"),
         }
     }
+
+    #[test]
+    fn test_project_new_collects_all_broken_modules() {
+        let dir = tempdir().unwrap();
+        fs::write(
+            dir.path().join(format!("a.py.{}", SYSTEM_FILES_EXTENSION)),
+            "sections: [\n",
+        )
+        .unwrap();
+        fs::write(
+            dir.path().join(format!("b.py.{}", SYSTEM_FILES_EXTENSION)),
+            "sections:\n  - code: x\n",
+        )
+        .unwrap();
+        fs::write(dir.path().join("c.txt"), "content").unwrap();
+
+        match Project::new(dir.path(), &GlobSet::empty()) {
+            Err(LPError::Multiple(errors)) => {
+                assert_eq!(errors.len(), 2);
+                assert!(errors
+                    .iter()
+                    .all(|e| matches!(e, LPError::MalformedModule { .. })));
+            }
+            _ => panic!("Expected Multiple error"),
+        }
+    }
+
+    #[test]
+    fn test_project_parse_keeps_valid_modules() {
+        let dir = tempdir().unwrap();
+        fs::write(
+            dir.path().join(format!("a.py.{}", SYSTEM_FILES_EXTENSION)),
+            "sections: [\n",
+        )
+        .unwrap();
+        fs::write(dir.path().join("b.txt"), "content").unwrap();
+
+        let (project, errors) = Project::parse(dir.path(), &GlobSet::empty());
+        assert_eq!(project.modules.len(), 1);
+        assert_eq!(project.modules[0].path, PathBuf::from("b.txt"));
+        assert_eq!(errors.len(), 1);
+        assert!(matches!(errors[0], LPError::MalformedModule { .. }));
+    }
 }
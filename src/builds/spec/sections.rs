@@ -1,5 +1,7 @@
 #![forbid(unsafe_code)]
 
+use std::{path::Path, rc::Rc};
+
 use pulldown_cmark::{Event, Parser, Tag};
 use serde::{Deserialize, Serialize};
 
@@ -34,27 +36,36 @@ impl RawSection {
     pub fn get_references(&self) -> Vec<Reference> {
         let parser = Parser::new(&self.docs);
         let mut references = Vec::new();
+        let mut current: Option<(String, String)> = None;
+        let mut text = String::new();
 
         for event in parser {
-            if let Event::Start(Tag::Link(_, dest, _)) = event {
-                let dest_str = dest.into_string();
-
-                let path;
-                let header;
-
-                if let Some(hash_pos) = dest_str.find('#') {
-                    path = dest_str[..hash_pos].to_string();
-                    header = dest_str[hash_pos + 1..].to_string();
-                } else {
-                    continue;
+            match event {
+                Event::Start(Tag::Link(_, dest, _)) => {
+                    let dest_str = dest.into_string();
+
+                    if let Some(hash_pos) = dest_str.find('#') {
+                        current = Some((
+                            dest_str[..hash_pos].to_string(),
+                            dest_str[hash_pos + 1..].to_string(),
+                        ));
+                        text.clear();
+                    }
                 }
-
-                if !path.is_empty() || !header.is_empty() {
-                    references.push(Reference {
-                        path: path.into(),
-                        header,
-                    });
+                Event::Text(t) | Event::Code(t) if current.is_some() => text.push_str(&t),
+                Event::SoftBreak | Event::HardBreak if current.is_some() => text.push(' '),
+                Event::End(Tag::Link(..)) => {
+                    if let Some((path, header)) = current.take() {
+                        if !path.is_empty() || !header.is_empty() {
+                            references.push(Reference {
+                                path: path.into(),
+                                header,
+                                text: text.clone(),
+                            });
+                        }
+                    }
                 }
+                _ => {}
             }
         }
 
@@ -91,18 +102,189 @@ struct RawLiterateFile {
     sections: Vec<RawSection>,
 }
 
+/// How many lines before the erroneous one are shown in a snippet
+const SNIPPET_CONTEXT_LINES: usize = 2;
+
+/// Renders the lines around `line` (1-based) with a caret under `column` (1-based)
+fn render_snippet(content: &str, line: usize, column: usize) -> String {
+    let lines: Vec<&str> = content.lines().collect();
+    if line == 0 || line > lines.len() {
+        return String::new();
+    }
+
+    let first = line.saturating_sub(SNIPPET_CONTEXT_LINES).max(1);
+    let width = line.to_string().len();
+    let mut snippet = Vec::new();
+    for (number, text) in lines.iter().enumerate().take(line).skip(first - 1) {
+        snippet.push(format!("{:>width$} | {}", number + 1, text, width = width));
+    }
+    snippet.push(format!(
+        "{:>width$} | {}^",
+        "",
+        " ".repeat(column.saturating_sub(1)),
+        width = width
+    ));
+    snippet.join("\n")
+}
+
 impl RawLiterateFile {
-    pub fn new(content: &str) -> Self {
-        serde_yaml::from_str(content).unwrap()
+    /// Parses the YAML content of the module at `path`.
+    /// Returns `LPError::MalformedModule` pointing to the offending place if the content is not a valid module.
+    pub fn new(path: &Path, content: &str) -> Result<Self, LPError> {
+        serde_yaml::from_str(content).map_err(|e| {
+            let (line, column) = e
+                .location()
+                .map(|location| (location.line(), location.column()))
+                .unwrap_or((1, 1));
+            LPError::MalformedModule {
+                path: path.to_path_buf(),
+                line,
+                column,
+                message: e.to_string(),
+                snippet: render_snippet(content, line, column),
+            }
+        })
+    }
+}
+
+/// Where the code of a section is in the module's YAML content
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CodeLines {
+    /// the line (1-based) holding the first line of the code
+    pub start: usize,
+    /// whether each following line of the code is on the following line of the content,
+    /// otherwise only the first line is known, e.g. for folded or quoted scalars
+    pub exact: bool,
+}
+
+/// How the value of a `code` key is written
+#[derive(Debug, PartialEq)]
+enum CodeStyle {
+    /// `code: |`, the lines are kept as they are
+    Literal,
+    /// `code: >`, the lines are joined
+    Folded,
+    /// `code: x = 1`
+    Plain,
+    /// `code: "x = 1"` or `code: 'x = 1'`, may contain escapes
+    Quoted,
+}
+
+/// Scans the block style list of sections for their `code` keys,
+/// returns the line (1-based) where each section's code starts and its style.
+///
+/// The keys of an item are expected at the column of its first key, e.g. `- docs: ...` followed by `  code: |`.
+fn scan_code_starts(lines: &[&str]) -> Vec<Option<(usize, CodeStyle)>> {
+    let indent_of = |line: &str| line.len() - line.trim_start().len();
+
+    let Some(list_start) = lines.iter().position(|line| line.trim_end() == "sections:") else {
+        return vec![];
+    };
+    let Some(item_indent) = lines[list_start + 1..]
+        .iter()
+        .find(|line| !line.trim().is_empty())
+        .filter(|line| line.trim_start().starts_with('-'))
+        .map(|line| indent_of(line))
+    else {
+        return vec![];
+    };
+
+    let mut result: Vec<Option<(usize, CodeStyle)>> = Vec::new();
+    let mut key_indent = None;
+    for (number, line) in lines.iter().enumerate().skip(list_start + 1) {
+        let indent = indent_of(line);
+        let mut key = line.trim_start();
+        if key.is_empty() || key.starts_with('#') && indent <= item_indent {
+            continue;
+        }
+        if indent < item_indent || indent == item_indent && !key.starts_with('-') {
+            break;
+        }
+        if indent == item_indent {
+            result.push(None);
+            let rest = key[1..].trim_start();
+            key_indent = (!rest.is_empty()).then_some(line.len() - rest.len());
+            key = rest;
+        } else if key_indent.is_none() {
+            key_indent = Some(indent);
+        } else if key_indent != Some(indent) {
+            continue;
+        }
+
+        let (Some(value), Some(last)) = (key.strip_prefix("code:"), result.last_mut()) else {
+            continue;
+        };
+        *last = Some(match value.trim().chars().next() {
+            Some('|') => (number + 2, CodeStyle::Literal),
+            Some('>') => (number + 2, CodeStyle::Folded),
+            Some('"' | '\'') => (number + 1, CodeStyle::Quoted),
+            _ => (number + 1, CodeStyle::Plain),
+        });
+    }
+    result
+}
+
+/// Checks the located start against the parsed code,
+/// returns None if the code isn't there
+fn code_lines(lines: &[&str], start: usize, style: CodeStyle, code: &str) -> Option<CodeLines> {
+    let source = |i: usize| lines.get(start - 1 + i).map(|line| line.trim());
+    let first_line = code.lines().next().unwrap_or_default().trim();
+    let exact = match style {
+        CodeStyle::Literal => {
+            if source(0)? != first_line {
+                return None;
+            }
+            code.lines()
+                .enumerate()
+                .all(|(i, line)| source(i) == Some(line.trim()))
+        }
+        CodeStyle::Folded => {
+            // the first line of the code is joined from several lines of the content
+            if !first_line.starts_with(source(0)?) {
+                return None;
+            }
+            false
+        }
+        CodeStyle::Plain => {
+            let value = source(0)?.split_once("code:")?.1.trim();
+            if !first_line.starts_with(value) {
+                return None;
+            }
+            value == code.trim()
+        }
+        CodeStyle::Quoted => false,
+    };
+    Some(CodeLines { start, exact })
+}
+
+/// Finds where the code of each section is in the module's YAML content.
+///
+/// For block scalars (`code: |`) it starts on the line after the key, for inline ones on the line of the key.
+/// The lines are found by scanning the block style list of sections and checked against the parsed `sections`.
+/// Returns None if the layout isn't supported, e.g. for flow-style sections.
+pub fn locate_code_lines(content: &str, sections: &[Rc<Section>]) -> Option<Vec<CodeLines>> {
+    let lines: Vec<&str> = content.lines().collect();
+    let starts = scan_code_starts(&lines);
+    if starts.len() != sections.len() {
+        return None;
     }
+    starts
+        .into_iter()
+        .zip(sections)
+        .map(|(start, section)| {
+            let (start, style) = start?;
+            code_lines(&lines, start, style, &section.code)
+        })
+        .collect()
 }
 
 impl LiterateFile {
     /// Returns a new LiterateFile instance.
-    /// Returns an error if the file's sections have duplicate headers.
+    /// `path` is used only to point to the module in errors.
+    /// Returns an error if the file is not a valid YAML module or its sections have duplicate headers.
     /// References are not validated.
-    pub fn new(content: &str) -> Result<Self, LPError> {
-        let raw_lit_file = RawLiterateFile::new(content);
+    pub fn new(path: &Path, content: &str) -> Result<Self, LPError> {
+        let raw_lit_file = RawLiterateFile::new(path, content)?;
 
         let mut sections = Vec::new();
         let mut seen_headers = std::collections::HashSet::new();
@@ -135,7 +317,46 @@ impl LiterateFile {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use std::path::Path;
+
+    fn parse_sections(content: &str) -> Vec<Rc<Section>> {
+        LiterateFile::new(Path::new("m.lpnb"), content)
+            .unwrap()
+            .sections
+            .into_iter()
+            .map(Rc::new)
+            .collect()
+    }
+
+    #[test]
+    fn test_locate_code_lines() {
+        let lines = |content: &str| locate_code_lines(content, &parse_sections(content));
+        let exact = |start: usize| CodeLines { start, exact: true };
+        let first_only = |start: usize| CodeLines {
+            start,
+            exact: false,
+        };
+
+        let content = "sections:\n  - docs: >-\n      # A\n\n      code: not a key\n    code: |-\n      a = 1\n      b = 2\n  - code: x = 1\n    docs: text\n  - docs: empty code\n    code: ''\n";
+        assert_eq!(
+            lines(content),
+            Some(vec![exact(7), exact(9), first_only(12)])
+        );
+
+        let content = "sections:\n-   docs: '# A'\n    code: >\n        a = 1\n        b = 2\n-\n  code: \"a\\nb\"\n  docs: B\n";
+        assert_eq!(lines(content), Some(vec![first_only(4), first_only(7)]));
+    }
+
+    #[test]
+    fn test_locate_code_lines_unsupported_layout() {
+        let lines = |content: &str| locate_code_lines(content, &parse_sections(content));
+
+        assert_eq!(lines("sections: [{code: x, docs: y}]"), None);
+        assert_eq!(lines("sections:\n  - {docs: y,\n     code: x}\n"), None);
+        assert_eq!(
+            lines("sections:\n  - docs: a\n    code: &c x = 1\n  - docs: b\n    code: *c\n"),
+            None
+        );
+    }
 
     #[test]
     fn test_raw_section_get_header() {
@@ -197,6 +418,16 @@ mod tests {
         assert_eq!(refs[0].header, "header1");
         assert_eq!(refs[1].path, Path::new("other"));
         assert_eq!(refs[1].header, "header2");
+        assert_eq!(refs[0].text, "one");
+        assert_eq!(refs[1].text, "two");
+
+        let section = RawSection {
+            code: "".to_string(),
+            docs: "Uses [the `App`\nclass](app#App) and [a file](static/spec.txt)".to_string(),
+        };
+        let refs = section.get_references();
+        assert_eq!(refs.len(), 1);
+        assert_eq!(refs[0].text, "the App class");
 
         let section = RawSection {
             code: "".to_string(),
@@ -251,7 +482,7 @@ sections:
         ## World Function ##
         This function says world.
 "#;
-        let result = LiterateFile::new(content);
+        let result = LiterateFile::new(Path::new("test.lpnb"), content);
         assert!(result.is_ok());
         let lit_file = result.unwrap();
         assert_eq!(lit_file.sections.len(), 2);
@@ -280,7 +511,7 @@ sections:
         # Duplicate Header
         This function says world.
 "#;
-        let result = LiterateFile::new(content);
+        let result = LiterateFile::new(Path::new("test.lpnb"), content);
         assert!(result.is_err());
         match result {
             Err(LPError::DuplicateHeader(header)) => {
@@ -305,11 +536,47 @@ sections:
         # Other
         This is another section.
 "#;
-        let result = LiterateFile::new(content);
+        let result = LiterateFile::new(Path::new("test.lpnb"), content);
         assert!(result.is_ok());
         let lit_file = result.unwrap();
         assert_eq!(lit_file.sections[0].references.len(), 1);
         assert_eq!(lit_file.sections[0].references[0].path, Path::new(""));
         assert_eq!(lit_file.sections[0].references[0].header, "other");
     }
+
+    #[test]
+    fn test_literate_file_malformed_yaml() {
+        let content = "sections:\n  - code: |\n      fn main() {}\n    docs: [unclosed\n";
+        let result = LiterateFile::new(Path::new("dir/broken.rs.lpnb"), content);
+        match result {
+            Err(LPError::MalformedModule {
+                path,
+                line,
+                column,
+                snippet,
+                ..
+            }) => {
+                assert_eq!(path, Path::new("dir/broken.rs.lpnb"));
+                assert!(line > 1);
+                assert!(column > 0);
+                assert!(snippet.contains('^'));
+            }
+            _ => panic!("Expected MalformedModule error"),
+        }
+    }
+
+    #[test]
+    fn test_literate_file_missing_sections() {
+        let result = LiterateFile::new(Path::new("empty.lpnb"), "metadata: {}\n");
+        assert!(matches!(result, Err(LPError::MalformedModule { .. })));
+    }
+
+    #[test]
+    fn test_render_snippet() {
+        let content = "a: 1\nb: 2\nc: [\nd: 4";
+        let snippet = render_snippet(content, 3, 4);
+        assert_eq!(snippet, "1 | a: 1\n2 | b: 2\n3 | c: [\n  |    ^");
+
+        assert_eq!(render_snippet(content, 10, 1), "");
+    }
 }
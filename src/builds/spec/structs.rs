@@ -19,6 +19,11 @@ pub struct Reference {
     ///
     /// E.g. if the header is `## Some header` then this would be `Some header`, not `## Some header` or `Some-header`
     pub header: String,
+
+    /// The text of the link, as it's shown in the documentation.
+    ///
+    /// E.g. for `[Main App](app/app#Main-App)` it's `Main App`
+    pub text: String,
 }
 
 /// Section is the smallest unit of the literate programming system.
@@ -1,6 +1,6 @@
 #![forbid(unsafe_code)]
 
-use std::path::{Path, PathBuf};
+use std::path::{Component, Path, PathBuf};
 
 use crate::config::constants::SYSTEM_FILES_EXTENSION;
 
@@ -35,6 +35,7 @@ pub fn get_module_extension(module: &Path) -> String {
     let without_main_extension = prepare_module_file_extension(module);
     without_main_extension
         .extension()
+        .or_else(|| without_main_extension.file_name())
         .unwrap_or(without_main_extension.as_os_str())
         .to_string_lossy()
         .to_string()
@@ -58,6 +59,46 @@ pub fn header_to_anchor(header: &str) -> String {
     header.trim().replace(' ', "-")
 }
 
+/// Returns the path to `to` relative to the `from_dir` directory, always with `/` as a separator.
+///
+/// Both paths should be relative to the same root.
+///
+/// E.g. `dir/a` and `lib/b.md` -> `../../lib/b.md`
+pub fn relative_path(from_dir: &Path, to: &Path) -> String {
+    let from: Vec<Component> = from_dir.components().collect();
+    let to: Vec<Component> = to.components().collect();
+    let common = from
+        .iter()
+        .zip(to.iter())
+        .take_while(|(a, b)| a == b)
+        .count();
+
+    let mut parts: Vec<String> = vec!["..".to_string(); from.len() - common];
+    parts.extend(
+        to[common..]
+            .iter()
+            .map(|c| c.as_os_str().to_string_lossy().to_string()),
+    );
+    parts.join("/")
+}
+
+/// Levenshtein distance between two strings, counted in chars
+pub fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+
+    for (i, a_char) in a.chars().enumerate() {
+        let mut current = vec![i + 1; b.len() + 1];
+        for (j, b_char) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(a_char != *b_char);
+            current[j + 1] = substitution.min(previous[j + 1] + 1).min(current[j] + 1);
+        }
+        previous = current;
+    }
+
+    previous[b.len()]
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -104,6 +145,9 @@ mod tests {
         let path = PathBuf::from("Dockerfile.lpnb");
         assert_eq!(get_module_extension(&path), "Dockerfile");
 
+        let path = PathBuf::from("build/Makefile.lpnb");
+        assert_eq!(get_module_extension(&path), "Makefile");
+
         let path = PathBuf::from("file.txt.lpnb");
         assert_eq!(get_module_extension(&path), "txt");
     }
@@ -134,4 +178,34 @@ mod tests {
             "hyphens-and!other-signs?"
         );
     }
+
+    #[test]
+    fn test_relative_path() {
+        assert_eq!(
+            relative_path(Path::new("/t/docs"), Path::new("/t/docs/a.md")),
+            "a.md"
+        );
+        assert_eq!(
+            relative_path(Path::new("/t/docs/entry"), Path::new("/t/docs/app/app.md")),
+            "../app/app.md"
+        );
+        assert_eq!(
+            relative_path(Path::new("dir/a"), Path::new("lib/b.md")),
+            "../../lib/b.md"
+        );
+        assert_eq!(
+            relative_path(Path::new("dir"), Path::new("dir/sub/c.md")),
+            "sub/c.md"
+        );
+    }
+
+    #[test]
+    fn test_edit_distance() {
+        assert_eq!(edit_distance("", ""), 0);
+        assert_eq!(edit_distance("Main-App", "Main-App"), 0);
+        assert_eq!(edit_distance("Main-Ap", "Main-App"), 1);
+        assert_eq!(edit_distance("kitten", "sitting"), 3);
+        assert_eq!(edit_distance("", "abc"), 3);
+        assert_eq!(edit_distance("Простота", "Простоты"), 1);
+    }
 }
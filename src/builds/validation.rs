@@ -0,0 +1,226 @@
+#![forbid(unsafe_code)]
+
+use std::path::PathBuf;
+
+use crate::error::{BrokenReference, LPError};
+
+use super::{
+    index::ProjectIndex,
+    spec::{structs::Project, utils::edit_distance},
+};
+
+/// How many closest headers are suggested for a misspelled reference
+const MAX_SUGGESTIONS: usize = 3;
+
+/// Returns the headers of the module which are close enough to the given one, the closest first.
+fn closest_headers(index: &ProjectIndex, module_path: &PathBuf, header: &str) -> Vec<String> {
+    let max_distance = (header.chars().count() / 3).max(1);
+
+    let mut candidates: Vec<(usize, &String)> = index
+        .get_headers(module_path)
+        .into_iter()
+        .map(|candidate| (edit_distance(header, candidate), candidate))
+        .filter(|(distance, _)| *distance <= max_distance)
+        .collect();
+    candidates.sort();
+
+    candidates
+        .into_iter()
+        .take(MAX_SUGGESTIONS)
+        .map(|(_, candidate)| candidate.clone())
+        .collect()
+}
+
+/// Validates all the references of the project in one pass.
+///
+/// Returns every incorrect reference found, not only the first one.
+pub fn validate_references(project: &Project, index: &ProjectIndex) -> Result<(), LPError> {
+    let mut errors = Vec::new();
+
+    for module in &project.modules {
+        let Some(sections) = &module.sections else {
+            continue;
+        };
+
+        for (section_index, section) in sections.iter().enumerate() {
+            for reference in &section.references {
+                let referenced_module_path = module.resolve_relative_module_path(&reference.path);
+                if index
+                    .get_section(&referenced_module_path, &reference.header)
+                    .is_none()
+                {
+                    let suggestions =
+                        closest_headers(index, &referenced_module_path, &reference.header);
+                    errors.push(LPError::IncorrectReference(Box::new(BrokenReference {
+                        module: module.path.clone(),
+                        section: section_index,
+                        text: reference.text.clone(),
+                        target: referenced_module_path,
+                        header: reference.header.clone(),
+                        suggestions,
+                    })));
+                }
+            }
+        }
+    }
+
+    LPError::from_many(errors)
+}
+
+/// Validates the chunk placeholders of all the modules in one pass.
+///
+/// Returns every chunks cycle found, at most one per module.
+pub fn validate_chunks(project: &Project) -> Result<(), LPError> {
+    let errors = project
+        .modules
+        .iter()
+        .filter(|module| module.sections.is_some())
+        .filter_map(|module| module.get_tangled_code().err())
+        .collect();
+    LPError::from_many(errors)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builds::spec::structs::{Module, Reference, Section};
+    use std::rc::Rc;
+
+    fn section(header: Option<&str>, references: Vec<Reference>) -> Rc<Section> {
+        Rc::new(Section {
+            code: "".to_string(),
+            docs: "".to_string(),
+            header: header.map(|h| h.to_string()),
+            references,
+        })
+    }
+
+    fn reference(path: &str, header: &str) -> Reference {
+        Reference {
+            path: PathBuf::from(path),
+            header: header.to_string(),
+            text: "link".to_string(),
+        }
+    }
+
+    fn create_test_project() -> Rc<Project> {
+        let lib = Rc::new(Module {
+            path: PathBuf::from("lib/primes.py.lpnb"),
+            sections: Some(vec![
+                section(Some("# Is Prime"), vec![]),
+                section(Some("# Next Prime"), vec![]),
+            ]),
+        });
+        let app = Rc::new(Module {
+            path: PathBuf::from("app/app.py.lpnb"),
+            sections: Some(vec![
+                section(Some("# App"), vec![reference("../lib/primes", "Is-Prime")]),
+                section(
+                    None,
+                    vec![
+                        reference("../lib/primes", "Is-Prme"),
+                        reference("../lib/missing", "App"),
+                        reference("", "Ap"),
+                    ],
+                ),
+            ]),
+        });
+        Rc::new(Project {
+            modules: vec![lib, app],
+        })
+    }
+
+    #[test]
+    fn test_validate_references_collects_all() {
+        let project = create_test_project();
+        let index = ProjectIndex::new(Rc::clone(&project));
+
+        let errors = match validate_references(&project, &index) {
+            Err(LPError::Multiple(errors)) => errors,
+            _ => panic!("Expected Multiple error"),
+        };
+        assert_eq!(errors.len(), 3);
+
+        match &errors[0] {
+            LPError::IncorrectReference(broken) => {
+                assert_eq!(broken.module, PathBuf::from("app/app.py.lpnb"));
+                assert_eq!(broken.section, 1);
+                assert_eq!(broken.target, PathBuf::from("lib/primes"));
+                assert_eq!(broken.header, "Is-Prme");
+                assert_eq!(broken.suggestions, vec!["Is-Prime".to_string()]);
+            }
+            _ => panic!("Expected IncorrectReference error"),
+        }
+
+        match &errors[1] {
+            LPError::IncorrectReference(broken) => assert!(broken.suggestions.is_empty()),
+            _ => panic!("Expected IncorrectReference error"),
+        }
+
+        match &errors[2] {
+            LPError::IncorrectReference(broken) => {
+                assert_eq!(broken.target, PathBuf::from("app/app"));
+                assert_eq!(broken.suggestions, vec!["App".to_string()]);
+                assert!(broken.to_string().ends_with("did you mean: App?"));
+            }
+            _ => panic!("Expected IncorrectReference error"),
+        }
+    }
+
+    #[test]
+    fn test_validate_references_ok() {
+        let project = Rc::new(Project {
+            modules: vec![Rc::new(Module {
+                path: PathBuf::from("main.py.lpnb"),
+                sections: Some(vec![
+                    section(Some("# Main"), vec![reference("", "Helper")]),
+                    section(Some("# Helper"), vec![]),
+                ]),
+            })],
+        });
+        let index = ProjectIndex::new(Rc::clone(&project));
+
+        assert!(validate_references(&project, &index).is_ok());
+    }
+
+    #[test]
+    fn test_validate_chunks() {
+        let module = |path: &str, code: &str| {
+            Rc::new(Module {
+                path: PathBuf::from(path),
+                sections: Some(vec![Rc::new(Section {
+                    code: code.to_string(),
+                    docs: "# Loop".to_string(),
+                    header: Some("# Loop".to_string()),
+                    references: vec![],
+                })]),
+            })
+        };
+        let project = Project {
+            modules: vec![
+                module("a.py.lpnb", "<<loop>>"),
+                module("b.py.lpnb", "<<missing>>"),
+                module("c.py.lpnb", "<<Loop>>"),
+            ],
+        };
+
+        match validate_chunks(&project) {
+            Err(LPError::Multiple(errors)) => assert_eq!(errors.len(), 2),
+            _ => panic!("Expected Multiple error"),
+        }
+    }
+
+    #[test]
+    fn test_closest_headers() {
+        let project = create_test_project();
+        let index = ProjectIndex::new(Rc::clone(&project));
+        let path = PathBuf::from("lib/primes");
+
+        assert_eq!(
+            closest_headers(&index, &path, "Next-Prim"),
+            vec!["Next-Prime".to_string()]
+        );
+        assert_eq!(closest_headers(&index, &path, "Nxt-Prime")[0], "Next-Prime");
+        assert!(closest_headers(&index, &path, "Something-Else").is_empty());
+    }
+}
@@ -0,0 +1,216 @@
+#![forbid(unsafe_code)]
+
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    thread,
+    time::{Duration, Instant, SystemTime},
+};
+
+use walkdir::WalkDir;
+
+use crate::{cli::structs::Params, config::config::Config, error::LPError};
+
+use super::builder::Builder;
+
+/// How often the watched directories are scanned for changes
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Modification time and size of every file in the watched directories
+type Snapshot = HashMap<PathBuf, (SystemTime, u64)>;
+
+/// Watcher rebuilds the project every time something changes in the source or the plugins directory
+/// or in the project configuration file.
+pub struct Watcher {
+    /// the CLI params the configuration is loaded again from when the project file changes
+    params: Params,
+    config: Config,
+}
+
+/// Returns the state of all the files under the `dirs`, missing directories are skipped
+fn take_snapshot(dirs: &[&Path]) -> Snapshot {
+    dirs.iter()
+        .flat_map(|dir| WalkDir::new(dir).into_iter().filter_map(Result::ok))
+        .filter(|entry| entry.file_type().is_file())
+        .filter_map(|entry| {
+            let metadata = entry.metadata().ok()?;
+            Some((
+                entry.path().to_path_buf(),
+                (metadata.modified().ok()?, metadata.len()),
+            ))
+        })
+        .collect()
+}
+
+/// Returns the files which were added, changed or removed between the snapshots, sorted
+fn changed_files(old: &Snapshot, new: &Snapshot) -> Vec<PathBuf> {
+    let mut changed: Vec<PathBuf> = new
+        .iter()
+        .filter(|(path, state)| old.get(*path) != Some(state))
+        .map(|(path, _)| path.clone())
+        .chain(old.keys().filter(|path| !new.contains_key(*path)).cloned())
+        .collect();
+    changed.sort();
+    changed
+}
+
+impl Watcher {
+    /// Creates a new Watcher instance.
+    /// # Arguments
+    /// * `params` - the CLI params the `config` was loaded from.
+    /// * `config` - a Config instance that contains the configuration of the project.
+    pub fn new(params: Params, config: Config) -> Self {
+        Watcher { params, config }
+    }
+
+    /// Builds the project with freshly loaded plugins.
+    ///
+    /// Only the outputs affected by the changes are rewritten, thanks to the build cache.
+    fn build(&self, config: Config) -> Result<usize, LPError> {
+        Builder::new(config)?.build()
+    }
+
+    /// Prints a single status line describing the build result
+    fn report(&self, trigger: &str, started: Instant, result: Result<usize, LPError>) {
+        let elapsed = started.elapsed().as_millis();
+        match result {
+            Ok(rewritten) => println!(
+                "[ok] {}: {} file(s) updated in {} ms",
+                trigger, rewritten, elapsed
+            ),
+            Err(e) => println!("[failed] {} in {} ms:\n{}", trigger, elapsed, e),
+        }
+    }
+
+    /// Returns the state of the source and the plugins directories and the project file
+    fn snapshot(&self) -> Snapshot {
+        take_snapshot(&[
+            self.config.source_dir.as_path(),
+            self.config.code_plugins_dir.as_path(),
+            self.config.project_file.as_path(),
+        ])
+    }
+
+    /// Rebuilds the project if anything changed since the `snapshot`, returns the new snapshot.
+    ///
+    /// If the project file changed, the configuration is loaded again before the build.
+    /// An invalid project file or a failed build is reported and the watching goes on with the previous configuration.
+    fn poll(&mut self, snapshot: &Snapshot) -> Snapshot {
+        let new_snapshot = self.snapshot();
+        let changed = changed_files(snapshot, &new_snapshot);
+        if changed.is_empty() {
+            return new_snapshot;
+        }
+
+        let trigger = match changed.as_slice() {
+            [path] => format!("{} changed", path.display()),
+            _ => format!("{} files changed", changed.len()),
+        };
+        let started = Instant::now();
+        if changed.contains(&self.config.project_file) {
+            match Config::load(&self.params) {
+                Ok(config) => self.config = config,
+                Err(e) => {
+                    self.report(&trigger, started, Err(e));
+                    return new_snapshot;
+                }
+            }
+        }
+        self.config.force = false;
+        self.report(&trigger, started, self.build(self.config.clone()));
+
+        // the reloaded configuration may point to other directories
+        self.snapshot()
+    }
+
+    /// Builds the project and then keeps rebuilding it on every change, never returns.
+    ///
+    /// Build errors are reported and the watching goes on, so a broken edit can be fixed by the next one.
+    pub fn run(mut self) -> ! {
+        let mut snapshot = self.snapshot();
+
+        let started = Instant::now();
+        self.report("initial build", started, self.build(self.config.clone()));
+        eprintln!(
+            "Watching {:?}, {:?} and {:?} for changes",
+            self.config.source_dir, self.config.code_plugins_dir, self.config.project_file
+        );
+
+        loop {
+            thread::sleep(POLL_INTERVAL);
+            snapshot = self.poll(&snapshot);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_changed_files() {
+        let dir = tempdir().unwrap();
+        let missing = dir.path().join("missing");
+        fs::write(dir.path().join("a"), "a").unwrap();
+        fs::write(dir.path().join("b"), "b").unwrap();
+        let old = take_snapshot(&[dir.path(), &missing]);
+        assert_eq!(old.len(), 2);
+        assert!(changed_files(&old, &take_snapshot(&[dir.path()])).is_empty());
+
+        fs::write(dir.path().join("a"), "changed").unwrap();
+        fs::remove_file(dir.path().join("b")).unwrap();
+        fs::write(dir.path().join("c"), "c").unwrap();
+        let new = take_snapshot(&[dir.path()]);
+
+        assert_eq!(
+            changed_files(&old, &new),
+            vec![
+                dir.path().join("a"),
+                dir.path().join("b"),
+                dir.path().join("c")
+            ]
+        );
+    }
+
+    #[test]
+    fn test_poll_goes_on_after_failed_build() {
+        let dir = tempdir().unwrap();
+        let src = dir.path().join("src");
+        fs::create_dir_all(&src).unwrap();
+        fs::write(src.join("notes.txt"), "notes").unwrap();
+        let project_file = dir.path().join("lp.yaml");
+        fs::write(
+            &project_file,
+            "source_dir: src\ntarget_dir: out\nplugins_dir: plugins\n",
+        )
+        .unwrap();
+        let params = Params {
+            src_dir: None,
+            target_dir: None,
+            plugins_dir: None,
+            config: Some(project_file.clone()),
+            force: false,
+            command: None,
+        };
+        let config = Config::load(&params).unwrap();
+        let mut watcher = Watcher::new(params, config);
+        let copied = dir.path().join("out").join("code").join("notes.txt");
+
+        let snapshot = watcher.snapshot();
+        fs::write(src.join("broken.py.lpnb"), "sections: [\n").unwrap();
+        let snapshot = watcher.poll(&snapshot);
+        assert!(snapshot.contains_key(&src.join("broken.py.lpnb")));
+        assert!(!copied.exists());
+
+        fs::write(
+            &project_file,
+            "source_dir: src\ntarget_dir: out\nplugins_dir: plugins\nignore: ['*.lpnb']\n",
+        )
+        .unwrap();
+        watcher.poll(&snapshot);
+        assert!(watcher.config.ignore.is_match("broken.py.lpnb"));
+        assert_eq!(fs::read_to_string(&copied).unwrap(), "notes");
+    }
+}
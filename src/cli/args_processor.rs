@@ -17,11 +17,15 @@ impl ParamsProcessor {
         Self {}
     }
 
+    /// Only the explicitly passed source directory is validated here,
+    /// the one resolved with the configuration file is validated by `Config::load`.
     fn validate_params(&self, params: &Params) -> Option<LPError> {
-        if !Path::new(&params.src_dir).is_dir() {
-            return Some(LPError::SourceDirectoryNotFound(
-                params.src_dir.to_string_lossy().to_string(),
-            ));
+        if let Some(src_dir) = &params.src_dir {
+            if !Path::new(src_dir).is_dir() {
+                return Some(LPError::SourceDirectoryNotFound(
+                    src_dir.to_string_lossy().to_string(),
+                ));
+            }
         }
         None
     }
@@ -49,10 +53,12 @@ mod tests {
         let processor = ParamsProcessor::new();
         let temp_dir = tempdir().unwrap();
         let params = Params {
-            src_dir: temp_dir.path().to_path_buf(),
-            target_dir: PathBuf::from("target"),
-            plugins_dir: PathBuf::from("plugins"),
+            src_dir: Some(temp_dir.path().to_path_buf()),
+            target_dir: Some(PathBuf::from("target")),
+            plugins_dir: Some(PathBuf::from("plugins")),
+            config: None,
             force: false,
+            command: None,
         };
 
         assert!(processor.validate_params(&params).is_none());
@@ -62,10 +68,12 @@ mod tests {
     fn test_validate_params_invalid() {
         let processor = ParamsProcessor::new();
         let params = Params {
-            src_dir: PathBuf::from("tests_invalid"),
-            target_dir: PathBuf::from("target"),
-            plugins_dir: PathBuf::from("plugins"),
+            src_dir: Some(PathBuf::from("tests_invalid")),
+            target_dir: Some(PathBuf::from("target")),
+            plugins_dir: Some(PathBuf::from("plugins")),
+            config: None,
             force: false,
+            command: None,
         };
 
         assert!(processor.validate_params(&params).is_some());
@@ -1,13 +1,9 @@
 #![forbid(unsafe_code)]
 //! structs for CLI arguments and parameters
 
-use clap::Parser;
+use clap::{Parser, Subcommand};
 use std::path::PathBuf;
 
-use crate::config::constants::{
-    DEFAULT_PLUGINS_DIR_NAME, DEFAULT_SOURCE_DIR_NAME, DEFAULT_TARGETS_DIR_NAME,
-};
-
 /// CLI arguments
 #[derive(Parser, Debug)]
 #[clap(
@@ -18,20 +14,49 @@ use crate::config::constants::{
 )]
 #[clap(version)]
 pub struct Params {
-    /// Source directory [default: DEFAULT_SOURCE_DIR_NAME]
-    #[clap(short='s', long, default_value = DEFAULT_SOURCE_DIR_NAME)]
-    pub src_dir: PathBuf,
+    /// Source directory, overrides the configuration file [default: src]
+    #[clap(short = 's', long, global = true)]
+    pub src_dir: Option<PathBuf>,
+
+    /// Target directory, overrides the configuration file [default: targets]
+    #[clap(short = 't', long, global = true)]
+    pub target_dir: Option<PathBuf>,
 
-    /// Target directory [default: DEFAULT_TARGETS_DIR_NAME]
-    #[clap(short='t', long, default_value = DEFAULT_TARGETS_DIR_NAME)]
-    pub target_dir: PathBuf,
+    /// Plugins directory, overrides the configuration file [default: plugins]
+    #[clap(short = 'p', long, global = true)]
+    pub plugins_dir: Option<PathBuf>,
 
-    /// Plugins directory [default: DEFAULT_PLUGINS_DIR_NAME]
-    #[clap(short='p', long, default_value = DEFAULT_PLUGINS_DIR_NAME)]
-    pub plugins_dir: PathBuf,
+    /// Project configuration file, `lp.yaml` in the current directory is used if it exists
+    #[clap(short = 'c', long, global = true)]
+    pub config: Option<PathBuf>,
 
     /// Clear the target directories before building.
     /// If not enabled, then existing files will be overwritten only if the same file appears after the build process.
     #[clap(short = 'f', long)]
     pub force: bool,
+
+    /// What to do instead of building the project
+    #[clap(subcommand)]
+    pub command: Option<Command>,
+}
+
+/// CLI subcommands
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Validate the project without writing anything to the target directory.
+    /// Problems are printed to stdout as JSON, one per line.
+    Check {
+        /// Also run the plugins' import and cleaning functions in memory
+        #[clap(long)]
+        run_plugins: bool,
+    },
+    /// Build the project and rebuild it every time the sources or the plugins change.
+    /// Only the affected outputs are rewritten, build errors don't stop the watching.
+    Watch,
+    /// Find the section of a literate module a line of the generated code comes from.
+    /// Uses the source map written next to the generated file.
+    Locate {
+        /// Location in the generated code, e.g. `targets/code/app/app.py:42`
+        location: String,
+    },
 }
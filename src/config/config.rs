@@ -2,7 +2,17 @@
 
 use std::path::{Path, PathBuf};
 
-use super::constants::{CODE_SUBDIR_NAME, DOCS_SUBDIR_NAME};
+use globset::{Glob, GlobSet, GlobSetBuilder};
+
+use crate::{cli::structs::Params, error::LPError};
+
+use super::{
+    constants::{
+        BUILD_CACHE_FILE_NAME, CODE_SUBDIR_NAME, DEFAULT_PLUGINS_DIR_NAME, DEFAULT_SOURCE_DIR_NAME,
+        DEFAULT_TARGETS_DIR_NAME, DOCS_SUBDIR_NAME, PROJECT_FILE_NAME,
+    },
+    project_file::{DocsSettings, PluginsSettings, ProjectFile},
+};
 
 #[derive(Clone)]
 pub struct Config {
@@ -10,12 +20,33 @@ pub struct Config {
     pub docs_dir: PathBuf,
     /// the directory where the code will be generated
     pub code_dir: PathBuf,
+    /// the file where the build cache is stored between the builds
+    pub cache_file: PathBuf,
     /// the directory where the code plugins are located
     pub code_plugins_dir: PathBuf,
     /// the source project directory
     pub source_dir: PathBuf,
     /// clear the target directory before building
     pub force: bool,
+    /// the files within the source directory which are not a part of the project
+    pub ignore: GlobSet,
+    pub plugins: PluginsSettings,
+    pub docs: DocsSettings,
+    /// the project configuration file, it may not exist
+    pub project_file: PathBuf,
+}
+
+/// Compiles the ignore globs of the configuration file at `path`
+fn build_ignore(path: &Path, globs: &[String]) -> Result<GlobSet, LPError> {
+    let invalid = |e: globset::Error| LPError::InvalidConfig {
+        path: path.to_path_buf(),
+        message: e.to_string(),
+    };
+    let mut builder = GlobSetBuilder::new();
+    for glob in globs {
+        builder.add(Glob::new(glob).map_err(invalid)?);
+    }
+    builder.build().map_err(invalid)
 }
 
 impl Config {
@@ -23,9 +54,138 @@ impl Config {
         Config {
             docs_dir: target_dir.join(DOCS_SUBDIR_NAME),
             code_dir: target_dir.join(CODE_SUBDIR_NAME),
+            cache_file: target_dir.join(BUILD_CACHE_FILE_NAME),
             code_plugins_dir: code_plugins_dir.to_path_buf(),
             source_dir: source_dir.to_path_buf(),
             force,
+            ignore: GlobSet::empty(),
+            plugins: PluginsSettings::default(),
+            docs: DocsSettings::default(),
+            project_file: PathBuf::from(PROJECT_FILE_NAME),
         }
     }
+
+    /// Merges the CLI params with the project configuration file.
+    ///
+    /// The file is taken from `--config`, otherwise `lp.yaml` in the current directory is used, if any.
+    /// The CLI flags take precedence over the file, and the file takes precedence over the defaults.
+    pub fn load(params: &Params) -> Result<Self, LPError> {
+        let (file, file_path) = match &params.config {
+            Some(path) => (ProjectFile::load(path)?, path.clone()),
+            None if Path::new(PROJECT_FILE_NAME).is_file() => (
+                ProjectFile::load(Path::new(PROJECT_FILE_NAME))?,
+                PathBuf::from(PROJECT_FILE_NAME),
+            ),
+            None => (ProjectFile::default(), PathBuf::from(PROJECT_FILE_NAME)),
+        };
+
+        let pick = |flag: &Option<PathBuf>, from_file: &Option<PathBuf>, default: &str| {
+            flag.clone()
+                .or_else(|| from_file.clone())
+                .unwrap_or_else(|| PathBuf::from(default))
+        };
+        let source_dir = pick(&params.src_dir, &file.source_dir, DEFAULT_SOURCE_DIR_NAME);
+        if !source_dir.is_dir() {
+            return Err(LPError::SourceDirectoryNotFound(
+                source_dir.to_string_lossy().to_string(),
+            ));
+        }
+
+        Ok(Config {
+            ignore: build_ignore(&file_path, &file.ignore)?,
+            plugins: file.plugins,
+            docs: file.docs,
+            project_file: file_path,
+            ..Config::new(
+                &pick(
+                    &params.target_dir,
+                    &file.target_dir,
+                    DEFAULT_TARGETS_DIR_NAME,
+                ),
+                &source_dir,
+                &pick(
+                    &params.plugins_dir,
+                    &file.plugins_dir,
+                    DEFAULT_PLUGINS_DIR_NAME,
+                ),
+                params.force,
+            )
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::tempdir;
+
+    fn params(config: &Path) -> Params {
+        Params {
+            src_dir: None,
+            target_dir: None,
+            plugins_dir: None,
+            config: Some(config.to_path_buf()),
+            force: false,
+            command: None,
+        }
+    }
+
+    #[test]
+    fn test_load_precedence() {
+        let dir = tempdir().unwrap();
+        fs::create_dir_all(dir.path().join("notebooks")).unwrap();
+        let config_path = dir.path().join("lp.yaml");
+        fs::write(
+            &config_path,
+            "source_dir: notebooks\ntarget_dir: out\nignore: ['drafts/**']\n",
+        )
+        .unwrap();
+
+        let config = Config::load(&params(&config_path)).unwrap();
+        assert_eq!(config.source_dir, dir.path().join("notebooks"));
+        assert_eq!(
+            config.code_dir,
+            dir.path().join("out").join(CODE_SUBDIR_NAME)
+        );
+        assert_eq!(
+            config.code_plugins_dir,
+            PathBuf::from(DEFAULT_PLUGINS_DIR_NAME)
+        );
+        assert!(config.ignore.is_match("drafts/a.py.lpnb"));
+
+        let config = Config::load(&Params {
+            target_dir: Some(PathBuf::from("cli_targets")),
+            ..params(&config_path)
+        })
+        .unwrap();
+        assert_eq!(
+            config.code_dir,
+            PathBuf::from("cli_targets").join(CODE_SUBDIR_NAME)
+        );
+    }
+
+    #[test]
+    fn test_load_missing_source_dir() {
+        let dir = tempdir().unwrap();
+        let config_path = dir.path().join("lp.yaml");
+        fs::write(&config_path, "source_dir: missing\n").unwrap();
+
+        assert!(matches!(
+            Config::load(&params(&config_path)),
+            Err(LPError::SourceDirectoryNotFound(_))
+        ));
+    }
+
+    #[test]
+    fn test_load_invalid_ignore() {
+        let dir = tempdir().unwrap();
+        let config_path = dir.path().join("lp.yaml");
+        fs::write(&config_path, "source_dir: .\nignore: ['a[']\n").unwrap();
+
+        assert!(matches!(
+            Config::load(&params(&config_path)),
+            Err(LPError::InvalidConfig { .. })
+        ));
+    }
 }
@@ -12,5 +12,30 @@ pub const DOCS_SUBDIR_NAME: &str = "docs";
 /// the name of the subdirectory of the target directory where the code will be generated
 pub const CODE_SUBDIR_NAME: &str = "code";
 
+/// the name of the project configuration file, looked up in the current directory
+pub const PROJECT_FILE_NAME: &str = "lp.yaml";
+
+/// the name of the page listing all the exported symbols of the project, in the docs directory,
+/// without the pages' extension
+pub const SYMBOL_INDEX_PAGE_NAME: &str = "symbols";
+
+/// the name of the entry page of the HTML documentation, without the pages' extension
+pub const DOCS_INDEX_PAGE_NAME: &str = "index";
+
+/// the name of the search page of the documentation, without the `html` extension
+pub const SEARCH_PAGE_NAME: &str = "search";
+
+/// the name of the search index of the documentation, in the docs directory
+pub const SEARCH_INDEX_FILE_NAME: &str = "search-index.json";
+
+/// the name of the stylesheet shared by the pages of the HTML documentation
+pub const DOCS_STYLE_FILE_NAME: &str = "lp-docs.css";
+
 /// the extension of this system files
 pub const SYSTEM_FILES_EXTENSION: &str = "lpnb";
+
+/// the extension of the source maps written next to the generated code files
+pub const SOURCE_MAP_EXTENSION: &str = "lpmap";
+
+/// the name of the build cache file in the target directory
+pub const BUILD_CACHE_FILE_NAME: &str = ".lpcache";
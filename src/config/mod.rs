@@ -1,4 +1,6 @@
 #![forbid(unsafe_code)]
 
+#[allow(clippy::module_inception)]
 pub mod config;
 pub mod constants;
+pub mod project_file;
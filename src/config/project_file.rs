@@ -0,0 +1,232 @@
+#![forbid(unsafe_code)]
+//! the project configuration file, `lp.yaml`
+
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+use serde::Deserialize;
+
+use crate::error::LPError;
+
+/// Settings of the code plugins
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PluginsSettings {
+    /// The plugin used for the files with the extension, e.g. `h: c`.
+    /// Files without an extension are matched by their names, e.g. `GNUmakefile: Makefile`.
+    /// By default, the plugin is named as the extension.
+    pub extensions: HashMap<String, String>,
+    /// What to do with the modules there is no plugin for
+    pub fallback: Fallback,
+    /// Whether the built-in plugins (`py`, `js` and `json`) are used,
+    /// the plugins with the same names in the plugins directory override them
+    pub builtin: bool,
+    /// Options passed to the plugin's functions, by the plugin's name
+    pub options: HashMap<String, serde_yaml::Value>,
+    pub sandbox: SandboxSettings,
+}
+
+/// What to do with the modules there is no plugin for
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Fallback {
+    /// fail with `LPError::PluginNotFound`
+    #[default]
+    Error,
+    /// build the code as is: the chunks are expanded, but no imports are added and nothing is cleaned
+    Raw,
+}
+
+/// Limits of the plugins' execution
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SandboxSettings {
+    /// Whether the plugins are sandboxed.
+    /// It may be disabled for trusted plugins which need the full standard library, e.g. `io` or `os`.
+    pub enabled: bool,
+    /// the maximum number of Lua instructions (or units of fuel for WebAssembly plugins) a single call of a plugin's function may execute
+    pub instruction_limit: u64,
+    /// the maximum memory (in bytes) a plugin's Lua state or WebAssembly memory may allocate
+    pub memory_limit: usize,
+}
+
+impl Default for SandboxSettings {
+    fn default() -> Self {
+        SandboxSettings {
+            enabled: true,
+            instruction_limit: 100_000_000,
+            memory_limit: 256 * 1024 * 1024,
+        }
+    }
+}
+
+/// Settings of the documentation
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DocsSettings {
+    /// whether the documentation is built at all
+    pub enabled: bool,
+    pub format: DocsFormat,
+}
+
+impl Default for DocsSettings {
+    fn default() -> Self {
+        DocsSettings {
+            enabled: true,
+            format: DocsFormat::default(),
+        }
+    }
+}
+
+/// The format the documentation pages are written in
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DocsFormat {
+    /// a Markdown mirror of each module
+    #[default]
+    Markdown,
+    /// a static HTML site with a sidebar of the source tree and a table of contents on each page
+    Html,
+}
+
+impl DocsFormat {
+    /// the extension of the pages
+    pub fn page_extension(&self) -> &'static str {
+        match self {
+            DocsFormat::Markdown => "md",
+            DocsFormat::Html => "html",
+        }
+    }
+}
+
+/// Contents of the project configuration file.
+///
+/// The directories are relative to the file itself, unknown keys are errors.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ProjectFile {
+    pub source_dir: Option<PathBuf>,
+    pub target_dir: Option<PathBuf>,
+    pub plugins_dir: Option<PathBuf>,
+    /// globs of the files and directories within the source directory which are not a part of the project
+    pub ignore: Vec<String>,
+    pub plugins: PluginsSettings,
+    pub docs: DocsSettings,
+}
+
+impl ProjectFile {
+    /// Reads and parses the configuration file, the directories in it are resolved relative to the file
+    pub fn load(path: &Path) -> Result<Self, LPError> {
+        let content = std::fs::read_to_string(path)
+            .map_err(|_| LPError::CannotReadFile(path.display().to_string()))?;
+        let mut file = Self::parse(&content).map_err(|message| LPError::InvalidConfig {
+            path: path.to_path_buf(),
+            message,
+        })?;
+
+        let root = path.parent().unwrap_or(Path::new(""));
+        for dir in [
+            &mut file.source_dir,
+            &mut file.target_dir,
+            &mut file.plugins_dir,
+        ]
+        .into_iter()
+        .flatten()
+        {
+            *dir = root.join(&dir);
+        }
+        Ok(file)
+    }
+
+    fn parse(content: &str) -> Result<Self, String> {
+        if content.trim().is_empty() {
+            return Ok(Self::default());
+        }
+        serde_yaml::from_str(content).map_err(|e| e.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_load() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("lp.yaml");
+        fs::write(
+            &path,
+            r#"
+source_dir: notebooks
+plugins_dir: ../shared/plugins
+ignore:
+  - "drafts"
+  - "**/*.bak"
+plugins:
+  extensions:
+    h: c
+  fallback: raw
+  builtin: true
+  options:
+    py:
+      line_length: 80
+  sandbox:
+    enabled: false
+docs:
+  enabled: false
+  format: html
+"#,
+        )
+        .unwrap();
+
+        let file = ProjectFile::load(&path).unwrap();
+        assert_eq!(file.source_dir, Some(dir.path().join("notebooks")));
+        assert_eq!(file.target_dir, None);
+        assert_eq!(file.plugins_dir, Some(dir.path().join("../shared/plugins")));
+        assert_eq!(file.ignore, vec!["drafts", "**/*.bak"]);
+        assert_eq!(file.plugins.extensions["h"], "c");
+        assert!(!file.plugins.extensions.contains_key("py"));
+        assert_eq!(file.plugins.fallback, Fallback::Raw);
+        assert!(file.plugins.builtin);
+        assert!(file.plugins.options.contains_key("py"));
+        assert!(!file.plugins.sandbox.enabled);
+        assert_eq!(
+            file.plugins.sandbox.instruction_limit,
+            SandboxSettings::default().instruction_limit
+        );
+        assert!(!file.docs.enabled);
+        assert_eq!(file.docs.format, DocsFormat::Html);
+    }
+
+    #[test]
+    fn test_load_empty() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("lp.yaml");
+        fs::write(&path, "\n").unwrap();
+
+        let file = ProjectFile::load(&path).unwrap();
+        assert!(file.source_dir.is_none());
+        assert!(file.docs.enabled);
+        assert_eq!(file.docs.format, DocsFormat::Markdown);
+        assert_eq!(file.plugins.fallback, Fallback::Error);
+        assert!(!file.plugins.builtin);
+    }
+
+    #[test]
+    fn test_load_unknown_key() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("lp.yaml");
+        fs::write(&path, "source_dir: src\ndocs:\n  enable: false\n").unwrap();
+
+        match ProjectFile::load(&path) {
+            Err(LPError::InvalidConfig { message, .. }) => {
+                assert!(message.contains("unknown field `enable`"), "{}", message)
+            }
+            _ => panic!("Expected InvalidConfig error"),
+        }
+    }
+}
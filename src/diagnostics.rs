@@ -0,0 +1,175 @@
+#![forbid(unsafe_code)]
+//! machine-readable representation of the errors
+
+use std::path::PathBuf;
+
+use serde::Serialize;
+
+use crate::error::LPError;
+
+/// A single problem found in the project, ready to be serialized.
+#[derive(Debug, Serialize, PartialEq)]
+pub struct Diagnostic {
+    /// short stable identifier of the problem's kind, e.g. `incorrect_reference`
+    pub kind: &'static str,
+    /// human-readable description of the problem
+    pub message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub module: Option<PathBuf>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub line: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub column: Option<usize>,
+    /// index of the section within the module
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub section: Option<usize>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub suggestions: Vec<String>,
+}
+
+impl Diagnostic {
+    fn new(kind: &'static str, message: String) -> Self {
+        Diagnostic {
+            kind,
+            message,
+            module: None,
+            line: None,
+            column: None,
+            section: None,
+            suggestions: vec![],
+        }
+    }
+
+    /// Converts an error into diagnostics.
+    /// `LPError::Multiple` is flattened, so each of the collected errors becomes a separate diagnostic.
+    pub fn from_error(error: &LPError) -> Vec<Diagnostic> {
+        let diagnostic = match error {
+            LPError::Multiple(errors) => {
+                return errors.iter().flat_map(Diagnostic::from_error).collect();
+            }
+            LPError::Io(e) => Diagnostic::new("io", e.to_string()),
+            LPError::SourceDirectoryNotFound(_) => {
+                Diagnostic::new("source_directory_not_found", error.to_string())
+            }
+            LPError::DuplicateHeader(_) => Diagnostic::new("duplicate_header", error.to_string()),
+            LPError::CannotReadFile(_) => Diagnostic::new("cannot_read_file", error.to_string()),
+            LPError::PluginNotFound(_) => Diagnostic::new("plugin_not_found", error.to_string()),
+            LPError::LuaRuntime(_) => Diagnostic::new("lua_runtime", error.to_string()),
+            LPError::PluginFailed(failure) => Diagnostic {
+                module: failure.module.clone(),
+                section: failure.section,
+                ..Diagnostic::new("plugin_failed", error.to_string())
+            },
+            LPError::WasmRuntime(_) => Diagnostic::new("wasm_runtime", error.to_string()),
+            LPError::InvalidLocation(_) => Diagnostic::new("invalid_location", error.to_string()),
+            LPError::InvalidConfig { .. } => Diagnostic::new("invalid_config", error.to_string()),
+            LPError::InvalidPlugin { .. } => Diagnostic::new("invalid_plugin", error.to_string()),
+            LPError::PluginWarning { .. } => Diagnostic::new("plugin_warning", error.to_string()),
+            LPError::ChunkCycle(module, _) => Diagnostic {
+                module: Some(module.clone()),
+                ..Diagnostic::new("chunk_cycle", error.to_string())
+            },
+            LPError::IncorrectReference(broken) => Diagnostic {
+                module: Some(broken.module.clone()),
+                section: Some(broken.section),
+                suggestions: broken.suggestions.clone(),
+                ..Diagnostic::new("incorrect_reference", error.to_string())
+            },
+            LPError::MalformedModule {
+                path,
+                line,
+                column,
+                message,
+                ..
+            } => Diagnostic {
+                module: Some(path.clone()),
+                line: Some(*line),
+                column: Some(*column),
+                ..Diagnostic::new("malformed_module", message.clone())
+            },
+        };
+        vec![diagnostic]
+    }
+
+    /// Serializes the diagnostic as a single line of JSON.
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::{BrokenReference, PluginFailure};
+
+    #[test]
+    fn test_from_error_flattens_multiple() {
+        let error = LPError::Multiple(vec![
+            LPError::PluginNotFound("py".to_string()),
+            LPError::DuplicateHeader("Main".to_string()),
+        ]);
+
+        let diagnostics = Diagnostic::from_error(&error);
+        assert_eq!(diagnostics.len(), 2);
+        assert_eq!(diagnostics[0].kind, "plugin_not_found");
+        assert_eq!(diagnostics[1].kind, "duplicate_header");
+    }
+
+    #[test]
+    fn test_incorrect_reference_to_json() {
+        let error = LPError::IncorrectReference(Box::new(BrokenReference {
+            module: PathBuf::from("main.py.lpnb"),
+            section: 2,
+            text: "App".to_string(),
+            target: PathBuf::from("app/app"),
+            header: "Ap".to_string(),
+            suggestions: vec!["App".to_string()],
+        }));
+
+        let json: serde_json::Value =
+            serde_json::from_str(&Diagnostic::from_error(&error)[0].to_json()).unwrap();
+        assert_eq!(json["kind"], "incorrect_reference");
+        assert_eq!(json["module"], "main.py.lpnb");
+        assert_eq!(json["section"], 2);
+        assert_eq!(json["suggestions"][0], "App");
+        assert!(json.get("line").is_none());
+    }
+
+    #[test]
+    fn test_malformed_module_to_json() {
+        let error = LPError::MalformedModule {
+            path: PathBuf::from("bad.py.lpnb"),
+            line: 3,
+            column: 5,
+            message: "unexpected end".to_string(),
+            snippet: "".to_string(),
+        };
+
+        let diagnostics = Diagnostic::from_error(&error);
+        assert_eq!(diagnostics[0].line, Some(3));
+        assert_eq!(diagnostics[0].column, Some(5));
+        assert_eq!(diagnostics[0].message, "unexpected end");
+    }
+
+    #[test]
+    fn test_plugin_failed() {
+        let error = LPError::PluginFailed(Box::new(PluginFailure {
+            plugin: "py".to_string(),
+            hook: "get_import_code".to_string(),
+            message: "boom".to_string(),
+            traceback: Some("stack traceback:\n\t[C]: in ?".to_string()),
+            ..PluginFailure::default()
+        }))
+        .in_section(&PathBuf::from("main.py.lpnb"), Some(1), Some("app"))
+        .with_reference(&PathBuf::from("app/app.py.lpnb"), "app-class");
+
+        assert_eq!(
+            error.to_string(),
+            "Plugin py failed in get_import_code: boom\n  module: main.py.lpnb\n  section: #1 app\n  referenced module: app/app.py.lpnb, section: app-class\nstack traceback:\n\t[C]: in ?"
+        );
+        let diagnostics = Diagnostic::from_error(&error);
+        assert_eq!(diagnostics[0].kind, "plugin_failed");
+        assert_eq!(diagnostics[0].module, Some(PathBuf::from("main.py.lpnb")));
+        assert_eq!(diagnostics[0].section, Some(1));
+    }
+}
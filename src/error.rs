@@ -1,6 +1,6 @@
 #![forbid(unsafe_code)]
 
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 use thiserror::Error;
 
@@ -28,10 +28,177 @@ pub enum LPError {
     PluginNotFound(String),
 
     /// Error when an incorrect reference is found
-    #[error("Impossible to refer to the module: {0}, section: {1}")]
-    IncorrectReference(PathBuf, String),
+    #[error("{0}")]
+    IncorrectReference(Box<BrokenReference>),
 
     /// Lua runtime errors.
     #[error("Lua error: {0}")]
     LuaRuntime(String),
+
+    /// Error raised by a plugin's function, with the context of the call
+    #[error("{0}")]
+    PluginFailed(Box<PluginFailure>),
+
+    /// WebAssembly plugins runtime errors.
+    #[error("Wasm error: {0}")]
+    WasmRuntime(String),
+
+    /// Error when a literate file is not a valid YAML module
+    #[error("Malformed module {path}:{line}:{column}: {message}\n{snippet}")]
+    MalformedModule {
+        path: PathBuf,
+        line: usize,
+        column: usize,
+        message: String,
+        snippet: String,
+    },
+
+    /// Error when chunks of a module include each other
+    #[error("Chunks cycle in the module {0}: {1}")]
+    ChunkCycle(PathBuf, String),
+
+    /// Error when a location in the generated code can't be found, it should look like `<file>:<line>`
+    #[error("Cannot locate: {0}")]
+    InvalidLocation(String),
+
+    /// Error when the project configuration file is invalid, e.g. contains an unknown key
+    #[error("Invalid configuration file {}: {message}", .path.display())]
+    InvalidConfig { path: PathBuf, message: String },
+
+    /// Error when a plugin's manifest is invalid or the plugin is incompatible with this system
+    #[error("Invalid plugin {plugin}: {message}")]
+    InvalidPlugin { plugin: String, message: String },
+
+    /// A warning logged by a plugin with `lp.warn`, reported as a problem by the `check` subcommand
+    #[error("Plugin {plugin} warning: {message}")]
+    PluginWarning { plugin: String, message: String },
+
+    /// Several errors collected during a single pass
+    #[error("{}", .0.iter().map(|e| e.to_string()).collect::<Vec<_>>().join("\n"))]
+    Multiple(Vec<LPError>),
+}
+
+/// A reference to a section which does not exist.
+#[derive(Debug)]
+pub struct BrokenReference {
+    /// the module containing the reference
+    pub module: PathBuf,
+    /// index of the section containing the reference within its module
+    pub section: usize,
+    /// text of the link
+    pub text: String,
+    /// resolved path of the referenced module
+    pub target: PathBuf,
+    /// referenced header, as written in the link
+    pub header: String,
+    /// existing headers of the referenced module which are close to the referenced one
+    pub suggestions: Vec<String>,
+}
+
+impl std::fmt::Display for BrokenReference {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Impossible to refer to the module: {}, section: {} (from {}, section #{}, link [{}])",
+            self.target.display(),
+            self.header,
+            self.module.display(),
+            self.section,
+            self.text
+        )?;
+        if !self.suggestions.is_empty() {
+            write!(f, ", did you mean: {}?", self.suggestions.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+/// A failed call of a plugin's function.
+///
+/// The plugin fills in what failed, the builder adds what it was building.
+#[derive(Debug, Default)]
+pub struct PluginFailure {
+    pub plugin: String,
+    /// the plugin's function, e.g. `get_import_code`
+    pub hook: String,
+    pub message: String,
+    /// Lua stack traceback, if any
+    pub traceback: Option<String>,
+    /// the module being built
+    pub module: Option<PathBuf>,
+    /// index of the section being processed within its module
+    pub section: Option<usize>,
+    /// header of the section being processed
+    pub header: Option<String>,
+    /// the module referenced by the section, for `get_import_code`
+    pub referenced_module: Option<PathBuf>,
+    /// the header referenced by the section, for `get_import_code`
+    pub referenced_header: Option<String>,
+}
+
+impl std::fmt::Display for PluginFailure {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Plugin {} failed in {}: {}",
+            self.plugin, self.hook, self.message
+        )?;
+        if let Some(module) = &self.module {
+            write!(f, "\n  module: {}", module.display())?;
+        }
+        match (self.section, &self.header) {
+            (Some(section), Some(header)) => write!(f, "\n  section: #{} {}", section, header)?,
+            (Some(section), None) => write!(f, "\n  section: #{}", section)?,
+            (None, Some(header)) => write!(f, "\n  section: {}", header)?,
+            (None, None) => {}
+        }
+        if let Some(referenced_module) = &self.referenced_module {
+            write!(f, "\n  referenced module: {}", referenced_module.display())?;
+            if let Some(referenced_header) = &self.referenced_header {
+                write!(f, ", section: {}", referenced_header)?;
+            }
+        }
+        if let Some(traceback) = &self.traceback {
+            write!(f, "\n{}", traceback)?;
+        }
+        Ok(())
+    }
+}
+
+impl LPError {
+    /// Adds the module and the section being built to a plugin's failure, other errors are returned as is
+    pub fn in_section(self, module: &Path, section: Option<usize>, header: Option<&str>) -> Self {
+        match self {
+            LPError::PluginFailed(mut failure) => {
+                failure.module = Some(module.to_path_buf());
+                failure.section = section;
+                failure.header = header.map(String::from);
+                LPError::PluginFailed(failure)
+            }
+            error => error,
+        }
+    }
+
+    /// Adds the referenced module and header to a plugin's failure, other errors are returned as is
+    pub fn with_reference(self, module: &Path, header: &str) -> Self {
+        match self {
+            LPError::PluginFailed(mut failure) => {
+                failure.referenced_module = Some(module.to_path_buf());
+                failure.referenced_header = Some(header.to_string());
+                LPError::PluginFailed(failure)
+            }
+            error => error,
+        }
+    }
+
+    /// Turns a list of collected errors into a single result.
+    ///
+    /// No errors is `Ok`, a single error is returned as is, several errors are wrapped into `LPError::Multiple`.
+    pub fn from_many(mut errors: Vec<LPError>) -> Result<(), LPError> {
+        match errors.len() {
+            0 => Ok(()),
+            1 => Err(errors.remove(0)),
+            _ => Err(LPError::Multiple(errors)),
+        }
+    }
 }
@@ -3,13 +3,35 @@
 mod builds;
 mod cli;
 mod config;
+mod diagnostics;
 mod error;
 
-use builds::builder::Builder;
-use cli::args_processor::ParamsProcessor;
+use builds::{builder::Builder, checker::Checker, code::source_map, watcher::Watcher};
+use cli::{args_processor::ParamsProcessor, structs::Command};
 use config::config::Config;
+use diagnostics::Diagnostic;
 use error::LPError;
-use std::{process, rc::Rc};
+use std::process;
+
+/// Runs the `check` subcommand, exits with a non-zero code if any problem is found.
+fn check(config: Config, run_plugins: bool) {
+    let diagnostics: Vec<Diagnostic> = Checker::new(config, run_plugins)
+        .check()
+        .iter()
+        .flat_map(Diagnostic::from_error)
+        .collect();
+
+    for diagnostic in &diagnostics {
+        println!("{}", diagnostic.to_json());
+    }
+
+    if diagnostics.is_empty() {
+        eprintln!("No problems found");
+    } else {
+        eprintln!("Found {} problem(s)", diagnostics.len());
+        process::exit(1);
+    }
+}
 
 fn main() -> Result<(), LPError> {
     let params_parser = ParamsProcessor::new();
@@ -21,16 +43,40 @@ fn main() -> Result<(), LPError> {
     }
 
     let params = params.unwrap();
-    let config = Config::new(
-        &params.target_dir,
-        &params.src_dir,
-        &params.plugins_dir,
-        params.force,
-    );
 
-    let lua = Rc::new(mlua::Lua::new());
+    if let Some(Command::Locate { location }) = &params.command {
+        match source_map::locate(location) {
+            Ok(result) => println!("{}", result),
+            Err(e) => {
+                eprintln!("{}", e);
+                process::exit(1);
+            }
+        }
+        return Ok(());
+    }
+    let config = match Config::load(&params) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(1);
+        }
+    };
+
+    if let Some(Command::Check { run_plugins }) = params.command {
+        check(config, run_plugins);
+        return Ok(());
+    }
+
+    if let Some(Command::Watch) = params.command {
+        Watcher::new(params, config).run();
+    }
+
+    println!("Bulding code to: {:?}", config.code_dir);
+    if config.docs.enabled {
+        println!("Bulding documentation to: {:?}", config.docs_dir);
+    }
 
-    let builder = match Builder::new(config, Rc::clone(&lua)) {
+    let builder = match Builder::new(config) {
         Ok(builder) => builder,
         Err(e) => {
             eprintln!("{}", e);
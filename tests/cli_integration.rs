@@ -100,14 +100,13 @@ fn run_lp_command(
         cmd.arg("-f");
     }
 
-    let test_files: Vec<PathBuf>;
     let test_file_content = "This file should not be affected without force flag";
 
-    if test_with_messed_dirs {
-        test_files = create_test_files(&target_dir);
+    let test_files: Vec<PathBuf> = if test_with_messed_dirs {
+        create_test_files(&target_dir)
     } else {
-        test_files = Vec::new();
-    }
+        Vec::new()
+    };
 
     let output = cmd.output().expect("Failed to execute cargo run");
 
@@ -221,3 +220,394 @@ fn test_python_and_node_js_project_without_plugins() {
         "Python and Node.js project without plugins has not failed, but it should have"
     );
 }
+
+fn run_lp_check(project_dir: &Path, tmp_root: &TempDir) -> std::process::Output {
+    let plugins_dir = tmp_root.path().join("plugins");
+    copy_dir_all("examples/plugins", &plugins_dir).unwrap();
+
+    Command::new("cargo")
+        .arg("run")
+        .arg("--")
+        .arg("check")
+        .arg("--run-plugins")
+        .arg("--src-dir")
+        .arg(project_dir)
+        .arg("--plugins-dir")
+        .arg(&plugins_dir)
+        .arg("--target-dir")
+        .arg(tmp_root.path().join("targets"))
+        .output()
+        .expect("Failed to execute cargo run")
+}
+
+#[test]
+fn test_check_python_project() {
+    let tmpdir = tempdir().unwrap();
+    let output = run_lp_check(
+        &Path::new("examples").join("projects").join("python"),
+        &tmpdir,
+    );
+
+    assert!(output.status.success(), "Check of a valid project failed");
+    assert!(output.stdout.is_empty());
+    assert!(
+        !tmpdir.path().join("targets").exists(),
+        "Check should not write anything"
+    );
+}
+
+#[test]
+fn test_check_broken_project() {
+    let tmpdir = tempdir().unwrap();
+    let project_dir = tmpdir.path().join("projects").join("python");
+    copy_dir_all(
+        Path::new("examples").join("projects").join("python"),
+        &project_dir,
+    )
+    .unwrap();
+    let main_module = project_dir.join("main.py.lpnb");
+    let content = fs::read_to_string(&main_module).unwrap();
+    fs::write(
+        &main_module,
+        content.replace("#Класс-приложения", "#Класс-приложени"),
+    )
+    .unwrap();
+    fs::write(project_dir.join("broken.py.lpnb"), "sections: [\n").unwrap();
+
+    let output = run_lp_check(&project_dir, &tmpdir);
+
+    assert!(!output.status.success(), "Check of a broken project passed");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let lines: Vec<&str> = stdout.lines().collect();
+    assert_eq!(lines.len(), 2, "Unexpected diagnostics: {}", stdout);
+    assert!(lines[0].contains("\"kind\":\"malformed_module\""));
+    assert!(lines[1].contains("\"kind\":\"incorrect_reference\""));
+    assert!(lines[1].contains("section: Класс-приложени "));
+}
+
+fn run_lp_build(project_dir: &Path, tmp_root: &TempDir) -> std::process::Output {
+    Command::new("cargo")
+        .arg("run")
+        .arg("--")
+        .arg("--src-dir")
+        .arg(project_dir)
+        .arg("--plugins-dir")
+        .arg(tmp_root.path().join("plugins"))
+        .arg("--target-dir")
+        .arg(tmp_root.path().join("targets"))
+        .output()
+        .expect("Failed to execute cargo run")
+}
+
+#[test]
+fn test_incremental_build() {
+    let tmpdir = tempdir().unwrap();
+    let project_dir = tmpdir.path().join("projects").join("python");
+    copy_dir_all(
+        Path::new("examples").join("projects").join("python"),
+        &project_dir,
+    )
+    .unwrap();
+    copy_dir_all("examples/plugins", tmpdir.path().join("plugins")).unwrap();
+
+    assert!(run_lp_build(&project_dir, &tmpdir).status.success());
+    assert!(tmpdir.path().join("targets").join(".lpcache").exists());
+
+    let code_dir = tmpdir.path().join("targets").join("code");
+    let modified = |path: &str| {
+        fs::metadata(code_dir.join(path))
+            .unwrap()
+            .modified()
+            .unwrap()
+    };
+    let main_modified = modified("main.py");
+    let app_modified = modified("app/app.py");
+    let primes_modified = modified("lib/primes.py");
+
+    let primes_module = project_dir.join("lib").join("primes.py.lpnb");
+    let content = fs::read_to_string(&primes_module).unwrap();
+    fs::write(
+        &primes_module,
+        content.replace("def is_prime", "def is_prime_number"),
+    )
+    .unwrap();
+
+    assert!(run_lp_build(&project_dir, &tmpdir).status.success());
+
+    assert_eq!(
+        modified("main.py"),
+        main_modified,
+        "Unchanged module was rewritten"
+    );
+    assert_ne!(modified("lib/primes.py"), primes_modified);
+    assert_ne!(
+        modified("app/app.py"),
+        app_modified,
+        "Module referencing the changed section was not rebuilt"
+    );
+    assert!(fs::read_to_string(code_dir.join("app").join("app.py"))
+        .unwrap()
+        .contains("is_prime_number"));
+    assert!(code_dir.join("app").join("__init__.py").exists());
+    assert!(code_dir.join("lib").join("__init__.py").exists());
+
+    let primes_docs = tmpdir
+        .path()
+        .join("targets")
+        .join("docs")
+        .join("lib")
+        .join("primes.md");
+    let primes_docs = fs::read_to_string(primes_docs).unwrap();
+    assert!(primes_docs.contains("Exports: `is_prime_number`"));
+    assert!(primes_docs.contains(
+        "Referenced by: [app/app.py: Поиск ближайшего простого](../app/app.md#Поиск-ближайшего-простого)"
+    ));
+    assert!(fs::read_to_string(
+        tmpdir
+            .path()
+            .join("targets")
+            .join("docs")
+            .join("symbols.md")
+    )
+    .unwrap()
+    .contains("[`is_prime_number`](lib/primes.md#"));
+    let search_index = fs::read_to_string(
+        tmpdir
+            .path()
+            .join("targets")
+            .join("docs")
+            .join("search-index.json"),
+    )
+    .unwrap();
+    assert!(search_index.contains("\"page\":\"lib/primes.md\""));
+    assert!(search_index.contains("is_prime_number"));
+}
+
+#[test]
+fn test_builtin_plugins() {
+    let tmpdir = tempdir().unwrap();
+    let project_dir = Path::new("examples")
+        .join("projects")
+        .join("python_and_node_js");
+    let build = |name: &str, config: Option<&Path>| {
+        let plugins_dir = tmpdir.path().join(name).join("plugins");
+        if config.is_none() {
+            copy_dir_all("examples/plugins", &plugins_dir).unwrap();
+        }
+        let target_dir = tmpdir.path().join(name).join("targets");
+        let mut cmd = Command::new("cargo");
+        cmd.arg("run")
+            .arg("--")
+            .arg("--src-dir")
+            .arg(&project_dir)
+            .arg("--plugins-dir")
+            .arg(&plugins_dir)
+            .arg("--target-dir")
+            .arg(&target_dir);
+        if let Some(config) = config {
+            cmd.arg("--config").arg(config);
+        }
+        let output = cmd.output().expect("Failed to execute cargo run");
+        assert!(
+            output.status.success(),
+            "{}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+        target_dir.join("code")
+    };
+
+    let config = tmpdir.path().join("lp.yaml");
+    fs::write(&config, "plugins:\n  builtin: true\n").unwrap();
+    let builtin_code = build("builtin", Some(&config));
+    let lua_code = build("lua", None);
+
+    for path in [
+        "app.js",
+        "python_call.js",
+        "package.json",
+        "python_app/primes.py",
+        "python_app/__init__.py",
+    ] {
+        assert_eq!(
+            fs::read_to_string(builtin_code.join(path)).unwrap(),
+            fs::read_to_string(lua_code.join(path)).unwrap(),
+            "{} differs",
+            path
+        );
+    }
+}
+
+#[test]
+fn test_wasm_plugin() {
+    let tmpdir = tempdir().unwrap();
+    let project_dir = Path::new("examples").join("projects").join("python");
+    let plugins_dir = tmpdir.path().join("plugins");
+    copy_dir_all("examples/plugins", &plugins_dir).unwrap();
+    fs::remove_file(plugins_dir.join("py.lua")).unwrap();
+    fs::write(
+        plugins_dir.join("py.wasm"),
+        wat::parse_file("tests/fixtures/plugins/comment.wat").unwrap(),
+    )
+    .unwrap();
+
+    let output = run_lp_build(&project_dir, &tmpdir);
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert!(fs::read_to_string(
+        tmpdir
+            .path()
+            .join("targets")
+            .join("code")
+            .join("app")
+            .join("app.py")
+    )
+    .unwrap()
+    .contains("# import lib/primes.py"));
+}
+
+#[test]
+fn test_wasm_plugin_change_rebuilds() {
+    let tmpdir = tempdir().unwrap();
+    let project_dir = Path::new("examples").join("projects").join("python");
+    let plugins_dir = tmpdir.path().join("plugins");
+    copy_dir_all("examples/plugins", &plugins_dir).unwrap();
+    fs::remove_file(plugins_dir.join("py.lua")).unwrap();
+    let plugin = fs::read_to_string("tests/fixtures/plugins/comment.wat").unwrap();
+    let app = tmpdir
+        .path()
+        .join("targets")
+        .join("code")
+        .join("app")
+        .join("app.py");
+
+    fs::write(
+        plugins_dir.join("py.wasm"),
+        wat::parse_str(&plugin).unwrap(),
+    )
+    .unwrap();
+    assert!(run_lp_build(&project_dir, &tmpdir).status.success());
+    assert!(fs::read_to_string(&app)
+        .unwrap()
+        .contains("# import lib/primes.py"));
+
+    fs::write(
+        plugins_dir.join("py.wasm"),
+        wat::parse_str(plugin.replace("\"# import \"", "\"#:import \"")).unwrap(),
+    )
+    .unwrap();
+    let output = run_lp_build(&project_dir, &tmpdir);
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let code = fs::read_to_string(&app).unwrap();
+    assert!(code.contains("#:import lib/primes.py"), "{}", code);
+}
+
+#[test]
+fn test_plugin_error_context() {
+    let tmpdir = tempdir().unwrap();
+    let project_dir = Path::new("examples").join("projects").join("python");
+    let plugins_dir = tmpdir.path().join("plugins");
+    copy_dir_all("examples/plugins", &plugins_dir).unwrap();
+    let plugin = fs::read_to_string(plugins_dir.join("py.lua")).unwrap();
+    fs::write(
+        plugins_dir.join("py.lua"),
+        plugin.replace(
+            "function get_import_code(current_path, referenced_path_str, code_block)",
+            "function get_import_code(current_path, referenced_path_str, code_block)\n    error(\"cannot import \" .. referenced_path_str)",
+        ),
+    )
+    .unwrap();
+
+    let output = run_lp_build(&project_dir, &tmpdir);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("Plugin py failed in get_import_code:"),
+        "{}",
+        stderr
+    );
+    assert!(stderr.contains("\n  module: "), "{}", stderr);
+    assert!(stderr.contains("\n  referenced module: "), "{}", stderr);
+    assert!(stderr.contains("\nstack traceback:"), "{}", stderr);
+}
+
+#[test]
+fn test_html_docs() {
+    let tmpdir = tempdir().unwrap();
+    copy_dir_all("examples/plugins", tmpdir.path().join("plugins")).unwrap();
+    let config = tmpdir.path().join("lp.yaml");
+    fs::write(&config, "docs:\n  format: html\n").unwrap();
+    let output = Command::new("cargo")
+        .arg("run")
+        .arg("--")
+        .arg("--src-dir")
+        .arg(Path::new("examples").join("projects").join("python"))
+        .arg("--plugins-dir")
+        .arg(tmpdir.path().join("plugins"))
+        .arg("--target-dir")
+        .arg(tmpdir.path().join("targets"))
+        .arg("--config")
+        .arg(&config)
+        .output()
+        .expect("Failed to execute cargo run");
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    let docs_dir = tmpdir.path().join("targets").join("docs");
+    for path in [
+        "index.html",
+        "lp-docs.css",
+        "main.html",
+        "app/app.html",
+        "lib/primes.html",
+        "static/input_spec.txt",
+    ] {
+        assert!(docs_dir.join(path).exists(), "{} is missing", path);
+    }
+    assert!(!docs_dir.join("main.md").exists());
+
+    let app = fs::read_to_string(docs_dir.join("app").join("app.html")).unwrap();
+    assert!(app.contains("<link rel=\"stylesheet\" href=\"../lp-docs.css\">"));
+    assert!(app.contains("<a href=\"app.html\" class=\"current\">app.py</a>"));
+    assert!(app.contains("<a href=\"../main.html\">main.py</a>"));
+    assert!(app.contains("href=\"../static/input_spec.txt\""));
+    assert!(app.contains("<nav class=\"toc\">"));
+    assert!(!app.contains("http://") && !app.contains("https://"));
+
+    let main = fs::read_to_string(docs_dir.join("main.html")).unwrap();
+    let anchor = "%D0%9A%D0%BB%D0%B0%D1%81%D1%81-%D0%BF%D1%80%D0%B8%D0%BB%D0%BE%D0%B6%D0%B5%D0%BD%D0%B8%D1%8F";
+    assert!(main.contains(&format!("href=\"app/app.html#{}\"", anchor)));
+    assert!(app.contains("id=\"Класс-приложения\""));
+    assert!(app.contains("<a class=\"search\" href=\"../search.html\">Search</a>"));
+
+    let index: serde_json::Value =
+        serde_json::from_str(&fs::read_to_string(docs_dir.join("search-index.json")).unwrap())
+            .unwrap();
+    let primes = index
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|entry| entry["module"] == "lib/primes.py")
+        .unwrap();
+    assert_eq!(primes["page"], "lib/primes.html");
+    assert_eq!(primes["anchor"], "Проверка-на-простоту");
+    assert!(primes["identifiers"]
+        .as_array()
+        .unwrap()
+        .contains(&serde_json::json!("is_prime")));
+    let search = fs::read_to_string(docs_dir.join("search.html")).unwrap();
+    assert!(search.contains("<script type=\"application/json\" id=\"search-index\">"));
+    assert!(search.contains("<nav class=\"sidebar\">"));
+
+    let primes = fs::read_to_string(docs_dir.join("lib").join("primes.html")).unwrap();
+    assert!(primes.contains("<p>Referenced by: <a href=\"../app/app.html#"));
+}
@@ -0,0 +1,60 @@
+;; A test plugin for the WebAssembly plugins ABI, see src/builds/code/plugins/wasm_plugin.rs.
+;; It imports a module with a comment, keeps the code as is and treats every line of a section as an exported name.
+(module
+  (memory (export "memory") 1)
+  (data (i32.const 0) "# import ")
+  (global $heap_start i32 (i32.const 1024))
+  (global $heap (mut i32) (i32.const 1024))
+
+  ;; frees everything allocated since the previous call
+  (func (export "lp_reset")
+    (global.set $heap (global.get $heap_start)))
+
+  ;; a bump allocator growing the memory when needed
+  (func $alloc (export "lp_alloc") (param $len i32) (result i32)
+    (local $ptr i32)
+    (local $end i32)
+    (local.set $ptr (global.get $heap))
+    (local.set $end (i32.add (local.get $ptr) (local.get $len)))
+    (if (i32.gt_u (local.get $end) (i32.shl (memory.size) (i32.const 16)))
+      (then
+        (if (i32.eq
+              (memory.grow
+                (i32.sub
+                  (i32.shr_u (i32.add (local.get $end) (i32.const 65535)) (i32.const 16))
+                  (memory.size)))
+              (i32.const -1))
+          (then unreachable))))
+    (global.set $heap (local.get $end))
+    (local.get $ptr))
+
+  ;; packs a string into the returned value
+  (func $string (param $ptr i32) (param $len i32) (result i64)
+    (i64.or
+      (i64.shl (i64.extend_i32_u (local.get $ptr)) (i64.const 32))
+      (i64.extend_i32_u (local.get $len))))
+
+  (func (export "get_import_code")
+    (param $current_ptr i32) (param $current_len i32)
+    (param $referenced_ptr i32) (param $referenced_len i32)
+    (param $code_ptr i32) (param $code_len i32)
+    (result i64)
+    (local $out i32)
+    (local $len i32)
+    (local.set $len (i32.add (i32.const 9) (local.get $referenced_len)))
+    (local.set $out (call $alloc (local.get $len)))
+    (memory.copy (local.get $out) (i32.const 0) (i32.const 9))
+    (memory.copy
+      (i32.add (local.get $out) (i32.const 9))
+      (local.get $referenced_ptr)
+      (local.get $referenced_len))
+    (call $string (local.get $out) (local.get $len)))
+
+  (func (export "clean_code") (param $code_ptr i32) (param $code_len i32) (result i64)
+    (call $string (local.get $code_ptr) (local.get $code_len)))
+
+  (func (export "get_exports")
+    (param $module_ptr i32) (param $module_len i32)
+    (param $code_ptr i32) (param $code_len i32)
+    (result i64)
+    (call $string (local.get $code_ptr) (local.get $code_len))))